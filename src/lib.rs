@@ -2,9 +2,13 @@
 //! 高性能的松散叉树
 //！采用二进制掩码 表达xyz的大小， child&1 == 0 表示x为小，否则为大。
 //！采用Slab，内部用偏移量来分配八叉节点。这样内存连续，八叉树本身可以快速拷贝。
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+
+extern crate alloc;
 
 pub mod oct_helper;
 pub mod quad_helper;
 pub mod tree;
 pub mod tilemap;
+#[cfg(feature = "std")]
 pub mod web;
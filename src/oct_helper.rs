@@ -1,275 +1,833 @@
 //! 八叉相关接口
 
-use std::mem;
+use ::core::cmp::Ordering;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use ::core::marker::PhantomData;
+use ::core::mem;
 
 use nalgebra::*;
 use parry3d::{bounding_volume::*, math::Real};
+use parry2d::bounding_volume::Aabb as Aabb2;
+use parry3d_f64::bounding_volume::{Aabb as Aabb64, BoundingVolume as _};
 use num_traits::{One, Zero, FromPrimitive, AsPrimitive};
+use pi_slotmap::Key;
 
-use crate::tree::{Helper, Tree};
+use crate::quad_helper::QuadTree;
+use crate::tree::{Helper, Tree, DEEP_MAX};
 
 
 /// 八叉树
-pub type OctTree<K, T> = Tree<K, OctHelper, T, 8>;
+pub type OctTree<K, T> = Tree<K, OctHelper<f32>, T, 8>;
+/// 双精度的八叉树
+pub type OctTreeF64<K, T> = Tree<K, OctHelper<f64>, T, 8>;
 
 #[derive(Debug, Clone)]
-pub struct OctHelper();
+pub struct OctHelper<S = Real>(PhantomData<S>);
 
-impl Helper<8> for OctHelper {
-    type Point = Point3<Real>;
-    type Vector = Vector3<Real>;
-    type Aabb = Aabb;
+// 为指定精度的标量生成Helper的实现，f32使用parry3d的Aabb，f64使用parry3d-f64的Aabb
+macro_rules! impl_oct_helper {
+    ($s:ident, $aabb:ident) => {
+        impl Helper<8> for OctHelper<$s> {
+            type Point = Point3<$s>;
+            type Vector = Vector3<$s>;
+            type Aabb = $aabb;
+            type Scalar = $s;
 
-    /// 获得AABB的差
-    fn aabb_extents(aabb: &Aabb) -> Vector3<Real> {
-        aabb.extents()
+            /// 获得AABB的差
+            fn aabb_extents(aabb: &$aabb) -> Vector3<$s> {
+                aabb.extents()
+            }
+            /// 移动AABB
+            fn aabb_shift(aabb: &$aabb, distance: &Vector3<$s>) -> $aabb {
+                $aabb::new(aabb.mins + distance, aabb.maxs + distance)
+            }
+            /// 判断指定的aabb是否包含另一个aabb
+            fn aabb_contains(aabb: &$aabb, other: &$aabb) -> bool {
+                aabb.contains(other)
+            }
+            /// 判断2个aabb是否相交
+            fn aabb_intersects(aabb: &$aabb, other: &$aabb) -> bool {
+                aabb.intersects(other)
+            }
+            /// 计算点到aabb的最近距离的平方，点在aabb内则为0
+            fn aabb_point_distance_sq(aabb: &$aabb, point: &Point3<$s>) -> $s {
+                (aabb.mins - point)
+                    .sup(&(point - aabb.maxs))
+                    .sup(&Vector3::zeros())
+                    .norm_squared()
+            }
+            /// 计算点到aabb的最远距离的平方
+            fn aabb_point_max_distance_sq(aabb: &$aabb, point: &Point3<$s>) -> $s {
+                (point - aabb.mins)
+                    .abs()
+                    .sup(&(point - aabb.maxs).abs())
+                    .norm_squared()
+            }
+            /// 计算点到aabb表面的有符号距离，点在aabb内则为负
+            fn aabb_point_signed_distance(aabb: &$aabb, point: &Point3<$s>) -> $s {
+                let d = (aabb.mins - point).sup(&(point - aabb.maxs));
+                d.sup(&Vector3::zeros()).norm() + d.max().min(0.0)
+            }
+            /// 计算点到aabb中心的距离的平方
+            fn aabb_center_distance_sq(aabb: &$aabb, point: &Point3<$s>) -> $s {
+                (aabb.center() - point).norm_squared()
+            }
+            /// 获得aabb的中心点
+            fn aabb_center(aabb: &$aabb) -> Point3<$s> {
+                aabb.center()
+            }
+            /// 获得包含2个aabb的最小aabb
+            fn aabb_merged(aabb: &$aabb, other: &$aabb) -> $aabb {
+                aabb.merged(other)
+            }
+            /// 判断aabb是否有效，所有坐标都是有限值，并且每个轴上min不大于max
+            fn aabb_is_valid(aabb: &$aabb) -> bool {
+                aabb.mins
+                    .iter()
+                    .zip(aabb.maxs.iter())
+                    .all(|(min, max)| min.is_finite() && max.is_finite() && min <= max)
+            }
+            /// 计算2个点的距离的平方
+            fn point_distance_sq(a: &Point3<$s>, b: &Point3<$s>) -> $s {
+                (a - b).norm_squared()
+            }
+            /// 在2个点之间插值，t为0时返回a，t为1时返回b
+            fn point_lerp(a: &Point3<$s>, b: &Point3<$s>, t: $s) -> Point3<$s> {
+                a + (b - a) * t
+            }
+            /// 计算八叉树的深度
+            fn get_deap(
+                d: &mut Vector3<$s>,
+                loose_layer: usize,
+                max_loose: &Vector3<$s>,
+                deep: usize,
+                min_loose: &Vector3<$s>,
+            ) -> usize {
+                let two = $s::one() + $s::one();
+                let x = ComplexField::powf(
+                    (max_loose.x / d.x + $s::one()) / two,
+                    FromPrimitive::from_usize(loose_layer).unwrap(),
+                );
+                let y = ComplexField::powf(
+                    (max_loose.y / d.y + $s::one()) / two,
+                    FromPrimitive::from_usize(loose_layer).unwrap(),
+                );
+                let z = ComplexField::powf(
+                    (max_loose.z / d.z + $s::one()) / two,
+                    FromPrimitive::from_usize(loose_layer).unwrap(),
+                );
+                d.x *= x;
+                d.y *= y;
+                d.z *= z;
+                let deep = if loose_layer < deep {
+                    // 高于该层的节点，松散值都是用最小值， 也可计算其下每层的八叉节点的大小
+                    // 八叉节点的大小如果小于最小松散值的2倍， 应该停止向下划分， 因为最小松散值占据了八叉节点的大部分
+                    // 最大层由设置值和该停止划分的层的最小值
+                    let mut calc_deep = loose_layer;
+                    let min = min_loose * two;
+                    while calc_deep < deep && d.x >= min.x && d.y >= min.y && d.z >= min.z {
+                        *d = (*d + min_loose) / two;
+                        calc_deep += 1;
+                    }
+                    calc_deep
+                } else {
+                    deep
+                };
+                deep
+            }
+
+            #[inline]
+            /// 判定指定向量是否小于最小“松散”尺寸
+            fn smaller_than_min_loose(d: &Vector3<$s>, min_loose: &Vector3<$s>) -> bool {
+                if d.x <= min_loose.x && d.y <= min_loose.y && d.z <= min_loose.z {
+                    return true;
+                };
+                return false;
+            }
+
+            #[inline]
+            /// 指定向量以及最大松散尺寸计算对应的层
+            fn calc_layer(loose: &Vector3<$s>, el: &Vector3<$s>) -> usize {
+                let x = if el.x == $s::zero() {
+                    usize::max_value()
+                } else {
+                    (loose.x / el.x).as_()
+                };
+                let y = if el.y == $s::zero() {
+                    usize::max_value()
+                } else {
+                    (loose.y / el.y).as_()
+                };
+                let z = if el.z == $s::zero() {
+                    usize::max_value()
+                } else {
+                    (loose.z / el.z).as_()
+                };
+                let min = x.min(y).min(z);
+                if min == 0 {
+                    return 0;
+                }
+                (mem::size_of::<usize>() << 3) - (min.leading_zeros() as usize) - 1
+            }
+
+            #[inline]
+            /// 判断所在的子节点
+            fn get_child(point: &Point3<$s>, aabb: &$aabb) -> u8 {
+                let mut i = 0;
+                if aabb.maxs.x > point.x {
+                    i += 1;
+                }
+                if aabb.maxs.y > point.y {
+                    i += 2;
+                }
+                if aabb.maxs.z > point.z {
+                    i += 4;
+                }
+                i
+            }
+
+            #[inline]
+            fn get_max_half_loose(aabb: &$aabb, loose: &Vector3<$s>) -> Point3<$s> {
+                let two = $s::one() + $s::one();
+                let x = (aabb.mins.x + aabb.maxs.x + loose.x) / two;
+                let y = (aabb.mins.y + aabb.maxs.y + loose.y) / two;
+                let z = (aabb.mins.z + aabb.maxs.z + loose.z) / two;
+                Point3::new(x, y, z)
+            }
+
+            /// 创建ab的子节点集合
+            fn make_childs(aabb: &$aabb, loose: &Vector3<$s>) -> [$aabb; 8] {
+                let two = $s::one() + $s::one();
+                let x = (aabb.mins.x + aabb.maxs.x - loose.x) / two;
+                let y = (aabb.mins.y + aabb.maxs.y - loose.y) / two;
+                let z = (aabb.mins.z + aabb.maxs.z - loose.z) / two;
+                let p1 = Point3::new(x, y, z);
+                let p2 = Self::get_max_half_loose(&aabb, &loose);
+                [
+                    $aabb::new(aabb.mins, p2),
+                    $aabb::new(
+                        Point3::new(p1.x, aabb.mins.y, aabb.mins.z),
+                        Point3::new(aabb.maxs.x, p2.y, p2.z),
+                    ),
+                    $aabb::new(
+                        Point3::new(aabb.mins.x, p1.y, aabb.mins.z),
+                        Point3::new(p2.x, aabb.maxs.y, p2.z),
+                    ),
+                    $aabb::new(
+                        Point3::new(p1.x, p1.y, aabb.mins.z),
+                        Point3::new(aabb.maxs.x, aabb.maxs.y, p2.z),
+                    ),
+                    $aabb::new(
+                        Point3::new(aabb.mins.x, aabb.mins.y, p1.z),
+                        Point3::new(p2.x, p2.y, aabb.maxs.z),
+                    ),
+                    $aabb::new(
+                        Point3::new(p1.x, aabb.mins.y, p1.z),
+                        Point3::new(aabb.maxs.x, p2.y, aabb.maxs.z),
+                    ),
+                    $aabb::new(
+                        Point3::new(aabb.mins.x, p1.y, p1.z),
+                        Point3::new(p2.x, aabb.maxs.y, aabb.maxs.z),
+                    ),
+                    $aabb::new(p1, aabb.maxs),
+                ]
+            }
+
+            /// 指定创建ab的子节点
+            fn create_child(
+                aabb: &$aabb,
+                loose: &Vector3<$s>,
+                layer: usize,
+                loose_layer: usize,
+                min_loose: &Vector3<$s>,
+                index: u8,
+            ) -> ($aabb, Vector3<$s>) {
+                let two = $s::one() + $s::one();
+                macro_rules! c1 {
+                    ($c:ident) => {
+                        (aabb.mins.$c + aabb.maxs.$c - loose.$c) / two
+                    };
+                }
+                macro_rules! c2 {
+                    ($c:ident) => {
+                        (aabb.mins.$c + aabb.maxs.$c + loose.$c) / two
+                    };
+                }
+                let a = match index {
+                    0 => $aabb::new(aabb.mins, Point3::new(c2!(x), c2!(y), c2!(z))),
+                    1 => $aabb::new(
+                        Point3::new(c1!(x), aabb.mins.y, aabb.mins.z),
+                        Point3::new(aabb.maxs.x, c2!(y), c2!(z)),
+                    ),
+                    2 => $aabb::new(
+                        Point3::new(aabb.mins.x, c1!(y), aabb.mins.z),
+                        Point3::new(c2!(x), aabb.maxs.y, c2!(z)),
+                    ),
+                    3 => $aabb::new(
+                        Point3::new(c1!(x), c1!(y), aabb.mins.z),
+                        Point3::new(aabb.maxs.x, aabb.maxs.y, c2!(z)),
+                    ),
+                    4 => $aabb::new(
+                        Point3::new(aabb.mins.x, aabb.mins.y, c1!(z)),
+                        Point3::new(c2!(x), c2!(y), aabb.maxs.z),
+                    ),
+                    5 => $aabb::new(
+                        Point3::new(c1!(x), aabb.mins.y, c1!(z)),
+                        Point3::new(aabb.maxs.x, c2!(y), aabb.maxs.z),
+                    ),
+                    6 => $aabb::new(
+                        Point3::new(aabb.mins.x, c1!(y), c1!(z)),
+                        Point3::new(c2!(x), aabb.maxs.y, aabb.maxs.z),
+                    ),
+                    _ => $aabb::new(Point3::new(c1!(x), c1!(y), c1!(z)), aabb.maxs),
+                };
+                let loose = if layer < loose_layer {
+                    loose / two
+                } else {
+                    min_loose.clone()
+                };
+                (a, loose)
+            }
+        }
+    };
+}
+impl_oct_helper!(f32, Aabb);
+impl_oct_helper!(f64, Aabb64);
+
+impl<K: Key, T: Clone> OctTree<K, T> {
+    /// 将八叉树投影成四叉树，丢弃z轴，保留id及绑定，用于小地图等2D查询
+    /// + 根空间、松散参数取xy分量，收缩分裂阈值及深度限制不变
+    pub fn project_to_quad(&self) -> QuadTree<K, T> {
+        let root = &self.slab[self.root_key].aabb;
+        let mut tree = QuadTree::new(
+            project_aabb(root),
+            Vector2::new(self.max_loose.x, self.max_loose.y),
+            Vector2::new(self.min_loose.x, self.min_loose.y),
+            self.adjust.0,
+            self.adjust.1,
+            self.deep,
+        );
+        tree.add_batch(self.ab_map.keys().map(|id| {
+            let (aabb, bind) = self.get(id).unwrap();
+            (id, project_aabb(aabb), bind.clone())
+        }));
+        tree
     }
-    /// 移动AABB
-    fn aabb_shift(aabb: &Aabb, distance: &Vector3<Real>) -> Aabb {
-        Aabb::new(aabb.mins + distance, aabb.maxs + distance)
+}
+
+/// 丢弃z轴，将3D的aabb投影到xy平面
+fn project_aabb(aabb: &Aabb) -> Aabb2 {
+    Aabb2::new(
+        Point2::new(aabb.mins.x, aabb.mins.y),
+        Point2::new(aabb.maxs.x, aabb.maxs.y),
+    )
+}
+
+/// oct节点查询函数的范本，aabb是否相交，参数a是查询参数，参数b是oct节点的aabb， 所以最常用的判断是左闭右开
+/// 应用方为了功能和性能，应该实现自己需要的oct节点的查询函数， 比如点查询， 球查询， 视锥体查询...
+#[inline]
+pub fn intersects(a: &Aabb, b: &Aabb) -> bool {
+    a.mins.x <= b.maxs.x
+        && a.maxs.x > b.mins.x
+        && a.mins.y <= b.maxs.y
+        && a.maxs.y > b.mins.y
+        && a.mins.z <= b.maxs.z
+        && a.maxs.z > b.mins.z
+}
+
+/// 双精度的oct节点查询函数，和intersects相同
+#[inline]
+pub fn intersects_f64(a: &Aabb64, b: &Aabb64) -> bool {
+    a.mins.x <= b.maxs.x
+        && a.maxs.x > b.mins.x
+        && a.mins.y <= b.maxs.y
+        && a.maxs.y > b.mins.y
+        && a.mins.z <= b.maxs.z
+        && a.maxs.z > b.mins.z
+}
+
+/// oct节点查询函数的范本，aabb是否相交，两边都是闭区间，边界接触也算相交
+/// + 需要和物理引擎的接触生成保持一致（接触的物体算碰撞）时使用，其余情况应该使用左闭右开的intersects
+#[inline]
+pub fn intersects_closed(a: &Aabb, b: &Aabb) -> bool {
+    a.mins.x <= b.maxs.x
+        && a.maxs.x >= b.mins.x
+        && a.mins.y <= b.maxs.y
+        && a.maxs.y >= b.mins.y
+        && a.mins.z <= b.maxs.z
+        && a.maxs.z >= b.mins.z
+}
+
+/// aabb的查询函数的参数
+pub struct AbQueryArgs<K: Key, T> {
+    pub aabb: Aabb,
+    pub result: Vec<(K, T)>,
+    pub max_results: Option<usize>, // 结果数量上限，None表示不限制
+    pub truncated: bool,            // 是否因为达到上限而丢弃了结果
+}
+impl<K: Key, T: Clone> AbQueryArgs<K, T> {
+    pub fn new(aabb: Aabb) -> AbQueryArgs<K, T> {
+        AbQueryArgs {
+            aabb: aabb,
+            result: Vec::new(),
+            max_results: None,
+            truncated: false,
+        }
     }
-    /// 判断指定的aabb是否包含另一个aabb
-    fn aabb_contains(aabb: &Aabb, other: &Aabb) -> bool {
-        aabb.contains(other)
+    /// 创建限制结果数量的查询参数，达到上限后丢弃之后的结果，并设置truncated，防止查询范围过大时结果过多
+    pub fn with_max_results(aabb: Aabb, max_results: usize) -> AbQueryArgs<K, T> {
+        AbQueryArgs {
+            aabb,
+            result: Vec::new(),
+            max_results: Some(max_results),
+            truncated: false,
+        }
     }
-    /// 判断2个aabb是否相交
-    fn aabb_intersects(aabb: &Aabb, other: &Aabb) -> bool {
-        aabb.intersects(other)
+}
+
+/// ab节点的查询函数, 这里只是一个简单范本，使用了oct节点的查询函数intersects
+/// 应用方为了功能和性能，应该实现自己需要的ab节点的查询函数， 比如点查询， 球查询-包含或相交， 视锥体查询...
+pub fn ab_query_func<K: Key, T: Clone>(arg: &mut AbQueryArgs<K, T>, id: K, aabb: &Aabb, bind: &T) {
+    if intersects(&arg.aabb, aabb) {
+        match arg.max_results {
+            Some(max) if arg.result.len() >= max => arg.truncated = true,
+            _ => arg.result.push((id, bind.clone())),
+        }
     }
-    /// 计算八叉树的深度
-    fn get_deap(
-        d: &mut Vector3<Real>,
-        loose_layer: usize,
-        max_loose: &Vector3<Real>,
-        deep: usize,
-        min_loose: &Vector3<Real>,
-    ) -> usize {
-        let two = Real::one() + Real::one();
-        let x = ComplexField::powf(
-            (max_loose.x / d.x + Real::one()) / two,
-            FromPrimitive::from_usize(loose_layer).unwrap(),
-        );
-        let y = ComplexField::powf(
-            (max_loose.y / d.y + Real::one()) / two,
-            FromPrimitive::from_usize(loose_layer).unwrap(),
-        );
-        let z = ComplexField::powf(
-            (max_loose.z / d.z + Real::one()) / two,
-            FromPrimitive::from_usize(loose_layer).unwrap(),
-        );
-        d.x *= x;
-        d.y *= y;
-        d.z *= z;
-        let deep = if loose_layer < deep {
-            // 高于该层的节点，松散值都是用最小值， 也可计算其下每层的八叉节点的大小
-            // 八叉节点的大小如果小于最小松散值的2倍， 应该停止向下划分， 因为最小松散值占据了八叉节点的大部分
-            // 最大层由设置值和该停止划分的层的最小值
-            let mut calc_deep = loose_layer;
-            let min = min_loose * two;
-            while calc_deep < deep && d.x >= min.x && d.y >= min.y && d.z >= min.z {
-                *d = (*d + min_loose) / two;
-                calc_deep += 1;
-            }
-            calc_deep
-        } else {
-            deep
-        };
-        deep
+}
+
+/// ab节点的包含查询函数，只有aabb被查询范围完全包含的节点才会被接受，比如框选
+/// + 注意：oct节点的查询函数仍然应该使用相交判断intersects，被完全包含的节点可能在和查询范围相交的oct节点中
+pub fn ab_query_contains_func<K: Key, T: Clone>(
+    arg: &mut AbQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    if arg.aabb.contains(aabb) {
+        arg.result.push((id, bind.clone()));
     }
+}
 
-    #[inline]
-    /// 判定指定向量是否小于最小“松散”尺寸
-    fn smaller_than_min_loose(d: &Vector3<Real>, min_loose: &Vector3<Real>) -> bool {
-        if d.x <= min_loose.x && d.y <= min_loose.y && d.z <= min_loose.z {
-            return true;
+/// 视锥体，由6个平面组成，平面为(法线, d)，法线指向视锥体内部，点p在平面内侧表示 法线·p + d >= 0
+#[derive(Debug, Clone)]
+pub struct Frustum {
+    pub planes: [(Vector3<Real>, Real); 6],
+}
+impl Frustum {
+    pub fn new(planes: [(Vector3<Real>, Real); 6]) -> Self {
+        Frustum { planes }
+    }
+    /// 从观察投影矩阵提取视锥体的6个平面，要求投影矩阵的裁剪空间z范围为[-w, w]，比如nalgebra的Perspective3
+    pub fn from_matrix(m: &Matrix4<Real>) -> Self {
+        let row = |i: usize| m.row(i).transpose();
+        let (r0, r1, r2, r3) = (row(0), row(1), row(2), row(3));
+        let plane = |v: Vector4<Real>| {
+            let n = Vector3::new(v.x, v.y, v.z);
+            let len = n.norm();
+            (n / len, v.w / len)
         };
-        return false;
+        Frustum {
+            planes: [
+                plane(r3 + r0), // 左
+                plane(r3 - r0), // 右
+                plane(r3 + r1), // 下
+                plane(r3 - r1), // 上
+                plane(r3 + r2), // 近
+                plane(r3 - r2), // 远
+            ],
+        }
     }
+}
 
-    #[inline]
-    /// 指定向量以及最大松散尺寸计算对应的层
-    fn calc_layer(loose: &Vector3<Real>, el: &Vector3<Real>) -> usize {
-        let x = if el.x == Real::zero() {
-            usize::max_value()
-        } else {
-            (loose.x / el.x).as_()
-        };
-        let y = if el.y == Real::zero() {
-            usize::max_value()
-        } else {
-            (loose.y / el.y).as_()
-        };
-        let z = if el.z == Real::zero() {
-            usize::max_value()
-        } else {
-            (loose.z / el.z).as_()
-        };
-        let min = x.min(y).min(z);
-        if min == 0 {
-            return 0;
+/// 视锥体和aabb是否相交，可作为叉树节点的查询函数
+/// 使用“正顶点”测试：aabb只要不完全在任一平面的外侧，就认为可见。结果是保守的，不会漏掉可见的aabb
+#[inline]
+pub fn frustum_intersects_aabb(frustum: &Frustum, aabb: &Aabb) -> bool {
+    for (n, d) in &frustum.planes {
+        // 沿法线方向最远的顶点
+        let p = Vector3::new(
+            if n.x >= 0.0 { aabb.maxs.x } else { aabb.mins.x },
+            if n.y >= 0.0 { aabb.maxs.y } else { aabb.mins.y },
+            if n.z >= 0.0 { aabb.maxs.z } else { aabb.mins.z },
+        );
+        if n.dot(&p) + d < 0.0 {
+            return false;
         }
-        (mem::size_of::<usize>() << 3) - (min.leading_zeros() as usize) - 1
     }
+    true
+}
 
-    #[inline]
-    /// 判断所在的子节点
-    fn get_child(point: &Point3<Real>, aabb: &Aabb) -> u8 {
-        let mut i = 0;
-        if aabb.maxs.x > point.x {
-            i += 1;
+/// 视锥体查询函数的参数
+pub struct FrustumQueryArgs<K: Key, T> {
+    pub frustum: Frustum,
+    pub result: Vec<(K, T)>,
+}
+impl<K: Key, T: Clone> FrustumQueryArgs<K, T> {
+    pub fn new(frustum: Frustum) -> FrustumQueryArgs<K, T> {
+        FrustumQueryArgs {
+            frustum,
+            result: Vec::new(),
         }
-        if aabb.maxs.y > point.y {
-            i += 2;
+    }
+}
+
+/// 视锥体的ab节点查询函数，和视锥体相交的节点放入结果
+pub fn frustum_ab_query_func<K: Key, T: Clone>(
+    arg: &mut FrustumQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    if frustum_intersects_aabb(&arg.frustum, aabb) {
+        arg.result.push((id, bind.clone()));
+    }
+}
+
+/// 点查询的oct节点查询函数，点是否在oct节点的松散aabb内，边界上的点也算在内，保证本层的节点不会漏掉
+#[inline]
+pub fn contains_point(a: &Point3<Real>, b: &Aabb) -> bool {
+    a.x >= b.mins.x
+        && a.x <= b.maxs.x
+        && a.y >= b.mins.y
+        && a.y <= b.maxs.y
+        && a.z >= b.mins.z
+        && a.z <= b.maxs.z
+}
+
+/// 点查询函数的参数
+pub struct PointQueryArgs<K: Key, T> {
+    pub point: Point3<Real>,
+    pub result: Vec<(K, T)>,
+}
+impl<K: Key, T: Clone> PointQueryArgs<K, T> {
+    pub fn new(point: Point3<Real>) -> PointQueryArgs<K, T> {
+        PointQueryArgs {
+            point,
+            result: Vec::new(),
         }
-        if aabb.maxs.z > point.z {
-            i += 4;
+    }
+}
+
+/// 点查询的ab节点查询函数，aabb包含点的节点放入结果，和intersects一样左闭右开
+pub fn point_ab_query_func<K: Key, T: Clone>(
+    arg: &mut PointQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    let p = &arg.point;
+    if p.x >= aabb.mins.x
+        && p.x < aabb.maxs.x
+        && p.y >= aabb.mins.y
+        && p.y < aabb.maxs.y
+        && p.z >= aabb.mins.z
+        && p.z < aabb.maxs.z
+    {
+        arg.result.push((id, bind.clone()));
+    }
+}
+
+// 线段(start, start+d)和aabb相交时，返回进入aabb的参数t，t在[0, 1]内，slab算法
+fn segment_aabb_entry(start: Point3<Real>, d: Vector3<Real>, b: &Aabb) -> Option<Real> {
+    let (mut t0, mut t1) = (0.0, 1.0);
+    for i in 0..3 {
+        if d[i] == 0.0 {
+            if start[i] < b.mins[i] || start[i] > b.maxs[i] {
+                return None;
+            }
+        } else {
+            let (mut near, mut far) =
+                ((b.mins[i] - start[i]) / d[i], (b.maxs[i] - start[i]) / d[i]);
+            if near > far {
+                mem::swap(&mut near, &mut far);
+            }
+            t0 = near.max(t0);
+            t1 = far.min(t1);
+            if t0 > t1 {
+                return None;
+            }
         }
-        i
     }
+    Some(t0)
+}
+
+/// 线段(p0, p1)和aabb是否相交，边界接触也算相交，可以作为oct节点的查询函数，对松散aabb是保守的
+#[inline]
+pub fn segment_intersects_aabb(p0: &Point3<Real>, p1: &Point3<Real>, b: &Aabb) -> bool {
+    segment_aabb_entry(*p0, p1 - p0, b).is_some()
+}
 
-    #[inline]
-    fn get_max_half_loose(aabb: &Aabb, loose: &Vector3<Real>) -> Point3<Real> {
-        let two = Real::one() + Real::one();
-        let x = (aabb.mins.x + aabb.maxs.x + loose.x) / two;
-        let y = (aabb.mins.y + aabb.maxs.y + loose.y) / two;
-        let z = (aabb.mins.z + aabb.maxs.z + loose.z) / two;
-        Point3::new(x, y, z)
+/// 线段，用于视线及激光等查询
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub p0: Point3<Real>,
+    pub p1: Point3<Real>,
+}
+impl Segment {
+    pub fn new(p0: Point3<Real>, p1: Point3<Real>) -> Self {
+        Segment { p0, p1 }
     }
+}
 
-    /// 创建ab的子节点集合
-    fn make_childs(aabb: &Aabb, loose: &Vector3<Real>) -> [Aabb; 8] {
-        let two = Real::one() + Real::one();
-        let x = (aabb.mins.x + aabb.maxs.x - loose.x) / two;
-        let y = (aabb.mins.y + aabb.maxs.y - loose.y) / two;
-        let z = (aabb.mins.z + aabb.maxs.z - loose.z) / two;
-        let p1 = Point3::new(x, y, z);
-        let p2 = Self::get_max_half_loose(&aabb, &loose);
-        [
-            Aabb::new(aabb.mins, p2),
-            Aabb::new(
-                Point3::new(p1.x, aabb.mins.y, aabb.mins.z),
-                Point3::new(aabb.maxs.x, p2.y, p2.z),
-            ),
-            Aabb::new(
-                Point3::new(aabb.mins.x, p1.y, aabb.mins.z),
-                Point3::new(p2.x, aabb.maxs.y, p2.z),
-            ),
-            Aabb::new(
-                Point3::new(p1.x, p1.y, aabb.mins.z),
-                Point3::new(aabb.maxs.x, aabb.maxs.y, p2.z),
-            ),
-            Aabb::new(
-                Point3::new(aabb.mins.x, aabb.mins.y, p1.z),
-                Point3::new(p2.x, p2.y, aabb.maxs.z),
-            ),
-            Aabb::new(
-                Point3::new(p1.x, aabb.mins.y, p1.z),
-                Point3::new(aabb.maxs.x, p2.y, aabb.maxs.z),
-            ),
-            Aabb::new(
-                Point3::new(aabb.mins.x, p1.y, p1.z),
-                Point3::new(p2.x, aabb.maxs.y, aabb.maxs.z),
-            ),
-            Aabb::new(p1, aabb.maxs),
-        ]
+/// 线段的oct节点查询函数
+pub fn segment_branch_func(segment: &Segment, b: &Aabb) -> bool {
+    segment_intersects_aabb(&segment.p0, &segment.p1, b)
+}
+
+/// 线段查询函数的参数，结果为(id, 绑定, 进入aabb的参数t)，t为0表示p0在aabb内，t为1表示在p1处进入
+pub struct SegmentQueryArgs<K: Key, T> {
+    pub segment: Segment,
+    pub result: Vec<(K, T, Real)>,
+}
+impl<K: Key, T: Clone> SegmentQueryArgs<K, T> {
+    pub fn new(segment: Segment) -> SegmentQueryArgs<K, T> {
+        SegmentQueryArgs {
+            segment,
+            result: Vec::new(),
+        }
+    }
+    /// 按进入aabb的参数t从近到远排序结果
+    pub fn sort_by_t(&mut self) {
+        self.result
+            .sort_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(Ordering::Equal));
     }
+}
 
-    /// 指定创建ab的子节点
-    fn create_child(
-        aabb: &Aabb,
-        loose: &Vector3<Real>,
-        layer: usize,
-        loose_layer: usize,
-        min_loose: &Vector3<Real>,
-        index: u8,
-    ) -> (Aabb, Vector3<Real>) {
-        let two = Real::one() + Real::one();
-        macro_rules! c1 {
-            ($c:ident) => {
-                (aabb.mins.$c + aabb.maxs.$c - loose.$c) / two
-            };
+/// 线段的ab节点查询函数，和线段相交的节点及进入参数t放入结果
+pub fn segment_ab_query_func<K: Key, T: Clone>(
+    arg: &mut SegmentQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    let s = &arg.segment;
+    if let Some(t) = segment_aabb_entry(s.p0, s.p1 - s.p0, aabb) {
+        arg.result.push((id, bind.clone(), t));
+    }
+}
+
+/// 移动的aabb，从aabb的位置沿delta平移，用于移动触发器的连续碰撞查询
+#[derive(Debug, Clone)]
+pub struct SweptAabb {
+    pub aabb: Aabb,
+    pub delta: Vector3<Real>,
+}
+impl SweptAabb {
+    pub fn new(aabb: Aabb, delta: Vector3<Real>) -> Self {
+        SweptAabb { aabb, delta }
+    }
+}
+
+/// 移动的aabb和aabb相交时，返回开始接触的参数t，t在[0, 1]内
+/// + 将b按移动aabb的半尺寸扩大，再和移动aabb中心的线段判断相交，结果是精确的，比查询起点和终点的合并aabb准确
+pub fn swept_aabb_entry(swept: &SweptAabb, b: &Aabb) -> Option<Real> {
+    let half = swept.aabb.half_extents();
+    let inflated = Aabb::new(b.mins - half, b.maxs + half);
+    segment_aabb_entry(swept.aabb.center(), swept.delta, &inflated)
+}
+
+/// 移动aabb的oct节点查询函数
+pub fn swept_aabb_branch_func(swept: &SweptAabb, b: &Aabb) -> bool {
+    swept_aabb_entry(swept, b).is_some()
+}
+
+/// 移动aabb查询函数的参数，结果为(id, 绑定, 开始接触的参数t)
+pub struct SweptAabbQueryArgs<K: Key, T> {
+    pub swept: SweptAabb,
+    pub result: Vec<(K, T, Real)>,
+}
+impl<K: Key, T: Clone> SweptAabbQueryArgs<K, T> {
+    pub fn new(swept: SweptAabb) -> SweptAabbQueryArgs<K, T> {
+        SweptAabbQueryArgs {
+            swept,
+            result: Vec::new(),
+        }
+    }
+}
+
+/// 移动aabb的ab节点查询函数，移动过程中接触到的节点及开始接触的参数t放入结果
+pub fn swept_aabb_ab_query_func<K: Key, T: Clone>(
+    arg: &mut SweptAabbQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    if let Some(t) = swept_aabb_entry(&arg.swept, aabb) {
+        arg.result.push((id, bind.clone(), t));
+    }
+}
+
+/// 按距离排序的查询函数的参数，结果为(id, aabb到参考点的距离的平方, 绑定)，按距离从近到远排列
+/// + 排序的开销在收集结果时支付，每个结果二分查找插入位置，结果较多时插入的移动开销为O(n^2)
+pub struct DistanceQueryArgs<K: Key, T> {
+    pub aabb: Aabb,
+    pub point: Point3<Real>,
+    pub result: Vec<(K, Real, T)>,
+}
+impl<K: Key, T: Clone> DistanceQueryArgs<K, T> {
+    pub fn new(aabb: Aabb, point: Point3<Real>) -> DistanceQueryArgs<K, T> {
+        DistanceQueryArgs {
+            aabb,
+            point,
+            result: Vec::new(),
+        }
+    }
+}
+
+/// 按距离排序的ab节点查询函数，和范围相交的节点按aabb到参考点的距离插入结果，保持从近到远，距离相同的按查询到的顺序
+/// + oct节点的查询函数使用intersects
+pub fn distance_ab_query_func<K: Key, T: Clone>(
+    arg: &mut DistanceQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    if intersects(&arg.aabb, aabb) {
+        let d = OctHelper::<Real>::aabb_point_distance_sq(aabb, &arg.point);
+        let index = arg.result.partition_point(|r| r.1 <= d);
+        arg.result.insert(index, (id, d, bind.clone()));
+    }
+}
+
+/// 线段(p0, p1)到aabb的最近距离的平方，相交则为0
+/// + 按线段穿过aabb各个面所在平面的位置分段，每段上距离的平方是t的二次函数，分别求最小值
+pub fn segment_aabb_distance_sq(p0: &Point3<Real>, p1: &Point3<Real>, b: &Aabb) -> Real {
+    let d = p1 - p0;
+    // 分段点最多为两个端点加6个面
+    let mut ts: [Real; 8] = [0.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+    let mut len = 2;
+    for i in 0..3 {
+        if d[i] != 0.0 {
+            for v in [b.mins[i], b.maxs[i]] {
+                let t = (v - p0[i]) / d[i];
+                if t > 0.0 && t < 1.0 {
+                    ts[len] = t;
+                    len += 1;
+                }
+            }
         }
-        macro_rules! c2 {
-            ($c:ident) => {
-                (aabb.mins.$c + aabb.maxs.$c + loose.$c) / two
+    }
+    let ts = &mut ts[..len];
+    ts.sort_unstable_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let mut min = Real::MAX;
+    for w in ts.windows(2) {
+        // 段内每个轴在aabb的哪一侧不变，用段中点判断
+        let mid = p0 + d * ((w[0] + w[1]) * 0.5);
+        let (mut a, mut c) = (0.0, 0.0);
+        for i in 0..3 {
+            let v = if mid[i] < b.mins[i] {
+                b.mins[i]
+            } else if mid[i] > b.maxs[i] {
+                b.maxs[i]
+            } else {
+                continue;
             };
+            a += d[i] * d[i];
+            c += d[i] * (p0[i] - v);
         }
-        let a = match index {
-            0 => Aabb::new(aabb.mins, Point3::new(c2!(x), c2!(y), c2!(z))),
-            1 => Aabb::new(
-                Point3::new(c1!(x), aabb.mins.y, aabb.mins.z),
-                Point3::new(aabb.maxs.x, c2!(y), c2!(z)),
-            ),
-            2 => Aabb::new(
-                Point3::new(aabb.mins.x, c1!(y), aabb.mins.z),
-                Point3::new(c2!(x), aabb.maxs.y, c2!(z)),
-            ),
-            3 => Aabb::new(
-                Point3::new(c1!(x), c1!(y), aabb.mins.z),
-                Point3::new(aabb.maxs.x, aabb.maxs.y, c2!(z)),
-            ),
-            4 => Aabb::new(
-                Point3::new(aabb.mins.x, aabb.mins.y, c1!(z)),
-                Point3::new(c2!(x), c2!(y), aabb.maxs.z),
-            ),
-            5 => Aabb::new(
-                Point3::new(c1!(x), aabb.mins.y, c1!(z)),
-                Point3::new(aabb.maxs.x, c2!(y), aabb.maxs.z),
-            ),
-            6 => Aabb::new(
-                Point3::new(aabb.mins.x, c1!(y), c1!(z)),
-                Point3::new(c2!(x), aabb.maxs.y, aabb.maxs.z),
-            ),
-            _ => Aabb::new(Point3::new(c1!(x), c1!(y), c1!(z)), aabb.maxs),
-        };
-        let loose = if layer < loose_layer {
-            loose / two
+        let t = if a > 0.0 {
+            (-c / a).max(w[0]).min(w[1])
         } else {
-            min_loose.clone()
+            w[0]
         };
-        (a, loose)
+        let p = p0 + d * t;
+        min = min.min(
+            (b.mins - p)
+                .sup(&(p - b.maxs))
+                .sup(&Vector3::zeros())
+                .norm_squared(),
+        );
     }
+    min
 }
 
-/// oct节点查询函数的范本，aabb是否相交，参数a是查询参数，参数b是oct节点的aabb， 所以最常用的判断是左闭右开
-/// 应用方为了功能和性能，应该实现自己需要的oct节点的查询函数， 比如点查询， 球查询， 视锥体查询...
+/// 胶囊体，线段(p0, p1)扫过半径为radius的球，用于角色控制器的移动查询
+#[derive(Debug, Clone)]
+pub struct Capsule {
+    pub p0: Point3<Real>,
+    pub p1: Point3<Real>,
+    pub radius: Real,
+}
+impl Capsule {
+    pub fn new(p0: Point3<Real>, p1: Point3<Real>, radius: Real) -> Self {
+        Capsule { p0, p1, radius }
+    }
+}
+
+/// 胶囊体和aabb是否相交，距离正好等于半径也算相交
 #[inline]
-pub fn intersects(a: &Aabb, b: &Aabb) -> bool {
-    a.mins.x <= b.maxs.x
-        && a.maxs.x > b.mins.x
-        && a.mins.y <= b.maxs.y
-        && a.maxs.y > b.mins.y
-        && a.mins.z <= b.maxs.z
-        && a.maxs.z > b.mins.z
+pub fn capsule_intersects_aabb(capsule: &Capsule, b: &Aabb) -> bool {
+    segment_aabb_distance_sq(&capsule.p0, &capsule.p1, b) <= capsule.radius * capsule.radius
 }
 
-/// aabb的查询函数的参数
-pub struct AbQueryArgs<T> {
-    pub aabb: Aabb,
-    pub result: Vec<(usize, T)>,
+/// 胶囊体的oct节点查询函数，将oct节点的aabb按半径扩大后和线段判断相交，是保守的
+pub fn capsule_branch_func(capsule: &Capsule, b: &Aabb) -> bool {
+    segment_intersects_aabb(&capsule.p0, &capsule.p1, &b.loosened(capsule.radius))
 }
-impl<T: Clone> AbQueryArgs<T> {
-    pub fn new(aabb: Aabb) -> AbQueryArgs<T> {
-        AbQueryArgs {
-            aabb: aabb,
+
+/// 胶囊体查询函数的参数
+pub struct CapsuleQueryArgs<K: Key, T> {
+    pub capsule: Capsule,
+    pub result: Vec<(K, T)>,
+}
+impl<K: Key, T: Clone> CapsuleQueryArgs<K, T> {
+    pub fn new(capsule: Capsule) -> CapsuleQueryArgs<K, T> {
+        CapsuleQueryArgs {
+            capsule,
             result: Vec::new(),
         }
     }
 }
 
-/// ab节点的查询函数, 这里只是一个简单范本，使用了oct节点的查询函数intersects
-/// 应用方为了功能和性能，应该实现自己需要的ab节点的查询函数， 比如点查询， 球查询-包含或相交， 视锥体查询...
-pub fn ab_query_func<T: Clone>(
-    arg: &mut AbQueryArgs<T>,
-    id: usize,
+/// 胶囊体的ab节点查询函数，和胶囊体相交的节点放入结果
+pub fn capsule_ab_query_func<K: Key, T: Clone>(
+    arg: &mut CapsuleQueryArgs<K, T>,
+    id: K,
     aabb: &Aabb,
     bind: &T,
 ) {
-    if intersects(&arg.aabb, aabb) {
+    if capsule_intersects_aabb(&arg.capsule, aabb) {
         arg.result.push((id, bind.clone()));
     }
 }
 
+/// 根据样本aabb的尺寸分布，推荐八叉树的最大松散值、最小松散值及深度
+/// + 最大松散值取样本的最大尺寸，超过根空间的部分按根空间截断
+/// + 最小松散值取样本的中位尺寸，保证大部分节点能落在较深的层
+/// + 样本为空时，最大及最小松散值都取根空间的尺寸
+pub fn suggest_params(sample_aabbs: &[Aabb], root: &Aabb) -> (Vector3<Real>, Vector3<Real>, usize) {
+    let root_extents = root.extents();
+    if sample_aabbs.is_empty() {
+        let mut d = root_extents;
+        let deep = OctHelper::<Real>::get_deap(&mut d, 0, &root_extents, DEEP_MAX, &root_extents);
+        return (root_extents, root_extents, deep);
+    }
+    let mut max_loose: Vector3<Real> = Vector3::zeros();
+    let mut axes: [Vec<Real>; 3] = Default::default();
+    for aabb in sample_aabbs {
+        let e = aabb.extents();
+        max_loose = max_loose.sup(&e);
+        for i in 0..3 {
+            axes[i].push(e[i]);
+        }
+    }
+    let max_loose = max_loose.inf(&root_extents);
+    // 最小松散值不能为0，也不能小于最大松散值在最大深度下的尺寸
+    let limit = max_loose / (1usize << DEEP_MAX) as Real;
+    let mut min_loose = Vector3::zeros();
+    for i in 0..3 {
+        let axis = &mut axes[i];
+        axis.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        min_loose[i] = axis[axis.len() / 2].max(limit[i]).min(max_loose[i]);
+    }
+    let loose_layer = OctHelper::<Real>::calc_layer(&max_loose, &min_loose);
+    let mut d = root_extents;
+    let deep = OctHelper::<Real>::get_deap(&mut d, loose_layer, &max_loose, DEEP_MAX, &min_loose);
+    (max_loose, min_loose, deep)
+}
+
 
 
 // #[test]
@@ -1213,4 +1771,631 @@ pub fn ab_query_func<T: Clone>(
 //         tree.query(&aabb, intersects, &mut args, ab_query_func);
 //         assert!(args.result.len() > 0);
 //     }
-// }
\ No newline at end of file
+// }
+#[test]
+fn test_max_results() {
+    let mut tree: OctTree<usize, usize> = OctTree::new(
+        Aabb::new(
+            Point3::new(0f32, 0f32, 0f32),
+            Point3::new(100f32, 100f32, 100f32),
+        ),
+        Vector3::new(10f32, 10f32, 10f32),
+        Vector3::new(0.1f32, 0.1f32, 0.1f32),
+        0,
+        0,
+        0,
+    );
+    for i in 0..1000 {
+        let p = Point3::new((i % 10) as f32, (i / 10 % 10) as f32, (i / 100) as f32);
+        tree.add(i, Aabb::new(p, p + Vector3::new(0.5, 0.5, 0.5)), i);
+    }
+    tree.collect();
+    let region = Aabb::new(
+        Point3::new(0f32, 0f32, 0f32),
+        Point3::new(10f32, 10f32, 10f32),
+    );
+    let mut args: AbQueryArgs<usize, usize> = AbQueryArgs::with_max_results(region, 16);
+    tree.query(&region, intersects, &mut args, ab_query_func);
+    assert_eq!(args.result.len(), 16);
+    assert!(args.truncated);
+    // 上限足够时不截断
+    let mut args: AbQueryArgs<usize, usize> = AbQueryArgs::with_max_results(region, 1000);
+    tree.query(&region, intersects, &mut args, ab_query_func);
+    assert_eq!(args.result.len(), 1000);
+    assert!(!args.truncated);
+    let mut args: AbQueryArgs<usize, usize> = AbQueryArgs::new(region);
+    tree.query(&region, intersects, &mut args, ab_query_func);
+    assert_eq!(args.result.len(), 1000);
+    assert!(!args.truncated);
+}
+
+#[test]
+fn test_suggest_params() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let root = Aabb::new(
+        Point3::new(0f32, 0f32, 0f32),
+        Point3::new(10000f32, 10000f32, 10000f32),
+    );
+    // 大部分是1~10的小物体，少量100~200的大物体
+    let mut rng = Pcg32::seed_from_u64(99999);
+    let mut abs = Vec::new();
+    for i in 0..2000 {
+        let s = if i % 20 == 0 {
+            rng.gen_range(100f32..200f32)
+        } else {
+            rng.gen_range(1f32..10f32)
+        };
+        let p = Point3::new(
+            rng.gen_range(0f32..9000f32),
+            rng.gen_range(0f32..9000f32),
+            rng.gen_range(0f32..9000f32),
+        );
+        abs.push(Aabb::new(p, p + Vector3::new(s, s, s)));
+    }
+    let (max_loose, min_loose, deep) = suggest_params(&abs, &root);
+    assert!(max_loose.x >= 100.0 && max_loose.x <= 200.0);
+    assert!(min_loose.x >= 1.0 && min_loose.x <= 10.0);
+    assert!(deep > 0 && deep <= DEEP_MAX);
+
+    let mut tree: OctTree<usize, usize> = OctTree::new(root, max_loose, min_loose, 0, 0, deep);
+    for (i, ab) in abs.iter().enumerate() {
+        tree.add(i, *ab, i);
+    }
+    tree.collect();
+    assert!(tree.outer.is_empty());
+    // 小物体应该能落到较深的层，叉树被充分划分
+    assert!(tree.get_layer(&abs[1]) > 0);
+    assert!(tree.slab.len() > 8);
+
+    let (max_loose, min_loose, _) = suggest_params(&[], &root);
+    assert_eq!(max_loose, root.extents());
+    assert_eq!(min_loose, root.extents());
+}
+
+#[test]
+fn test_frustum() {
+    // 相机在原点，看向-z，垂直视角45°，近平面1，远平面100
+    let view = Isometry3::look_at_rh(
+        &Point3::new(0.0, 0.0, 0.0),
+        &Point3::new(0.0, 0.0, -1.0),
+        &Vector3::y(),
+    );
+    let proj = Perspective3::new(1.0, std::f32::consts::FRAC_PI_4, 1.0, 100.0);
+    let frustum = Frustum::from_matrix(&(proj.as_matrix() * view.to_homogeneous()));
+
+    let mut tree: OctTree<usize, usize> = OctTree::new(
+        Aabb::new(
+            Point3::new(-1024f32, -1024f32, -1024f32),
+            Point3::new(1024f32, 1024f32, 1024f32),
+        ),
+        Vector3::new(100f32, 100f32, 100f32),
+        Vector3::new(1f32, 1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let unit = |x: f32, y: f32, z: f32| {
+        Aabb::new(
+            Point3::new(x - 0.1, y - 0.1, z - 0.1),
+            Point3::new(x + 0.1, y + 0.1, z + 0.1),
+        )
+    };
+    // 视锥内
+    tree.add(1, unit(0.0, 0.0, -10.0), 1);
+    tree.add(2, unit(3.0, 3.0, -10.0), 2);
+    tree.add(3, unit(0.0, 0.0, -99.0), 3);
+    // 相机后方
+    tree.add(4, unit(0.0, 0.0, 10.0), 4);
+    // 相机和近平面之间
+    tree.add(5, unit(0.0, 0.0, -0.5), 5);
+    // 视角外
+    tree.add(6, unit(10.0, 0.0, -10.0), 6);
+    // 远平面外
+    tree.add(7, unit(0.0, 0.0, -200.0), 7);
+    // 跨越左平面，部分可见
+    tree.add(
+        8,
+        Aabb::new(Point3::new(-20.0, 0.0, -10.0), Point3::new(0.0, 1.0, -9.0)),
+        8,
+    );
+    for i in 100..1100 {
+        let f = i as f32;
+        tree.add(i, unit(f.sin() * 500.0, f.cos() * 500.0, 600.0), i);
+    }
+    tree.collect();
+
+    let mut args = FrustumQueryArgs::new(frustum.clone());
+    tree.query(
+        &frustum,
+        frustum_intersects_aabb,
+        &mut args,
+        frustum_ab_query_func,
+    );
+    let mut ids: Vec<usize> = args.result.iter().map(|r| r.0).collect();
+    ids.sort();
+    assert_eq!(ids, vec![1, 2, 3, 8]);
+}
+
+#[test]
+fn test_project_to_quad() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: OctTree<usize, usize> = OctTree::new(
+        Aabb::new(
+            Point3::new(-1024f32, -1024f32, -1024f32),
+            Point3::new(1024f32, 1024f32, 1024f32),
+        ),
+        Vector3::new(100f32, 100f32, 100f32),
+        Vector3::new(1f32, 1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1013);
+    for i in 1..500 {
+        let p = Point3::new(
+            rng.gen_range(-1000f32..1000f32),
+            rng.gen_range(-1000f32..1000f32),
+            rng.gen_range(-1000f32..1000f32),
+        );
+        tree.add(i, Aabb::new(p, p + Vector3::new(5.0, 5.0, 5.0)), i * 10);
+    }
+    let quad = tree.project_to_quad();
+    assert_eq!(quad.len(), tree.len());
+    for i in 1..500 {
+        let (ab3, bind3) = tree.get(i).unwrap();
+        let (ab2, bind2) = quad.get(i).unwrap();
+        assert_eq!(bind2, bind3);
+        assert_eq!(ab2.mins, Point2::new(ab3.mins.x, ab3.mins.y));
+        assert_eq!(ab2.maxs, Point2::new(ab3.maxs.x, ab3.maxs.y));
+    }
+
+    // 3D查询结果中xy相交的部分，应该都在2D查询结果中；2D查询不限制z，结果更多
+    let aabb = Aabb::new(
+        Point3::new(-300f32, -300f32, -300f32),
+        Point3::new(300f32, 300f32, 300f32),
+    );
+    let mut args3: AbQueryArgs<usize, usize> = AbQueryArgs::new(aabb);
+    tree.query(&aabb, intersects, &mut args3, ab_query_func);
+    let aabb2 = project_aabb(&aabb);
+    fn quad_func(arg: &mut (Aabb2, Vec<usize>), id: usize, aabb: &Aabb2, _bind: &usize) {
+        if crate::quad_helper::intersects(&arg.0, aabb) {
+            arg.1.push(id);
+        }
+    }
+    let mut args2 = (aabb2, Vec::new());
+    quad.query(
+        &aabb2,
+        crate::quad_helper::intersects,
+        &mut args2,
+        quad_func,
+    );
+    let mut r3: Vec<usize> = args3.result.iter().map(|r| r.0).collect();
+    let mut r2 = args2.1;
+    r3.sort();
+    r2.sort();
+    assert!(!r3.is_empty());
+    assert!(r2.len() > r3.len());
+    for id in &r3 {
+        assert!(r2.binary_search(id).is_ok());
+    }
+    // 2D结果即所有xy相交的3D节点
+    let mut expect: Vec<usize> = (1..500)
+        .filter(|&i| crate::quad_helper::intersects(&aabb2, &project_aabb(&tree.get(i).unwrap().0)))
+        .collect();
+    expect.sort();
+    assert_eq!(r2, expect);
+}
+
+#[test]
+fn test_point_query() {
+    let mut tree: OctTree<usize, usize> = OctTree::new(
+        Aabb::new(
+            Point3::new(-1024f32, -1024f32, -1024f32),
+            Point3::new(1024f32, 1024f32, 1024f32),
+        ),
+        Vector3::new(100f32, 100f32, 100f32),
+        Vector3::new(1f32, 1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    // 两个共享x=10面的盒子
+    tree.add(
+        1,
+        Aabb::new(Point3::new(0.0, 0.0, 0.0), Point3::new(10.0, 10.0, 10.0)),
+        1,
+    );
+    tree.add(
+        2,
+        Aabb::new(Point3::new(10.0, 0.0, 0.0), Point3::new(20.0, 10.0, 10.0)),
+        2,
+    );
+    for i in 10..300 {
+        let p = Point3::new((i % 20) as f32 * 20.0 + 50.0, (i / 20) as f32 * 20.0, 5.0);
+        tree.add(i, Aabb::new(p, p + Vector3::new(2.0, 2.0, 2.0)), i);
+    }
+    tree.collect();
+    let query = |p: Point3<f32>| {
+        let mut args: PointQueryArgs<usize, usize> = PointQueryArgs::new(p);
+        tree.query(&p, contains_point, &mut args, point_ab_query_func);
+        let mut ids: Vec<usize> = args.result.iter().map(|r| r.0).collect();
+        ids.sort();
+        ids
+    };
+    assert_eq!(query(Point3::new(10.0, 5.0, 5.0)), [2]);
+    assert_eq!(query(Point3::new(9.9, 5.0, 5.0)), [1]);
+    assert_eq!(query(Point3::new(5.0, 5.0, 10.0)), Vec::<usize>::new());
+    assert_eq!(query(Point3::new(51.0, 21.0, 6.0)), [20]);
+}
+
+#[test]
+fn test_ab_query_contains() {
+    let mut tree: OctTree<usize, usize> = OctTree::new(
+        Aabb::new(
+            Point3::new(-1024f32, -1024f32, -1024f32),
+            Point3::new(1024f32, 1024f32, 1024f32),
+        ),
+        Vector3::new(100f32, 100f32, 100f32),
+        Vector3::new(1f32, 1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let ab =
+        |min: f32, max: f32| Aabb::new(Point3::new(min, 0.0, 0.0), Point3::new(max, 10.0, 10.0));
+    tree.add(1, ab(10.0, 20.0), 1);
+    tree.add(2, ab(90.0, 120.0), 2);
+    tree.add(3, ab(200.0, 220.0), 3);
+    let marquee = Aabb::new(
+        Point3::new(0.0, -5.0, -5.0),
+        Point3::new(100.0, 100.0, 100.0),
+    );
+    let mut args: AbQueryArgs<usize, usize> = AbQueryArgs::new(marquee);
+    tree.query(&marquee, intersects, &mut args, ab_query_func);
+    args.result.sort();
+    assert_eq!(args.result, [(1, 1), (2, 2)]);
+    let mut args: AbQueryArgs<usize, usize> = AbQueryArgs::new(marquee);
+    tree.query(&marquee, intersects, &mut args, ab_query_contains_func);
+    assert_eq!(args.result, [(1, 1)]);
+}
+
+#[test]
+fn test_segment_query() {
+    let mut tree: OctTree<usize, usize> = OctTree::new(
+        Aabb::new(
+            Point3::new(-1024f32, -1024f32, -1024f32),
+            Point3::new(1024f32, 1024f32, 1024f32),
+        ),
+        Vector3::new(100f32, 100f32, 100f32),
+        Vector3::new(1f32, 1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    // 5x5x5的盒子网格，盒子大小10，间隔20，倒序添加
+    for id in (0..125).rev() {
+        let p = Point3::new(
+            (id % 5) as f32 * 20.0,
+            (id / 5 % 5) as f32 * 20.0,
+            (id / 25) as f32 * 20.0,
+        );
+        tree.add(id, Aabb::new(p, p + Vector3::new(10f32, 10f32, 10f32)), id);
+    }
+    tree.collect();
+    // y = 3 + x / 2, z = 1 + x / 2，只穿过(0, 0, 0)、(2, 1, 1)、(4, 2, 2)的盒子
+    let segment = Segment::new(
+        Point3::new(0f32, 3f32, 1f32),
+        Point3::new(100f32, 53f32, 51f32),
+    );
+    let mut args = SegmentQueryArgs::new(segment.clone());
+    tree.query(
+        &segment,
+        segment_branch_func,
+        &mut args,
+        segment_ab_query_func,
+    );
+    args.sort_by_t();
+    assert_eq!(args.result, vec![(0, 0, 0.0), (32, 32, 0.4), (64, 64, 0.8)]);
+
+    let b = Aabb::new(
+        Point3::new(20f32, 0f32, 0f32),
+        Point3::new(30f32, 10f32, 10f32),
+    );
+    assert!(!segment_intersects_aabb(&segment.p0, &segment.p1, &b));
+    assert!(segment_intersects_aabb(
+        &Point3::new(25f32, 5f32, -5f32),
+        &Point3::new(25f32, 5f32, 15f32),
+        &b
+    ));
+}
+
+#[test]
+fn test_capsule_query() {
+    let mut tree: OctTree<usize, usize> = OctTree::new(
+        Aabb::new(
+            Point3::new(-1024f32, -1024f32, -1024f32),
+            Point3::new(1024f32, 1024f32, 1024f32),
+        ),
+        Vector3::new(100f32, 100f32, 100f32),
+        Vector3::new(1f32, 1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    // 5x5x5的盒子网格，盒子大小10，间隔20
+    for id in 0..125 {
+        let p = Point3::new(
+            (id % 5) as f32 * 20.0,
+            (id / 5 % 5) as f32 * 20.0,
+            (id / 25) as f32 * 20.0,
+        );
+        tree.add(id, Aabb::new(p, p + Vector3::new(10f32, 10f32, 10f32)), id);
+    }
+    // 单独的盒子
+    let b = Aabb::new(
+        Point3::new(500f32, 500f32, 500f32),
+        Point3::new(510f32, 510f32, 510f32),
+    );
+    tree.add(1000, b, 1000);
+    tree.collect();
+    let query = |capsule: Capsule| {
+        let mut args = CapsuleQueryArgs::new(capsule.clone());
+        tree.query(
+            &capsule,
+            capsule_branch_func,
+            &mut args,
+            capsule_ab_query_func,
+        );
+        let mut ids: Vec<usize> = args.result.iter().map(|r| r.0).collect();
+        ids.sort();
+        ids
+    };
+
+    // 线段离单独盒子的角(510, 510, 510)最近，最近点为(512, 513, 516)，距离正好为7
+    let p0 = Point3::new(509f32, 515f32, 516f32);
+    let p1 = Point3::new(515f32, 511f32, 516f32);
+    assert_eq!(segment_aabb_distance_sq(&p0, &p1, &b), 49.0);
+    assert_eq!(query(Capsule::new(p0, p1, 7.0)), vec![1000]);
+    assert!(query(Capsule::new(p0, p1, 6.99)).is_empty());
+
+    // 穿过盒子的线段距离为0，和brute force比较
+    let capsule = Capsule::new(
+        Point3::new(-5f32, 5f32, 5f32),
+        Point3::new(95f32, 45f32, 85f32),
+        4.0,
+    );
+    let expected: Vec<usize> = (0..125)
+        .filter(|id| capsule_intersects_aabb(&capsule, &tree.get(*id).unwrap().0))
+        .collect();
+    assert!(expected.contains(&0));
+    assert_eq!(query(capsule), expected);
+}
+
+#[test]
+fn test_slotmap_key() {
+    use pi_slotmap::{DefaultKey, SlotMap};
+
+    let mut tree: OctTree<DefaultKey, usize> = OctTree::new(
+        Aabb::new(
+            Point3::new(-1024f32, -1024f32, -1024f32),
+            Point3::new(1024f32, 1024f32, 1024f32),
+        ),
+        Vector3::new(100f32, 100f32, 100f32),
+        Vector3::new(1f32, 1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut keys: SlotMap<DefaultKey, usize> = SlotMap::new();
+    let mut ids = Vec::new();
+    for i in 0..100 {
+        let id = keys.insert(i);
+        let p = Point3::new(i as f32 * 10.0, 0.0, 0.0);
+        tree.add(id, Aabb::new(p, p + Vector3::new(5f32, 5f32, 5f32)), i);
+        ids.push(id);
+    }
+    tree.collect();
+    let region = Aabb::new(
+        Point3::new(96f32, -1f32, -1f32),
+        Point3::new(205f32, 1f32, 1f32),
+    );
+    let mut args: AbQueryArgs<DefaultKey, usize> = AbQueryArgs::new(region);
+    tree.query(&region, intersects, &mut args, ab_query_func);
+    args.result.sort_by_key(|r| r.1);
+    assert_eq!(
+        args.result,
+        (10..21).map(|i| (ids[i], i)).collect::<Vec<_>>()
+    );
+
+    let p = Point3::new(52f32, 2f32, 2f32);
+    let mut args: PointQueryArgs<DefaultKey, usize> = PointQueryArgs::new(p);
+    tree.query(&p, contains_point, &mut args, point_ab_query_func);
+    assert_eq!(args.result, vec![(ids[5], 5)]);
+}
+
+#[test]
+fn test_distance_query() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: OctTree<usize, usize> = OctTree::new(
+        Aabb::new(
+            Point3::new(0f32, 0f32, 0f32),
+            Point3::new(1000f32, 1000f32, 1000f32),
+        ),
+        Vector3::new(100f32, 100f32, 100f32),
+        Vector3::new(1f32, 1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(10422);
+    for i in 0..1000 {
+        let p = Point3::new(
+            rng.gen_range(0f32..980f32),
+            rng.gen_range(0f32..980f32),
+            rng.gen_range(0f32..980f32),
+        );
+        let s = rng.gen_range(1f32..20f32);
+        tree.add(i, Aabb::new(p, p + Vector3::new(s, s, s)), i);
+    }
+    tree.collect();
+    let region = Aabb::new(
+        Point3::new(200f32, 300f32, 200f32),
+        Point3::new(500f32, 600f32, 500f32),
+    );
+    let point = Point3::new(350f32, 450f32, 350f32);
+    let mut args = DistanceQueryArgs::new(region, point);
+    tree.query(&region, intersects, &mut args, distance_ab_query_func);
+    assert!(args.result.len() > 10);
+    assert!(args.result.windows(2).all(|w| w[0].1 <= w[1].1));
+    // 和先查询再排序的结果一致
+    let mut expected: Vec<(usize, f32)> = tree
+        .query_collect(&region)
+        .iter()
+        .map(|(id, ab, _)| (*id, OctHelper::<Real>::aabb_point_distance_sq(ab, &point)))
+        .collect();
+    expected.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+    let mut ids: Vec<usize> = args.result.iter().map(|r| r.0).collect();
+    ids.sort();
+    let mut expected_ids: Vec<usize> = expected.iter().map(|r| r.0).collect();
+    expected_ids.sort();
+    assert_eq!(ids, expected_ids);
+    assert!(args
+        .result
+        .iter()
+        .zip(expected.iter())
+        .all(|(r, e)| r.1 == e.1 && r.2 == r.0));
+}
+
+#[test]
+fn test_f64() {
+    let mut tree: OctTreeF64<usize, usize> = OctTreeF64::new(
+        Aabb64::new(
+            Point3::new(-1e7f64, -1e7f64, -1e7f64),
+            Point3::new(1e7f64, 1e7f64, 1e7f64),
+        ),
+        Vector3::new(1e5f64, 1e5f64, 1e5f64),
+        Vector3::new(0.01f64, 0.01f64, 0.01f64),
+        0,
+        0,
+        0,
+    );
+    // 远离原点处间隔0.01的小节点，f32无法区分
+    let base = 9_000_000f64;
+    for i in 0..100 {
+        let x = base + i as f64 * 0.01;
+        tree.add(
+            i,
+            Aabb64::new(
+                Point3::new(x, -base, base),
+                Point3::new(x + 0.005, -base + 0.005, base + 0.005),
+            ),
+            i,
+        );
+    }
+    tree.collect();
+    assert!(tree.slab.len() > 1);
+    assert_eq!(tree.validate(), Ok(()));
+    let r = tree.k_nearest(&Point3::new(base + 0.5001, -base, base), 1);
+    assert_eq!(r[0].0, 50);
+    fn ab_func(arg: &mut (Aabb64, Vec<usize>), id: usize, aabb: &Aabb64, _: &usize) {
+        if intersects_f64(&arg.0, aabb) {
+            arg.1.push(id);
+        }
+    }
+    let aabb = Aabb64::new(
+        Point3::new(base + 0.097, -base, base),
+        Point3::new(base + 0.197, -base + 0.005, base + 0.005),
+    );
+    let mut args = (aabb, Vec::new());
+    tree.query(&aabb, intersects_f64, &mut args, ab_func);
+    args.1.sort();
+    assert_eq!(args.1, (10..20).collect::<Vec<usize>>());
+}
+
+#[test]
+fn test_intersects_closed() {
+    let a = Aabb::new(
+        Point3::new(0f32, 0f32, 0f32),
+        Point3::new(10f32, 10f32, 10f32),
+    );
+    // 共享z=10的面
+    let b = Aabb::new(
+        Point3::new(0f32, 0f32, 10f32),
+        Point3::new(10f32, 10f32, 20f32),
+    );
+    assert!(!intersects(&a, &b));
+    assert!(intersects_closed(&a, &b));
+    assert!(intersects_closed(&b, &a));
+    let d = Aabb::new(
+        Point3::new(0f32, 0f32, 10.5f32),
+        Point3::new(10f32, 10f32, 20f32),
+    );
+    assert!(!intersects_closed(&a, &d));
+}
+
+#[test]
+fn test_query_max_payload() {
+    // web的OctTree的用法：payload为id转成的f64，query_max限制结果数量
+    use pi_slotmap::{DefaultKey, KeyData, SlotMap};
+
+    let mut keys: SlotMap<DefaultKey, ()> = SlotMap::new();
+    let mut tree: OctTree<DefaultKey, f64> = OctTree::new(
+        Aabb::new(
+            Point3::new(-1024f32, -1024f32, -1024f32),
+            Point3::new(3072f32, 3072f32, 3072f32),
+        ),
+        Vector3::new(100f32, 100f32, 100f32),
+        Vector3::new(1f32, 1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut add = |tree: &mut OctTree<DefaultKey, f64>, mins: Point3<f32>, maxs: Point3<f32>| {
+        let id = keys.insert(());
+        let payload = id.data().as_ffi() as f64;
+        tree.add(id, Aabb::new(mins, maxs), payload);
+        payload
+    };
+    let id1 = add(
+        &mut tree,
+        Point3::new(-50f32, -50f32, -50f32),
+        Point3::new(50f32, -10f32, 50f32),
+    );
+    let id2 = add(
+        &mut tree,
+        Point3::new(10f32, -50f32, 10f32),
+        Point3::new(50f32, -10f32, 50f32),
+    );
+    let point = Aabb::new(
+        Point3::new(1f32, -20f32, 1f32),
+        Point3::new(1f32, -20f32, 1f32),
+    );
+    let mut args = AbQueryArgs::with_max_results(point, 423);
+    tree.query(&point, intersects, &mut args, ab_query_func);
+    assert_eq!(args.result.len(), 1);
+    assert_eq!(args.result[0].1, id1);
+    // payload可以转回id
+    let key = DefaultKey::from(KeyData::from_ffi(args.result[0].1 as u64));
+    assert_eq!(args.result[0].0, key);
+    // 两个节点都相交，结果数不超过上限
+    let region = Aabb::new(
+        Point3::new(20f32, -20f32, 20f32),
+        Point3::new(20f32, -20f32, 20f32),
+    );
+    let mut args = AbQueryArgs::with_max_results(region, 1);
+    tree.query(&region, intersects, &mut args, ab_query_func);
+    assert_eq!(args.result.len(), 1);
+    assert!(args.truncated);
+    let mut args = AbQueryArgs::with_max_results(region, 0);
+    tree.query(&region, intersects, &mut args, ab_query_func);
+    assert!(args.result.is_empty());
+    assert!(tree
+        .remove(DefaultKey::from(KeyData::from_ffi(id2 as u64)))
+        .is_some());
+}
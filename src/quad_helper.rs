@@ -1,190 +1,251 @@
 //! 四叉相关接口
 
-use std::fmt;
-use std::mem;
+use ::core::cmp::Ordering;
+#[cfg(not(feature = "std"))]
+use alloc::vec::Vec;
+use ::core::fmt;
+use ::core::marker::PhantomData;
+use ::core::mem;
 
 use nalgebra::*;
 use parry2d::{bounding_volume::*, math::Real};
+use parry2d_f64::bounding_volume::{Aabb as Aabb64, BoundingVolume as _};
 use num_traits::{FromPrimitive, One, Zero, AsPrimitive};
 use pi_slotmap::Key;
 
 use crate::tree::{Helper, Tree};
 
 /// 四叉树
-pub type QuadTree<K, T> = Tree<K, QuadHelper, T, 4>;
+pub type QuadTree<K, T> = Tree<K, QuadHelper<f32>, T, 4>;
+/// 双精度的四叉树
+pub type QuadTreeF64<K, T> = Tree<K, QuadHelper<f64>, T, 4>;
 
 #[derive(Debug, Clone)]
-pub struct QuadHelper();
-
-impl Helper<4> for QuadHelper {
-    type Point = Point2<Real>;
-    type Vector = Vector2<Real>;
-    type Aabb = Aabb;
-
-    /// 获得AABB的差
-    fn aabb_extents(aabb: &Aabb) -> Vector2<Real> {
-        aabb.extents()
-    }
-    /// 移动AABB
-    fn aabb_shift(aabb: &Aabb, distance: &Vector2<Real>) -> Aabb {
-        Aabb::new(aabb.mins + distance, aabb.maxs + distance)
-    }
-    /// 判断指定的aabb是否包含另一个aabb
-    fn aabb_contains(aabb: &Aabb, other: &Aabb) -> bool {
-        aabb.contains(other)
-    }
-    /// 判断2个aabb是否相交
-    fn aabb_intersects(aabb: &Aabb, other: &Aabb) -> bool {
-        aabb.intersects(other)
-    }
-    /// 计算四叉树的深度
-    fn get_deap(
-        d: &mut Vector2<Real>,
-        loose_layer: usize,
-        max_loose: &Vector2<Real>,
-        deep: usize,
-        min_loose: &Vector2<Real>,
-    ) -> usize {
-        let two = Real::one() + Real::one();
-        let x = ComplexField::powf(
-            (max_loose.x / d.x + Real::one()) / two,
-            FromPrimitive::from_usize(loose_layer).unwrap(),
-        );
-        let y = ComplexField::powf(
-            (max_loose.y / d.y + Real::one()) / two,
-            FromPrimitive::from_usize(loose_layer).unwrap(),
-        );
-        d.x *= x;
-        d.y *= y;
-        let deep = if loose_layer < deep {
-            // 高于该层的节点，松散值都是用最小值， 也可计算其下每层的八叉节点的大小
-            // 八叉节点的大小如果小于最小松散值的2倍， 应该停止向下划分， 因为最小松散值占据了八叉节点的大部分
-            // 最大层由设置值和该停止划分的层的最小值
-            let mut calc_deep = loose_layer;
-            let min = min_loose * two;
-            while calc_deep < deep && d.x >= min.x && d.y >= min.y {
-                *d = (*d + min_loose) / two;
-                calc_deep += 1;
+pub struct QuadHelper<S = Real>(PhantomData<S>);
+
+// 为指定精度的标量生成Helper的实现，f32使用parry2d的Aabb，f64使用parry2d-f64的Aabb
+macro_rules! impl_quad_helper {
+    ($s:ident, $aabb:ident) => {
+        impl Helper<4> for QuadHelper<$s> {
+            type Point = Point2<$s>;
+            type Vector = Vector2<$s>;
+            type Aabb = $aabb;
+            type Scalar = $s;
+
+            /// 获得AABB的差
+            fn aabb_extents(aabb: &$aabb) -> Vector2<$s> {
+                aabb.extents()
+            }
+            /// 移动AABB
+            fn aabb_shift(aabb: &$aabb, distance: &Vector2<$s>) -> $aabb {
+                $aabb::new(aabb.mins + distance, aabb.maxs + distance)
+            }
+            /// 判断指定的aabb是否包含另一个aabb
+            fn aabb_contains(aabb: &$aabb, other: &$aabb) -> bool {
+                aabb.contains(other)
+            }
+            /// 判断2个aabb是否相交
+            fn aabb_intersects(aabb: &$aabb, other: &$aabb) -> bool {
+                aabb.intersects(other)
+            }
+            /// 计算点到aabb的最近距离的平方，点在aabb内则为0
+            fn aabb_point_distance_sq(aabb: &$aabb, point: &Point2<$s>) -> $s {
+                (aabb.mins - point)
+                    .sup(&(point - aabb.maxs))
+                    .sup(&Vector2::zeros())
+                    .norm_squared()
+            }
+            /// 计算点到aabb的最远距离的平方
+            fn aabb_point_max_distance_sq(aabb: &$aabb, point: &Point2<$s>) -> $s {
+                (point - aabb.mins)
+                    .abs()
+                    .sup(&(point - aabb.maxs).abs())
+                    .norm_squared()
+            }
+            /// 计算点到aabb表面的有符号距离，点在aabb内则为负
+            fn aabb_point_signed_distance(aabb: &$aabb, point: &Point2<$s>) -> $s {
+                let d = (aabb.mins - point).sup(&(point - aabb.maxs));
+                d.sup(&Vector2::zeros()).norm() + d.max().min(0.0)
+            }
+            /// 计算点到aabb中心的距离的平方
+            fn aabb_center_distance_sq(aabb: &$aabb, point: &Point2<$s>) -> $s {
+                (aabb.center() - point).norm_squared()
+            }
+            /// 获得aabb的中心点
+            fn aabb_center(aabb: &$aabb) -> Point2<$s> {
+                aabb.center()
+            }
+            /// 获得包含2个aabb的最小aabb
+            fn aabb_merged(aabb: &$aabb, other: &$aabb) -> $aabb {
+                aabb.merged(other)
+            }
+            /// 判断aabb是否有效，所有坐标都是有限值，并且每个轴上min不大于max
+            fn aabb_is_valid(aabb: &$aabb) -> bool {
+                aabb.mins
+                    .iter()
+                    .zip(aabb.maxs.iter())
+                    .all(|(min, max)| min.is_finite() && max.is_finite() && min <= max)
+            }
+            /// 计算2个点的距离的平方
+            fn point_distance_sq(a: &Point2<$s>, b: &Point2<$s>) -> $s {
+                (a - b).norm_squared()
+            }
+            /// 在2个点之间插值，t为0时返回a，t为1时返回b
+            fn point_lerp(a: &Point2<$s>, b: &Point2<$s>, t: $s) -> Point2<$s> {
+                a + (b - a) * t
+            }
+            /// 计算四叉树的深度
+            fn get_deap(
+                d: &mut Vector2<$s>,
+                loose_layer: usize,
+                max_loose: &Vector2<$s>,
+                deep: usize,
+                min_loose: &Vector2<$s>,
+            ) -> usize {
+                let two = $s::one() + $s::one();
+                let x = ComplexField::powf(
+                    (max_loose.x / d.x + $s::one()) / two,
+                    FromPrimitive::from_usize(loose_layer).unwrap(),
+                );
+                let y = ComplexField::powf(
+                    (max_loose.y / d.y + $s::one()) / two,
+                    FromPrimitive::from_usize(loose_layer).unwrap(),
+                );
+                d.x *= x;
+                d.y *= y;
+                let deep = if loose_layer < deep {
+                    // 高于该层的节点，松散值都是用最小值， 也可计算其下每层的八叉节点的大小
+                    // 八叉节点的大小如果小于最小松散值的2倍， 应该停止向下划分， 因为最小松散值占据了八叉节点的大部分
+                    // 最大层由设置值和该停止划分的层的最小值
+                    let mut calc_deep = loose_layer;
+                    let min = min_loose * two;
+                    while calc_deep < deep && d.x >= min.x && d.y >= min.y {
+                        *d = (*d + min_loose) / two;
+                        calc_deep += 1;
+                    }
+                    calc_deep
+                } else {
+                    deep
+                };
+                deep
             }
-            calc_deep
-        } else {
-            deep
-        };
-        deep
-    }
 
-    #[inline]
-    /// 判定指定向量是否小于最小“松散”尺寸
-    fn smaller_than_min_loose(d: &Vector2<Real>, min_loose: &Vector2<Real>) -> bool {
-        if d.x <= min_loose.x && d.y <= min_loose.y {
-            return true;
-        };
-        return false;
-    }
+            #[inline]
+            /// 判定指定向量是否小于最小“松散”尺寸
+            fn smaller_than_min_loose(d: &Vector2<$s>, min_loose: &Vector2<$s>) -> bool {
+                if d.x <= min_loose.x && d.y <= min_loose.y {
+                    return true;
+                };
+                return false;
+            }
 
-    #[inline]
-    /// 指定向量以及最大松散尺寸计算对应的层
-    fn calc_layer(loose: &Vector2<Real>, el: &Vector2<Real>) -> usize {
-        let x = if el.x == Real::zero() {
-            usize::max_value()
-        } else {
-            (loose.x / el.x).as_()
-        };
-        let y = if el.y == Real::zero() {
-            usize::max_value()
-        } else {
-            (loose.y / el.y).as_()
-        };
+            #[inline]
+            /// 指定向量以及最大松散尺寸计算对应的层
+            fn calc_layer(loose: &Vector2<$s>, el: &Vector2<$s>) -> usize {
+                let x = if el.x == $s::zero() {
+                    usize::max_value()
+                } else {
+                    (loose.x / el.x).as_()
+                };
+                let y = if el.y == $s::zero() {
+                    usize::max_value()
+                } else {
+                    (loose.y / el.y).as_()
+                };
 
-        let min = x.min(y);
-        if min == 0 {
-            return 0;
-        }
-        (mem::size_of::<usize>() << 3) - (min.leading_zeros() as usize) - 1
-    }
+                let min = x.min(y);
+                if min == 0 {
+                    return 0;
+                }
+                (mem::size_of::<usize>() << 3) - (min.leading_zeros() as usize) - 1
+            }
 
-    #[inline]
-    /// 判断所在的子节点
-    fn get_child(point: &Point2<Real>, aabb: &Aabb) -> u8 {
-        let mut i = 0;
-        if aabb.maxs.x > point.x {
-            i += 1;
-        }
-        if aabb.maxs.y > point.y {
-            i += 2;
-        }
-        i
-    }
-
-    #[inline]
-    fn get_max_half_loose(aabb: &Aabb, loose: &Vector2<Real>) -> Point2<Real> {
-        let two = Real::one() + Real::one();
-        let x = (aabb.mins.x + aabb.maxs.x + loose.x) / two;
-        let y = (aabb.mins.y + aabb.maxs.y + loose.y) / two;
-        Point2::new(x, y)
-    }
-
-    /// 创建ab的子节点集合
-    fn make_childs(aabb: &Aabb, loose: &Vector2<Real>) -> [Aabb; 4] {
-        let two = Real::one() + Real::one();
-        let x = (aabb.mins.x + aabb.maxs.x - loose.x) / two;
-        let y = (aabb.mins.y + aabb.maxs.y - loose.y) / two;
-        let p1 = Point2::new(x, y);
-        let p2 = Self::get_max_half_loose(&aabb, &loose);
-	[
-            Aabb::new(aabb.mins, p2),
-            Aabb::new(
-                Point2::new(p1.x, aabb.mins.y),
-                Point2::new(aabb.maxs.x, p2.y),
-            ),
-            Aabb::new(
-                Point2::new(aabb.mins.x, p1.y),
-                Point2::new(p2.x, aabb.maxs.y),
-            ),
-            Aabb::new(p1, aabb.maxs),
-        ]
-    }
+            #[inline]
+            /// 判断所在的子节点
+            fn get_child(point: &Point2<$s>, aabb: &$aabb) -> u8 {
+                let mut i = 0;
+                if aabb.maxs.x > point.x {
+                    i += 1;
+                }
+                if aabb.maxs.y > point.y {
+                    i += 2;
+                }
+                i
+            }
 
-    /// 指定创建ab的子节点
-    fn create_child(
-        aabb: &Aabb,
-        loose: &Vector2<Real>,
-        layer: usize,
-        loose_layer: usize,
-        min_loose: &Vector2<Real>,
-        index: u8,
-    ) -> (Aabb, Vector2<Real>) {
-        let two = Real::one() + Real::one();
-        macro_rules! c1 {
-            ($c:ident) => {
-                (aabb.mins.$c + aabb.maxs.$c - loose.$c) / two
-            };
-        }
-        macro_rules! c2 {
-            ($c:ident) => {
-                (aabb.mins.$c + aabb.maxs.$c + loose.$c) / two
-            };
+            #[inline]
+            fn get_max_half_loose(aabb: &$aabb, loose: &Vector2<$s>) -> Point2<$s> {
+                let two = $s::one() + $s::one();
+                let x = (aabb.mins.x + aabb.maxs.x + loose.x) / two;
+                let y = (aabb.mins.y + aabb.maxs.y + loose.y) / two;
+                Point2::new(x, y)
+            }
+
+            /// 创建ab的子节点集合
+            fn make_childs(aabb: &$aabb, loose: &Vector2<$s>) -> [$aabb; 4] {
+                let two = $s::one() + $s::one();
+                let x = (aabb.mins.x + aabb.maxs.x - loose.x) / two;
+                let y = (aabb.mins.y + aabb.maxs.y - loose.y) / two;
+                let p1 = Point2::new(x, y);
+                let p2 = Self::get_max_half_loose(&aabb, &loose);
+                [
+                    $aabb::new(aabb.mins, p2),
+                    $aabb::new(
+                        Point2::new(p1.x, aabb.mins.y),
+                        Point2::new(aabb.maxs.x, p2.y),
+                    ),
+                    $aabb::new(
+                        Point2::new(aabb.mins.x, p1.y),
+                        Point2::new(p2.x, aabb.maxs.y),
+                    ),
+                    $aabb::new(p1, aabb.maxs),
+                ]
+            }
+
+            /// 指定创建ab的子节点
+            fn create_child(
+                aabb: &$aabb,
+                loose: &Vector2<$s>,
+                layer: usize,
+                loose_layer: usize,
+                min_loose: &Vector2<$s>,
+                index: u8,
+            ) -> ($aabb, Vector2<$s>) {
+                let two = $s::one() + $s::one();
+                macro_rules! c1 {
+                    ($c:ident) => {
+                        (aabb.mins.$c + aabb.maxs.$c - loose.$c) / two
+                    };
+                }
+                macro_rules! c2 {
+                    ($c:ident) => {
+                        (aabb.mins.$c + aabb.maxs.$c + loose.$c) / two
+                    };
+                }
+                let a = match index {
+                    0 => $aabb::new(aabb.mins, Point2::new(c2!(x), c2!(y))),
+                    1 => $aabb::new(
+                        Point2::new(c1!(x), aabb.mins.y),
+                        Point2::new(aabb.maxs.x, c2!(y)),
+                    ),
+                    2 => $aabb::new(
+                        Point2::new(aabb.mins.x, c1!(y)),
+                        Point2::new(c2!(x), aabb.maxs.y),
+                    ),
+                    _ => $aabb::new(Point2::new(c1!(x), c1!(y)), aabb.maxs),
+                };
+                let loose = if layer < loose_layer {
+                    loose / two
+                } else {
+                    min_loose.clone()
+                };
+                (a, loose)
+            }
         }
-        let a = match index {
-            0 => Aabb::new(aabb.mins, Point2::new(c2!(x), c2!(y))),
-            1 => Aabb::new(
-                Point2::new(c1!(x), aabb.mins.y),
-                Point2::new(aabb.maxs.x, c2!(y)),
-            ),
-            2 => Aabb::new(
-                Point2::new(aabb.mins.x, c1!(y)),
-                Point2::new(c2!(x), aabb.maxs.y),
-            ),
-            _ => Aabb::new(Point2::new(c1!(x), c1!(y)), aabb.maxs),
-        };
-        let loose = if layer < loose_layer {
-            loose / two
-        } else {
-            min_loose.clone()
-        };
-        (a, loose)
-    }
+    };
 }
+impl_quad_helper!(f32, Aabb);
+impl_quad_helper!(f64, Aabb64);
 
 
 /// quad节点查询函数的范本，aabb是否相交，参数a是查询参数，参数b是quad节点的aabb， 所以最常用的判断是左闭右开
@@ -197,6 +258,16 @@ pub fn intersects(a: &Aabb, b: &Aabb) -> bool {
         && a.maxs.y > b.mins.y
 }
 
+/// quad节点查询函数的范本，aabb是否相交，两边都是闭区间，边界接触也算相交
+/// + 需要和物理引擎的接触生成保持一致（接触的物体算碰撞）时使用，其余情况应该使用左闭右开的intersects
+#[inline]
+pub fn intersects_closed(a: &Aabb, b: &Aabb) -> bool {
+    a.mins.x <= b.maxs.x
+        && a.maxs.x >= b.mins.x
+        && a.mins.y <= b.maxs.y
+        && a.maxs.y >= b.mins.y
+}
+
 /// aabb的查询函数的参数
 pub struct AbQueryArgs<K: Key, T: Clone + PartialOrd> {
     pub aabb: Aabb,
@@ -227,6 +298,410 @@ pub fn ab_query_func<K: Key, T: Clone + PartialOrd + fmt::Debug>(
     }
 }
 
+/// ab节点的包含查询函数，只有aabb被查询范围完全包含的节点才会被接受，比如框选
+/// + 注意：quad节点的查询函数仍然应该使用相交判断intersects，被完全包含的节点可能在和查询范围相交的quad节点中
+pub fn ab_query_contains_func<K: Key, T: Clone + PartialOrd + fmt::Debug>(
+    arg: &mut AbQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    if arg.aabb.contains(aabb) && bind > &arg.result.1 {
+        arg.result.0 = id;
+        arg.result.1 = bind.clone();
+    }
+}
+
+/// 扇形视野，apex为顶点，dir为朝向，half_angle为半张角（弧度），range为视野距离
+#[derive(Debug, Clone)]
+pub struct Cone {
+    pub apex: Point2<Real>,
+    pub dir: Vector2<Real>,
+    pub half_angle: Real,
+    pub range: Real,
+}
+impl Cone {
+    pub fn new(apex: Point2<Real>, dir: Vector2<Real>, half_angle: Real, range: Real) -> Self {
+        Cone {
+            apex,
+            dir,
+            half_angle,
+            range,
+        }
+    }
+}
+
+/// 扇形视野和aabb是否相交，既可以作为quad节点的查询函数，也可以作为ab节点的精确判断
+/// + 顶点后方（超出半张角）及超出视野距离的aabb不相交
+pub fn cone_intersects(
+    apex: Point2<Real>,
+    dir: Vector2<Real>,
+    half_angle: Real,
+    range: Real,
+    b: &Aabb,
+) -> bool {
+    // 超出视野距离
+    if <QuadHelper>::aabb_point_distance_sq(b, &apex) > range * range {
+        return false;
+    }
+    // 顶点在aabb内，或者视野是整个圆
+    if b.contains_local_point(&apex) || half_angle >= ::core::f32::consts::PI {
+        return true;
+    }
+    let dir = dir.normalize();
+    let cos = half_angle.cos();
+    let in_cone = |p: Point2<Real>| {
+        let v = p - apex;
+        let len = v.norm();
+        len <= range && v.dot(&dir) >= len * cos
+    };
+    // aabb的顶点在扇形内
+    let corners = [
+        b.mins,
+        Point2::new(b.maxs.x, b.mins.y),
+        b.maxs,
+        Point2::new(b.mins.x, b.maxs.y),
+    ];
+    if corners.iter().any(|p| in_cone(*p)) {
+        return true;
+    }
+    // 扇形的两条边和aabb相交
+    for angle in [half_angle, -half_angle] {
+        let (sin, cos) = angle.sin_cos();
+        let edge = Vector2::new(dir.x * cos - dir.y * sin, dir.x * sin + dir.y * cos) * range;
+        if segment_aabb_entry(apex, edge, b).is_some() {
+            return true;
+        }
+    }
+    // 扇形的弧和aabb的边相交
+    for i in 0..4 {
+        let (a, e) = (corners[i], corners[(i + 1) % 4] - corners[i]);
+        // 解 |a + t*e - apex| = range
+        let f = a - apex;
+        let (qa, qb, qc) = (e.dot(&e), 2.0 * f.dot(&e), f.dot(&f) - range * range);
+        let disc = qb * qb - 4.0 * qa * qc;
+        if qa == 0.0 || disc < 0.0 {
+            continue;
+        }
+        let sqrt = disc.sqrt();
+        for t in [(-qb - sqrt) / (2.0 * qa), (-qb + sqrt) / (2.0 * qa)] {
+            if (0.0..=1.0).contains(&t) {
+                let v = f + e * t;
+                if v.dot(&dir) >= v.norm() * cos {
+                    return true;
+                }
+            }
+        }
+    }
+    false
+}
+
+// 线段(start, start+d)和aabb相交时，返回进入aabb的参数t，t在[0, 1]内，slab算法
+fn segment_aabb_entry(start: Point2<Real>, d: Vector2<Real>, b: &Aabb) -> Option<Real> {
+    let (mut t0, mut t1) = (0.0, 1.0);
+    for i in 0..2 {
+        if d[i] == 0.0 {
+            if start[i] < b.mins[i] || start[i] > b.maxs[i] {
+                return None;
+            }
+        } else {
+            let (mut near, mut far) =
+                ((b.mins[i] - start[i]) / d[i], (b.maxs[i] - start[i]) / d[i]);
+            if near > far {
+                mem::swap(&mut near, &mut far);
+            }
+            t0 = near.max(t0);
+            t1 = far.min(t1);
+            if t0 > t1 {
+                return None;
+            }
+        }
+    }
+    Some(t0)
+}
+
+/// 扇形视野的quad节点查询函数
+pub fn cone_branch_func(cone: &Cone, b: &Aabb) -> bool {
+    cone_intersects(cone.apex, cone.dir, cone.half_angle, cone.range, b)
+}
+
+/// 扇形视野查询函数的参数
+pub struct ConeQueryArgs<K: Key, T> {
+    pub cone: Cone,
+    pub result: Vec<(K, T)>,
+}
+impl<K: Key, T: Clone> ConeQueryArgs<K, T> {
+    pub fn new(cone: Cone) -> ConeQueryArgs<K, T> {
+        ConeQueryArgs {
+            cone,
+            result: Vec::new(),
+        }
+    }
+}
+
+/// 扇形视野的ab节点查询函数，和扇形相交的节点放入结果
+pub fn cone_ab_query_func<K: Key, T: Clone>(
+    arg: &mut ConeQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    if cone_branch_func(&arg.cone, aabb) {
+        arg.result.push((id, bind.clone()));
+    }
+}
+
+/// 折线和aabb是否相交，折线的每段都看作宽度为width的胶囊体，既可以作为quad节点的查询函数，也可以作为ab节点的精确判断
+/// + 只有一个点的折线退化为半径为width/2的圆，没有点则不相交
+pub fn polyline_intersects(points: &[Point2<Real>], width: Real, b: &Aabb) -> bool {
+    let r = width / 2.0;
+    let r2 = r * r;
+    if points.len() == 1 {
+        return <QuadHelper>::aabb_point_distance_sq(b, &points[0]) <= r2;
+    }
+    let corners = [
+        b.mins,
+        Point2::new(b.maxs.x, b.mins.y),
+        b.maxs,
+        Point2::new(b.mins.x, b.maxs.y),
+    ];
+    points.windows(2).any(|w| {
+        let (a, d) = (w[0], w[1] - w[0]);
+        // 不相交时，线段和aabb的最近距离一定在线段的端点或aabb的顶点上取得
+        segment_aabb_entry(a, d, b).is_some()
+            || <QuadHelper>::aabb_point_distance_sq(b, &w[0]) <= r2
+            || <QuadHelper>::aabb_point_distance_sq(b, &w[1]) <= r2
+            || corners
+                .iter()
+                .any(|p| point_segment_distance_sq(p, a, d) <= r2)
+    })
+}
+
+// 点到线段(start, start+d)的距离的平方
+fn point_segment_distance_sq(p: &Point2<Real>, start: Point2<Real>, d: Vector2<Real>) -> Real {
+    let len2 = d.norm_squared();
+    let t = if len2 == 0.0 {
+        0.0
+    } else {
+        ((p - start).dot(&d) / len2).clamp(0.0, 1.0)
+    };
+    (start + d * t - p).norm_squared()
+}
+
+/// 折线路径，每段为宽度width的胶囊体
+#[derive(Debug, Clone)]
+pub struct Polyline {
+    pub points: Vec<Point2<Real>>,
+    pub width: Real,
+}
+impl Polyline {
+    pub fn new(points: Vec<Point2<Real>>, width: Real) -> Self {
+        Polyline { points, width }
+    }
+}
+
+/// 折线路径的quad节点查询函数
+pub fn polyline_branch_func(polyline: &Polyline, b: &Aabb) -> bool {
+    polyline_intersects(&polyline.points, polyline.width, b)
+}
+
+/// 折线路径查询函数的参数
+pub struct PolylineQueryArgs<K: Key, T> {
+    pub polyline: Polyline,
+    pub result: Vec<(K, T)>,
+}
+impl<K: Key, T: Clone> PolylineQueryArgs<K, T> {
+    pub fn new(polyline: Polyline) -> PolylineQueryArgs<K, T> {
+        PolylineQueryArgs {
+            polyline,
+            result: Vec::new(),
+        }
+    }
+}
+
+/// 折线路径的ab节点查询函数，和折线相交的节点放入结果
+pub fn polyline_ab_query_func<K: Key, T: Clone>(
+    arg: &mut PolylineQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    if polyline_branch_func(&arg.polyline, aabb) {
+        arg.result.push((id, bind.clone()));
+    }
+}
+
+/// 点查询的quad节点查询函数，点是否在quad节点的松散aabb内，边界上的点也算在内，保证本层的节点不会漏掉
+#[inline]
+pub fn contains_point(a: &Point2<Real>, b: &Aabb) -> bool {
+    a.x >= b.mins.x && a.x <= b.maxs.x && a.y >= b.mins.y && a.y <= b.maxs.y
+}
+
+/// 点查询函数的参数
+pub struct PointQueryArgs<K: Key, T> {
+    pub point: Point2<Real>,
+    pub result: Vec<(K, T)>,
+}
+impl<K: Key, T: Clone> PointQueryArgs<K, T> {
+    pub fn new(point: Point2<Real>) -> PointQueryArgs<K, T> {
+        PointQueryArgs {
+            point,
+            result: Vec::new(),
+        }
+    }
+}
+
+/// 点查询的ab节点查询函数，aabb包含点的节点放入结果，和intersects一样左闭右开
+pub fn point_ab_query_func<K: Key, T: Clone>(
+    arg: &mut PointQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    let p = &arg.point;
+    if p.x >= aabb.mins.x && p.x < aabb.maxs.x && p.y >= aabb.mins.y && p.y < aabb.maxs.y {
+        arg.result.push((id, bind.clone()));
+    }
+}
+
+/// 线段(p0, p1)和aabb是否相交，边界接触也算相交，可以作为quad节点的查询函数，对松散aabb是保守的
+#[inline]
+pub fn segment_intersects_aabb(p0: &Point2<Real>, p1: &Point2<Real>, b: &Aabb) -> bool {
+    segment_aabb_entry(*p0, p1 - p0, b).is_some()
+}
+
+/// 线段，用于视线及激光等查询
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub p0: Point2<Real>,
+    pub p1: Point2<Real>,
+}
+impl Segment {
+    pub fn new(p0: Point2<Real>, p1: Point2<Real>) -> Self {
+        Segment { p0, p1 }
+    }
+}
+
+/// 线段的quad节点查询函数
+pub fn segment_branch_func(segment: &Segment, b: &Aabb) -> bool {
+    segment_intersects_aabb(&segment.p0, &segment.p1, b)
+}
+
+/// 线段查询函数的参数，结果为(id, 绑定, 进入aabb的参数t)，t为0表示p0在aabb内，t为1表示在p1处进入
+pub struct SegmentQueryArgs<K: Key, T> {
+    pub segment: Segment,
+    pub result: Vec<(K, T, Real)>,
+}
+impl<K: Key, T: Clone> SegmentQueryArgs<K, T> {
+    pub fn new(segment: Segment) -> SegmentQueryArgs<K, T> {
+        SegmentQueryArgs {
+            segment,
+            result: Vec::new(),
+        }
+    }
+    /// 按进入aabb的参数t从近到远排序结果
+    pub fn sort_by_t(&mut self) {
+        self.result
+            .sort_by(|a, b| a.2.partial_cmp(&b.2).unwrap_or(Ordering::Equal));
+    }
+}
+
+/// 线段的ab节点查询函数，和线段相交的节点及进入参数t放入结果
+pub fn segment_ab_query_func<K: Key, T: Clone>(
+    arg: &mut SegmentQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    let s = &arg.segment;
+    if let Some(t) = segment_aabb_entry(s.p0, s.p1 - s.p0, aabb) {
+        arg.result.push((id, bind.clone(), t));
+    }
+}
+
+/// 移动的aabb，从aabb的位置沿delta平移，用于移动触发器的连续碰撞查询
+#[derive(Debug, Clone)]
+pub struct SweptAabb {
+    pub aabb: Aabb,
+    pub delta: Vector2<Real>,
+}
+impl SweptAabb {
+    pub fn new(aabb: Aabb, delta: Vector2<Real>) -> Self {
+        SweptAabb { aabb, delta }
+    }
+}
+
+/// 移动的aabb和aabb相交时，返回开始接触的参数t，t在[0, 1]内
+/// + 将b按移动aabb的半尺寸扩大，再和移动aabb中心的线段判断相交，结果是精确的，比查询起点和终点的合并aabb准确
+pub fn swept_aabb_entry(swept: &SweptAabb, b: &Aabb) -> Option<Real> {
+    let half = swept.aabb.half_extents();
+    let inflated = Aabb::new(b.mins - half, b.maxs + half);
+    segment_aabb_entry(swept.aabb.center(), swept.delta, &inflated)
+}
+
+/// 移动aabb的quad节点查询函数
+pub fn swept_aabb_branch_func(swept: &SweptAabb, b: &Aabb) -> bool {
+    swept_aabb_entry(swept, b).is_some()
+}
+
+/// 移动aabb查询函数的参数，结果为(id, 绑定, 开始接触的参数t)
+pub struct SweptAabbQueryArgs<K: Key, T> {
+    pub swept: SweptAabb,
+    pub result: Vec<(K, T, Real)>,
+}
+impl<K: Key, T: Clone> SweptAabbQueryArgs<K, T> {
+    pub fn new(swept: SweptAabb) -> SweptAabbQueryArgs<K, T> {
+        SweptAabbQueryArgs {
+            swept,
+            result: Vec::new(),
+        }
+    }
+}
+
+/// 移动aabb的ab节点查询函数，移动过程中接触到的节点及开始接触的参数t放入结果
+pub fn swept_aabb_ab_query_func<K: Key, T: Clone>(
+    arg: &mut SweptAabbQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    if let Some(t) = swept_aabb_entry(&arg.swept, aabb) {
+        arg.result.push((id, bind.clone(), t));
+    }
+}
+
+/// 按距离排序的查询函数的参数，结果为(id, aabb到参考点的距离的平方, 绑定)，按距离从近到远排列
+/// + 排序的开销在收集结果时支付，每个结果二分查找插入位置，结果较多时插入的移动开销为O(n^2)
+pub struct DistanceQueryArgs<K: Key, T> {
+    pub aabb: Aabb,
+    pub point: Point2<Real>,
+    pub result: Vec<(K, Real, T)>,
+}
+impl<K: Key, T: Clone> DistanceQueryArgs<K, T> {
+    pub fn new(aabb: Aabb, point: Point2<Real>) -> DistanceQueryArgs<K, T> {
+        DistanceQueryArgs {
+            aabb,
+            point,
+            result: Vec::new(),
+        }
+    }
+}
+
+/// 按距离排序的ab节点查询函数，和范围相交的节点按aabb到参考点的距离插入结果，保持从近到远，距离相同的按查询到的顺序
+/// + quad节点的查询函数使用intersects
+pub fn distance_ab_query_func<K: Key, T: Clone>(
+    arg: &mut DistanceQueryArgs<K, T>,
+    id: K,
+    aabb: &Aabb,
+    bind: &T,
+) {
+    if intersects(&arg.aabb, aabb) {
+        let d = QuadHelper::<Real>::aabb_point_distance_sq(aabb, &arg.point);
+        let index = arg.result.partition_point(|r| r.1 <= d);
+        arg.result.insert(index, (id, d, bind.clone()));
+    }
+}
+
 #[test]
 fn test1() {
 	use pi_slotmap::{SlotMap, DefaultKey};
@@ -1209,4 +1684,3939 @@ pub fn test_overflow() {
     tree.query(&aabb, intersects, &mut v, ab_query_func);
 
     debug_assert_eq!(v.as_slice(), &[1, 2]);
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_collision_pairs() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    let n = 20;
+    for i in 1..n + 1 {
+        tree.add(i, Aabb::new(Point2::new(0.0, 0.0), Point2::new(1.0, 1.0)), i);
+    }
+    tree.collect();
+    fn pair_func(
+        arg: &mut Vec<(usize, usize)>,
+        a: usize,
+        _: &Aabb,
+        _: &usize,
+        b: usize,
+        _: &Aabb,
+        _: &usize,
+    ) {
+        arg.push((a.min(b), a.max(b)));
+    }
+    let mut pairs = Vec::new();
+    tree.collision_pairs(&mut pairs, pair_func);
+    assert_eq!(pairs.len(), n * (n - 1) / 2);
+
+    // 和暴力检查的结果比较，包括outer上的节点
+    let mut rng = Pcg32::seed_from_u64(22222);
+    let mut abs = Vec::new();
+    for i in n + 1..n + 2000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..200f32);
+        let h = rng.gen_range(0.0..200f32);
+        let ab = Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h));
+        tree.add(i, ab, i);
+        abs.push((i, ab));
+    }
+    tree.collect();
+    for i in 1..n + 1 {
+        abs.push((i, Aabb::new(Point2::new(0.0, 0.0), Point2::new(1.0, 1.0))));
+    }
+    let mut expect = Vec::new();
+    for i in 0..abs.len() {
+        for j in i + 1..abs.len() {
+            if abs[i].1.intersects(&abs[j].1) {
+                expect.push((abs[i].0.min(abs[j].0), abs[i].0.max(abs[j].0)));
+            }
+        }
+    }
+    let mut pairs = Vec::new();
+    tree.collision_pairs(&mut pairs, pair_func);
+    pairs.sort();
+    expect.sort();
+    assert_eq!(pairs, expect);
+}
+
+#[test]
+fn test_query_farthest() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    assert!(tree.query_farthest(&Point2::new(0.0, 0.0)).is_none());
+
+    let mut rng = Pcg32::seed_from_u64(33333);
+    let mut abs = Vec::new();
+    for i in 0..2000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..200f32);
+        let h = rng.gen_range(0.0..200f32);
+        let ab = Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h));
+        tree.add(i, ab, i);
+        abs.push(ab);
+    }
+    tree.collect();
+    for _ in 0..100 {
+        let p = Point2::new(
+            rng.gen_range(-2000f32..5000f32),
+            rng.gen_range(-2000f32..5000f32),
+        );
+        let max = abs
+            .iter()
+            .map(|ab| <QuadHelper>::aabb_point_distance_sq(ab, &p))
+            .fold(0.0, Real::max);
+        let (id, d) = tree.query_farthest(&p).unwrap();
+        assert_eq!(d, max);
+        assert_eq!(<QuadHelper>::aabb_point_distance_sq(&abs[id], &p), max);
+    }
+}
+
+#[test]
+fn test_k_nearest() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    assert!(tree.k_nearest(&Point2::new(0.0, 0.0), 3).is_empty());
+
+    let mut rng = Pcg32::seed_from_u64(44444);
+    let mut abs = Vec::new();
+    for i in 0..2000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..200f32);
+        let h = rng.gen_range(0.0..200f32);
+        let ab = Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h));
+        tree.add(i, ab, i);
+        abs.push(ab);
+    }
+    tree.collect();
+    for k in [1, 5, 20] {
+        for _ in 0..50 {
+            let p = Point2::new(
+                rng.gen_range(-2000f32..5000f32),
+                rng.gen_range(-2000f32..5000f32),
+            );
+            let mut expect: Vec<Real> = abs
+                .iter()
+                .map(|ab| <QuadHelper>::aabb_point_distance_sq(ab, &p))
+                .collect();
+            expect.sort_by(|a, b| a.partial_cmp(b).unwrap());
+            expect.truncate(k);
+            let r = tree.k_nearest(&p, k);
+            let ds: Vec<Real> = r.iter().map(|(_, d)| *d).collect();
+            assert_eq!(ds, expect);
+            for (id, d) in r {
+                assert_eq!(<QuadHelper>::aabb_point_distance_sq(&abs[id], &p), d);
+            }
+        }
+    }
+}
+
+#[test]
+fn test_collect_if_beneficial() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    // 子空间列表的数量达到分裂阈值8后，每次添加都会增加脏计数
+    for i in 0..10 {
+        tree.add(
+            i,
+            Aabb::new(Point2::new(0.0, 0.0), Point2::new(1.0, 1.0)),
+            i,
+        );
+    }
+    assert_eq!(tree.slab.len(), 1);
+    assert!(!tree.collect_if_beneficial(4));
+    assert_eq!(tree.slab.len(), 1);
+    assert!(tree.collect_if_beneficial(3));
+    assert!(tree.slab.len() > 1);
+    assert!(!tree.collect_if_beneficial(0));
+}
+
+#[test]
+fn test_query_count() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(55555);
+    for i in 0..5000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..200f32);
+        let h = rng.gen_range(0.0..200f32);
+        tree.add(
+            i,
+            Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h)),
+            i,
+        );
+    }
+    tree.collect();
+    fn ab_func(arg: &mut Vec<usize>, id: usize, _: &Aabb, _: &usize) {
+        arg.push(id);
+    }
+    for _ in 0..50 {
+        let x = rng.gen_range(-2000f32..5000f32);
+        let y = rng.gen_range(-2000f32..5000f32);
+        let aabb = Aabb::new(Point2::new(x, y), Point2::new(x + 500.0, y + 500.0));
+        let mut ids = Vec::new();
+        tree.query(&aabb, intersects, &mut ids, ab_func);
+        // query会返回所有outer上的节点，query_count只计算通过判断的outer节点
+        let mut outer = Vec::new();
+        tree.query_outer(&mut outer, ab_func);
+        let skip = outer
+            .iter()
+            .filter(|id| !intersects(&aabb, &tree.get(**id).unwrap().0))
+            .count();
+        assert_eq!(tree.query_count(&aabb, intersects), ids.len() - skip);
+    }
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_query_stream() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+    use std::sync::mpsc::sync_channel;
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(66666);
+    for i in 0..5000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..200f32);
+        let h = rng.gen_range(0.0..200f32);
+        tree.add(
+            i,
+            Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h)),
+            i,
+        );
+    }
+    tree.collect();
+    fn ab_func(arg: &mut Vec<usize>, id: usize, _: &Aabb, _: &usize) {
+        arg.push(id);
+    }
+    let aabb = Aabb::new(Point2::new(0.0, 0.0), Point2::new(1000.0, 1000.0));
+    let mut expect = Vec::new();
+    tree.query(&aabb, intersects, &mut expect, ab_func);
+    expect.sort();
+
+    let (sender, receiver) = sync_channel(16);
+    let mut ids = std::thread::scope(|s| {
+        let handle = s.spawn(move || receiver.iter().map(|(id, _, _)| id).collect::<Vec<usize>>());
+        assert!(tree.query_stream(&aabb, intersects, &sender));
+        drop(sender);
+        handle.join().unwrap()
+    });
+    ids.sort();
+    assert_eq!(ids, expect);
+
+    // 接收方中途被丢弃，查询应停止
+    let (sender, receiver) = sync_channel(0);
+    std::thread::scope(|s| {
+        s.spawn(move || {
+            for _ in 0..10 {
+                receiver.recv().unwrap();
+            }
+        });
+        assert!(!tree.query_stream(&aabb, intersects, &sender));
+    });
+}
+
+#[cfg(feature = "serde")]
+#[test]
+fn test_serde() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(77777);
+    for i in 0..1000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..200f32);
+        let h = rng.gen_range(0.0..200f32);
+        tree.add(
+            i,
+            Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h)),
+            i,
+        );
+    }
+    tree.collect();
+    for i in 0..100 {
+        tree.remove(i * 3);
+    }
+
+    let s = serde_json::to_string(&tree).unwrap();
+    let tree2: QuadTree<usize, usize> = serde_json::from_str(&s).unwrap();
+    assert_eq!(tree2.len(), tree.len());
+    let keys: Vec<_> = tree.slab.iter().map(|(k, _)| k).collect();
+    let keys2: Vec<_> = tree2.slab.iter().map(|(k, _)| k).collect();
+    assert_eq!(keys, keys2);
+    let outer: Vec<_> = tree.outer.iter(&tree.ab_map).map(|(id, _)| id).collect();
+    let outer2: Vec<_> = tree2.outer.iter(&tree2.ab_map).map(|(id, _)| id).collect();
+    assert_eq!(outer, outer2);
+
+    fn ab_func(arg: &mut Vec<(usize, usize)>, id: usize, _: &Aabb, bind: &usize) {
+        arg.push((id, *bind));
+    }
+    for _ in 0..50 {
+        let x = rng.gen_range(-2000f32..5000f32);
+        let y = rng.gen_range(-2000f32..5000f32);
+        let aabb = Aabb::new(Point2::new(x, y), Point2::new(x + 500.0, y + 500.0));
+        let (mut r, mut r2) = (Vec::new(), Vec::new());
+        tree.query(&aabb, intersects, &mut r, ab_func);
+        tree2.query(&aabb, intersects, &mut r2, ab_func);
+        assert_eq!(r, r2);
+    }
+}
+
+#[test]
+fn test_mem_size() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    let size = tree.mem_size();
+    for i in 0..1000 {
+        let x = (i % 100) as f32 * 30.0;
+        let y = (i / 100) as f32 * 30.0;
+        tree.add(
+            i,
+            Aabb::new(Point2::new(x, y), Point2::new(x + 1.0, y + 1.0)),
+            i,
+        );
+    }
+    tree.collect();
+    assert!(tree.slab.len() > 1);
+    assert!(tree.mem_size() >= size + 1000 * std::mem::size_of::<(Aabb, usize)>());
+}
+
+#[test]
+fn test_clear() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let new_tree = || -> QuadTree<usize, usize> {
+        QuadTree::new(
+            Aabb::new(
+                Point2::new(-1024f32, -1024f32),
+                Point2::new(4096f32, 4096f32),
+            ),
+            Vector2::new(1024f32, 1024f32),
+            Vector2::new(10f32, 10f32),
+            0,
+            0,
+            0,
+        )
+    };
+    let mut rng = Pcg32::seed_from_u64(88888);
+    let mut gen_rect = || {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..200f32);
+        let h = rng.gen_range(0.0..200f32);
+        Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h))
+    };
+    let mut tree = new_tree();
+    for i in 0..2000 {
+        tree.add(i, gen_rect(), i);
+    }
+    tree.collect();
+    let capacity = (tree.slab.capacity(), tree.ab_map.capacity());
+
+    tree.clear();
+    assert_eq!(tree.len(), 0);
+    assert_eq!(tree.slab.len(), 1);
+    assert!(tree.outer.is_empty());
+    assert_eq!((tree.slab.capacity(), tree.ab_map.capacity()), capacity);
+    let all = Aabb::new(
+        Point2::new(-10000f32, -10000f32),
+        Point2::new(10000f32, 10000f32),
+    );
+    fn ab_func(arg: &mut Vec<usize>, id: usize, _: &Aabb, _: &usize) {
+        arg.push(id);
+    }
+    let mut ids = Vec::new();
+    tree.query(&all, intersects, &mut ids, ab_func);
+    assert!(ids.is_empty());
+
+    // 清空后重新添加，和新建的树结果一致
+    let mut fresh = new_tree();
+    for i in 0..2000 {
+        let ab = gen_rect();
+        tree.add(i, ab, i);
+        fresh.add(i, ab, i);
+    }
+    tree.collect();
+    fresh.collect();
+    assert_eq!(tree.slab.len(), fresh.slab.len());
+    for _ in 0..50 {
+        let ab = gen_rect();
+        let (mut r1, mut r2) = (Vec::new(), Vec::new());
+        tree.query(&ab, intersects, &mut r1, ab_func);
+        fresh.query(&ab, intersects, &mut r2, ab_func);
+        r1.sort();
+        r2.sort();
+        assert_eq!(r1, r2);
+    }
+}
+
+#[test]
+fn test_f64() {
+    let mut tree: QuadTreeF64<usize, usize> = QuadTreeF64::new(
+        Aabb64::new(
+            Point2::new(-1_000_000f64, -1_000_000f64),
+            Point2::new(1_000_000f64, 1_000_000f64),
+        ),
+        Vector2::new(1024f64, 1024f64),
+        Vector2::new(0.01f64, 0.01f64),
+        0,
+        0,
+        0,
+    );
+    // 远离原点处间隔0.01的小节点，f32无法区分
+    let base = 500_000f64;
+    for i in 0..100 {
+        let x = base + i as f64 * 0.01;
+        tree.add(
+            i,
+            Aabb64::new(Point2::new(x, base), Point2::new(x + 0.005, base + 0.005)),
+            i,
+        );
+    }
+    tree.collect();
+    assert!(tree.slab.len() > 1);
+    let r = tree.k_nearest(&Point2::new(base + 0.5001, base), 1);
+    assert_eq!(r[0].0, 50);
+    fn ab_func(arg: &mut Vec<usize>, id: usize, aabb: &Aabb64, _: &usize) {
+        if aabb.mins.x >= 500_000.2 && aabb.maxs.x <= 500_000.3 {
+            arg.push(id);
+        }
+    }
+    let aabb = Aabb64::new(
+        Point2::new(base + 0.2, base),
+        Point2::new(base + 0.3, base + 0.005),
+    );
+    let mut ids = Vec::new();
+    tree.query(&aabb, |a, b| a.intersects(b), &mut ids, ab_func);
+    ids.sort();
+    assert_eq!(ids, (20..30).collect::<Vec<usize>>());
+}
+
+#[test]
+fn test_for_each_in_mut() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    // 绑定为血量，按10x10、间隔500的网格摆放，包括超出根空间的节点
+    for i in 0..100 {
+        let x = (i % 10) as f32 * 500.0 - 1500.0;
+        let y = (i / 10) as f32 * 500.0 - 1500.0;
+        tree.add(
+            i,
+            Aabb::new(Point2::new(x, y), Point2::new(x + 10.0, y + 10.0)),
+            100,
+        );
+    }
+    tree.collect();
+    let region = Aabb::new(Point2::new(-1600.0, -1600.0), Point2::new(0.0, 0.0));
+    let mut count = 0;
+    tree.for_each_in_mut(&region, |_, _, hp| {
+        *hp -= 30;
+        count += 1;
+    });
+    assert_eq!(count, 16);
+    for i in 0..100 {
+        let (aabb, hp) = tree.get(i).unwrap();
+        if region.intersects(aabb) {
+            assert_eq!(*hp, 70);
+        } else {
+            assert_eq!(*hp, 100);
+        }
+    }
+}
+
+#[test]
+fn test_add_batch() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let new_tree = || -> QuadTree<usize, usize> {
+        let mut tree = QuadTree::new(
+            Aabb::new(
+                Point2::new(-1024f32, -1024f32),
+                Point2::new(4096f32, 4096f32),
+            ),
+            Vector2::new(1024f32, 1024f32),
+            Vector2::new(10f32, 10f32),
+            0,
+            0,
+            0,
+        );
+        tree.set_auto_collect(16);
+        tree
+    };
+    let mut rng = Pcg32::seed_from_u64(10101);
+    let mut abs = Vec::new();
+    for i in 0..20000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..100f32);
+        let h = rng.gen_range(0.0..100f32);
+        abs.push((
+            i,
+            Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h)),
+            i,
+        ));
+    }
+    let mut tree = new_tree();
+    for (id, ab, bind) in abs.iter() {
+        tree.add(*id, *ab, *bind);
+    }
+    tree.collect();
+    let mut batch = new_tree();
+    // 重复的id不会被添加
+    assert_eq!(
+        batch.add_batch(abs.iter().cloned().chain(abs[0..10].iter().cloned())),
+        20000
+    );
+    assert_eq!(batch.len(), 20000);
+    assert!(!batch.collect_if_beneficial(0));
+    assert_eq!(batch.get_auto_collect(), 16);
+
+    fn ab_func(arg: &mut Vec<usize>, id: usize, _: &Aabb, _: &usize) {
+        arg.push(id);
+    }
+    for _ in 0..50 {
+        let x = rng.gen_range(-2000f32..5000f32);
+        let y = rng.gen_range(-2000f32..5000f32);
+        let aabb = Aabb::new(Point2::new(x, y), Point2::new(x + 500.0, y + 500.0));
+        let (mut r1, mut r2) = (Vec::new(), Vec::new());
+        tree.query(&aabb, intersects, &mut r1, ab_func);
+        batch.query(&aabb, intersects, &mut r2, ab_func);
+        r1.sort();
+        r2.sort();
+        assert_eq!(r1, r2);
+    }
+}
+
+#[test]
+fn test_update_batch() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    tree.set_auto_collect(8);
+    let mut rng = Pcg32::seed_from_u64(12121);
+    let gen_rect = |rng: &mut Pcg32| {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..100f32);
+        let h = rng.gen_range(0.0..100f32);
+        Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h))
+    };
+    let n = 10000;
+    for i in 0..n {
+        tree.add(i, gen_rect(&mut rng), i);
+    }
+    tree.collect();
+    fn ab_func(arg: &mut (usize, bool), id: usize, _: &Aabb, _: &usize) {
+        if id == arg.0 {
+            arg.1 = true;
+        }
+    }
+    for _ in 0..5 {
+        let moves: Vec<(usize, Aabb)> = (0..n).map(|i| (i, gen_rect(&mut rng))).collect();
+        // 不存在的id不计数
+        let count = tree.update_batch(moves.iter().cloned().chain([(n + 1, gen_rect(&mut rng))]));
+        assert_eq!(count, n);
+        assert!(!tree.collect_if_beneficial(0));
+        for (id, aabb) in moves.iter().step_by(7) {
+            assert_eq!(&tree.get(*id).unwrap().0, aabb);
+            let mut arg = (*id, false);
+            tree.query(aabb, intersects, &mut arg, ab_func);
+            assert!(arg.1);
+        }
+    }
+}
+
+#[test]
+fn test_cone() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(1024f32, 1024f32),
+        ),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let unit =
+        |x: f32, y: f32| Aabb::new(Point2::new(x - 1.0, y - 1.0), Point2::new(x + 1.0, y + 1.0));
+    // 顶点在原点，朝向x轴正方向，半张角30°，视野距离100
+    let cone = Cone::new(
+        Point2::new(0.0, 0.0),
+        Vector2::new(2.0, 0.0),
+        std::f32::consts::FRAC_PI_6,
+        100.0,
+    );
+    // 视野内
+    tree.add(1, unit(50.0, 0.0), 1);
+    tree.add(2, unit(50.0, 25.0), 2);
+    // 只有边角进入视野
+    tree.add(3, unit(70.0, 40.5), 3);
+    // 横跨扇形的弧，顶点都不在扇形内
+    tree.add(
+        4,
+        Aabb::new(Point2::new(99.0, -1.0), Point2::new(101.0, 1.0)),
+        4,
+    );
+    // 横跨扇形的边，顶点都不在扇形内
+    tree.add(
+        5,
+        Aabb::new(Point2::new(20.0, 11.0), Point2::new(22.0, 100.0)),
+        5,
+    );
+    // 包含顶点
+    tree.add(6, unit(0.5, 0.0), 6);
+    // 顶点后方
+    tree.add(7, unit(-50.0, 0.0), 7);
+    // 超出半张角
+    tree.add(8, unit(50.0, 40.0), 8);
+    // 超出视野距离
+    tree.add(9, unit(150.0, 0.0), 9);
+    tree.add(10, unit(90.0, 50.0), 10);
+    for i in 100..1100 {
+        let f = i as f32;
+        tree.add(i, unit(f.sin() * 500.0 - 300.0, f.cos() * 500.0), i);
+    }
+    tree.collect();
+
+    let mut args: ConeQueryArgs<usize, usize> = ConeQueryArgs::new(cone.clone());
+    tree.query(&cone, cone_branch_func, &mut args, cone_ab_query_func);
+    let mut ids: Vec<usize> = args
+        .result
+        .iter()
+        .map(|r| r.0)
+        .filter(|id| *id < 100)
+        .collect();
+    ids.sort();
+    assert_eq!(ids, vec![1, 2, 3, 4, 5, 6]);
+}
+
+#[test]
+fn test_iter() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1013);
+    // 部分节点在根空间外，会放到outer上
+    for i in 1..2000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..100f32);
+        tree.add(
+            i,
+            Aabb::new(Point2::new(x, y), Point2::new(x + w, y + w)),
+            i,
+        );
+    }
+    tree.collect();
+    assert!(!tree.outer.is_empty());
+    let mut ids: Vec<usize> = tree.iter().map(|(id, _, _)| id).collect();
+    assert_eq!(ids.len(), tree.len());
+    ids.sort();
+    assert_eq!(ids, (1..2000).collect::<Vec<usize>>());
+    for (id, aabb, bind) in tree.iter() {
+        assert_eq!(*bind, id);
+        assert_eq!(aabb, &tree.get(id).unwrap().0);
+    }
+
+    for (id, _, bind) in tree.iter_mut() {
+        *bind = id * 2;
+    }
+    for i in 1..2000 {
+        assert_eq!(tree.get(i).unwrap().1, i * 2);
+    }
+}
+
+#[test]
+fn test_query_sorted_by() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, (u32, usize)> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1014);
+    let mut abs = vec![];
+    for i in 1..2000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..100f32);
+        let ab = Aabb::new(Point2::new(x, y), Point2::new(x + w, y + w));
+        // 绑定为(材质, 序号)，按材质排序
+        tree.add(i, ab, (rng.gen_range(0..16), i));
+        abs.push((i, ab));
+    }
+    tree.collect();
+    let region = Aabb::new(Point2::new(0f32, 0f32), Point2::new(2000f32, 2000f32));
+    let result = tree.query_sorted_by(&region, |bind| bind.0);
+    let keys: Vec<u32> = result
+        .iter()
+        .map(|id| tree.get(*id).unwrap().1 .0)
+        .collect();
+    assert!(keys.windows(2).all(|w| w[0] <= w[1]));
+    let mut ids = result.clone();
+    ids.sort();
+    let expect: Vec<usize> = abs
+        .iter()
+        .filter(|(_, ab)| intersects(&region, ab))
+        .map(|(i, _)| *i)
+        .collect();
+    assert!(!expect.is_empty());
+    assert_eq!(ids, expect);
+
+    // 逆序的键
+    let result = tree.query_sorted_by(&region, |bind| std::cmp::Reverse(bind.1));
+    assert!(result.windows(2).all(|w| w[0] > w[1]));
+}
+
+#[test]
+fn test_set_active() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    for i in 1..200 {
+        let p = Point2::new((i % 20) as f32 * 10.0, (i / 20) as f32 * 10.0);
+        tree.add(i, Aabb::new(p, p + Vector2::new(5.0, 5.0)), i);
+    }
+    // 在根空间外，放在outer上
+    tree.add(
+        200,
+        Aabb::new(Point2::new(-2000.0, 0.0), Point2::new(100.0, 5.0)),
+        200,
+    );
+    tree.collect();
+    fn ab_func(arg: &mut Vec<usize>, id: usize, _: &Aabb, _: &usize) {
+        arg.push(id);
+    }
+    let query = |tree: &QuadTree<usize, usize>| {
+        let mut ids = Vec::new();
+        let aabb = Aabb::new(Point2::new(0.0, 0.0), Point2::new(50.0, 50.0));
+        tree.query(&aabb, intersects, &mut ids, ab_func);
+        ids
+    };
+    let all = query(&tree);
+    assert!(all.contains(&22) && all.contains(&200));
+
+    assert!(tree.set_active(22, false));
+    assert!(tree.set_active(200, false));
+    assert!(!tree.set_active(1000, false));
+    assert_eq!(tree.is_active(22), Some(false));
+    assert_eq!(tree.is_active(1000), None);
+    let ids = query(&tree);
+    assert_eq!(ids.len(), all.len() - 2);
+    assert!(!ids.contains(&22) && !ids.contains(&200));
+    // 未激活的节点仍在树中，可以正常更新
+    assert_eq!(tree.len(), 200);
+    assert!(tree.update(
+        22,
+        Aabb::new(Point2::new(30.0, 30.0), Point2::new(35.0, 35.0))
+    ));
+    assert!(!query(&tree).contains(&22));
+
+    tree.set_active(22, true);
+    tree.set_active(200, true);
+    let mut ids = query(&tree);
+    let mut all = all;
+    ids.sort();
+    all.sort();
+    assert_eq!(ids, all);
+}
+
+#[test]
+fn test_distance_to_nearest() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    assert_eq!(tree.distance_to_nearest(&Point2::new(0.0, 0.0)), None);
+    let mut rng = Pcg32::seed_from_u64(1016);
+    let mut abs = vec![];
+    for i in 1..1000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..300f32);
+        let h = rng.gen_range(0.0..300f32);
+        let ab = Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h));
+        tree.add(i, ab, i);
+        abs.push(ab);
+    }
+    tree.collect();
+    // 有符号距离的暴力计算
+    let signed = |ab: &Aabb, p: &Point2<f32>| {
+        if ab.contains_local_point(p) {
+            -(p.x - ab.mins.x)
+                .min(ab.maxs.x - p.x)
+                .min(p.y - ab.mins.y)
+                .min(ab.maxs.y - p.y)
+        } else {
+            <QuadHelper>::aabb_point_distance_sq(ab, p).sqrt()
+        }
+    };
+    let mut inside = 0;
+    for _ in 0..500 {
+        let p = Point2::new(
+            rng.gen_range(-1500f32..4500f32),
+            rng.gen_range(-1500f32..4500f32),
+        );
+        let expect = abs.iter().map(|ab| signed(ab, &p)).fold(f32::MAX, f32::min);
+        let d = tree.distance_to_nearest(&p).unwrap();
+        assert!((d - expect).abs() < 1e-3, "{:?} {} {}", p, d, expect);
+        if d < 0.0 {
+            inside += 1;
+        }
+    }
+    assert!(inside > 0);
+
+    // 重叠节点返回穿透最深的
+    tree.clear();
+    tree.add(
+        1,
+        Aabb::new(Point2::new(0.0, 0.0), Point2::new(100.0, 100.0)),
+        1,
+    );
+    tree.add(
+        2,
+        Aabb::new(Point2::new(40.0, 0.0), Point2::new(60.0, 100.0)),
+        2,
+    );
+    assert_eq!(
+        tree.distance_to_nearest(&Point2::new(50.0, 30.0)),
+        Some(-30.0)
+    );
+    assert_eq!(
+        tree.distance_to_nearest(&Point2::new(45.0, 50.0)),
+        Some(-45.0)
+    );
+    assert_eq!(
+        tree.distance_to_nearest(&Point2::new(103.0, 104.0)),
+        Some(5.0)
+    );
+}
+
+#[test]
+fn test_query_first() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    for i in 1..500 {
+        let p = Point2::new((i % 25) as f32 * 10.0, (i / 25) as f32 * 10.0);
+        tree.add(i, Aabb::new(p, p + Vector2::new(5.0, 5.0)), i);
+    }
+    tree.collect();
+    // 参数为(查询范围, 回调次数)
+    fn ab_func(arg: &mut (Aabb, usize), _id: usize, aabb: &Aabb, _: &usize) -> bool {
+        arg.1 += 1;
+        intersects(&arg.0, aabb)
+    }
+    let aabb = Aabb::new(Point2::new(0.0, 0.0), Point2::new(240.0, 190.0));
+    let mut arg = (aabb, 0);
+    let id = tree.query_first(&aabb, intersects, &mut arg, ab_func);
+    assert!(id.is_some());
+    // 第一个被接受的节点就停止了查询
+    let mut all = (aabb, 0);
+    tree.query(&aabb, intersects, &mut all, |arg, id, aabb, bind| {
+        ab_func(arg, id, aabb, bind);
+    });
+    assert_eq!(all.1, 499);
+    assert!(arg.1 < all.1);
+    let (ab, _) = tree.get(id.unwrap()).unwrap();
+    assert!(intersects(&aabb, ab));
+
+    // 没有相交的节点
+    let aabb = Aabb::new(Point2::new(1000.0, 1000.0), Point2::new(1100.0, 1100.0));
+    let mut arg = (aabb, 0);
+    assert_eq!(tree.query_first(&aabb, intersects, &mut arg, ab_func), None);
+
+    // outer上的节点先被查询
+    tree.add(
+        1000,
+        Aabb::new(Point2::new(-2000.0, 0.0), Point2::new(10.0, 10.0)),
+        1000,
+    );
+    let aabb = Aabb::new(Point2::new(0.0, 0.0), Point2::new(240.0, 190.0));
+    let mut arg = (aabb, 0);
+    assert_eq!(
+        tree.query_first(&aabb, intersects, &mut arg, ab_func),
+        Some(1000)
+    );
+    assert_eq!(arg.1, 1);
+}
+
+#[test]
+fn test_point_query() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    // 两个共享x=10边的盒子
+    tree.add(
+        1,
+        Aabb::new(Point2::new(0.0, 0.0), Point2::new(10.0, 10.0)),
+        1,
+    );
+    tree.add(
+        2,
+        Aabb::new(Point2::new(10.0, 0.0), Point2::new(20.0, 10.0)),
+        2,
+    );
+    // 大盒子在较浅的层
+    tree.add(
+        3,
+        Aabb::new(Point2::new(-500.0, -500.0), Point2::new(500.0, 500.0)),
+        3,
+    );
+    for i in 10..300 {
+        let p = Point2::new(
+            (i % 20) as f32 * 50.0 + 100.0,
+            (i / 20) as f32 * 50.0 + 100.0,
+        );
+        tree.add(i, Aabb::new(p, p + Vector2::new(5.0, 5.0)), i);
+    }
+    tree.collect();
+    let query = |p: Point2<f32>| {
+        let mut args: PointQueryArgs<usize, usize> = PointQueryArgs::new(p);
+        tree.query(&p, contains_point, &mut args, point_ab_query_func);
+        let mut ids: Vec<usize> = args.result.iter().map(|r| r.0).collect();
+        ids.sort();
+        ids
+    };
+    // 共享边上的点只属于左闭的那个盒子
+    assert_eq!(query(Point2::new(10.0, 5.0)), [2, 3]);
+    assert_eq!(query(Point2::new(9.9, 5.0)), [1, 3]);
+    assert_eq!(query(Point2::new(20.0, 5.0)), [3]);
+    assert_eq!(query(Point2::new(102.0, 152.0)), [3, 20]);
+    assert_eq!(query(Point2::new(620.0, 620.0)), Vec::<usize>::new());
+}
+
+#[test]
+fn test_two_nearest() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    let p = Point2::new(0.0, 0.0);
+    assert_eq!(tree.two_nearest(&p), None);
+    tree.add(
+        1,
+        Aabb::new(Point2::new(1.0, 1.0), Point2::new(2.0, 2.0)),
+        1,
+    );
+    assert_eq!(tree.two_nearest(&p), None);
+    tree.clear();
+
+    let mut rng = Pcg32::seed_from_u64(1018);
+    let mut abs = vec![];
+    for i in 1..2000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..200f32);
+        let h = rng.gen_range(0.0..200f32);
+        let ab = Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h));
+        tree.add(i, ab, i);
+        abs.push((i, ab));
+    }
+    tree.collect();
+    for _ in 0..200 {
+        let p = Point2::new(
+            rng.gen_range(-1500f32..4500f32),
+            rng.gen_range(-1500f32..4500f32),
+        );
+        let mut all: Vec<(f32, usize)> = abs
+            .iter()
+            .map(|(i, ab)| ((ab.center() - p).norm_squared(), *i))
+            .collect();
+        all.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let (a, b) = tree.two_nearest(&p).unwrap();
+        assert_ne!(a, b);
+        let d = |id: usize| (tree.get(id).unwrap().0.center() - p).norm_squared();
+        assert_eq!(d(a), all[0].0);
+        assert_eq!(d(b), all[1].0);
+    }
+}
+
+#[test]
+fn test_grow_root() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(256f32, 256f32),
+        Vector2::new(4f32, 4f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1019);
+    let mut abs = vec![];
+    // 一半在根空间内，一半在根空间外
+    for i in 1..1000 {
+        let (min, max) = if i % 2 == 0 {
+            (0f32, 1000f32)
+        } else {
+            (-3000f32, 4000f32)
+        };
+        let p = Point2::new(rng.gen_range(min..max), rng.gen_range(min..max));
+        let ab = Aabb::new(p, p + Vector2::new(rng.gen_range(1.0..20f32), 5.0));
+        tree.add(i, ab, i);
+        abs.push((i, ab));
+    }
+    tree.collect();
+    tree.set_active(3, false);
+    let outer = tree.outer.len();
+    assert!(outer > 300);
+    let deep = tree.deep;
+
+    // 不包含原根空间
+    assert!(!tree.grow_root(Aabb::new(
+        Point2::new(100f32, 0f32),
+        Point2::new(5000f32, 5000f32)
+    )));
+    assert_eq!(tree.outer.len(), outer);
+
+    assert!(tree.grow_root(Aabb::new(
+        Point2::new(-4096f32, -4096f32),
+        Point2::new(5120f32, 5120f32)
+    )));
+    assert!(tree.outer.is_empty());
+    assert!(tree.deep >= deep);
+    assert_eq!(tree.len(), abs.len());
+    assert_eq!(tree.is_active(3), Some(false));
+    assert_eq!(tree.is_active(4), Some(true));
+    for (i, ab) in &abs {
+        assert_eq!(tree.get(*i), Some(&(*ab, *i)));
+    }
+    // 查询结果和暴力计算一致
+    fn ab_func(arg: &mut (Aabb, Vec<usize>), id: usize, aabb: &Aabb, _: &usize) {
+        if intersects(&arg.0, aabb) {
+            arg.1.push(id);
+        }
+    }
+    let aabb = Aabb::new(Point2::new(-2000.0, -1000.0), Point2::new(500.0, 3000.0));
+    let mut args = (aabb, Vec::new());
+    tree.query(&aabb, intersects, &mut args, ab_func);
+    args.1.sort();
+    let expect: Vec<usize> = abs
+        .iter()
+        .filter(|(i, ab)| *i != 3 && intersects(&aabb, ab))
+        .map(|(i, _)| *i)
+        .collect();
+    assert!(!expect.is_empty());
+    assert_eq!(args.1, expect);
+}
+
+#[test]
+fn test_query_accept() {
+    use crate::tree::QueryAction;
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    for i in 1..500 {
+        let p = Point2::new((i % 25) as f32 * 10.0, (i / 25) as f32 * 10.0);
+        tree.add(i, Aabb::new(p, p + Vector2::new(5.0, 5.0)), i);
+    }
+    tree.collect();
+    // 参数为(查询范围, 回调次数, 接受次数)，只接受偶数绑定的节点，接受到第3个时停止
+    fn ab_func(
+        arg: &mut (Aabb, usize, usize),
+        _id: usize,
+        aabb: &Aabb,
+        bind: &usize,
+    ) -> QueryAction {
+        arg.1 += 1;
+        if !intersects(&arg.0, aabb) || bind % 2 == 1 {
+            return QueryAction::Reject;
+        }
+        arg.2 += 1;
+        if arg.2 == 3 {
+            QueryAction::AcceptAndStop
+        } else {
+            QueryAction::Accept
+        }
+    }
+    let aabb = Aabb::new(Point2::new(0.0, 0.0), Point2::new(240.0, 190.0));
+    let mut arg = (aabb, 0, 0);
+    let ids = tree.query_accept(&aabb, intersects, &mut arg, ab_func);
+    assert_eq!(ids.len(), 3);
+    assert_eq!(arg.2, 3);
+    assert!(arg.1 < 499);
+    assert!(ids.iter().all(|id| id % 2 == 0));
+
+    // 不停止时接受所有满足条件的节点
+    fn accept_odd(_: &mut (), _id: usize, _: &Aabb, bind: &usize) -> QueryAction {
+        if bind % 2 == 1 {
+            QueryAction::Accept
+        } else {
+            QueryAction::Reject
+        }
+    }
+    let ids = tree.query_accept(&aabb, intersects, &mut (), accept_odd);
+    assert_eq!(ids.len(), 250);
+}
+
+#[test]
+fn test_query_layer_counts() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(256f32, 256f32),
+        Vector2::new(4f32, 4f32),
+        0,
+        0,
+        0,
+    );
+    // 3种大小的节点，分别在不同的层
+    let sizes = [200f32, 50f32, 4f32];
+    let mut id = 1;
+    for (s, size) in sizes.iter().enumerate() {
+        for i in 0..(10 * (s + 1)) {
+            let p = Point2::new(i as f32 * 30.0, s as f32 * 300.0);
+            tree.add(id, Aabb::new(p, p + Vector2::new(*size, *size)), id);
+            id += 1;
+        }
+    }
+    tree.collect();
+    let layers: Vec<usize> = sizes
+        .iter()
+        .map(|s| tree.get_layer(&Aabb::new(Point2::new(0.0, 0.0), Point2::new(*s, *s))))
+        .collect();
+    assert!(layers[0] < layers[1] && layers[1] < layers[2]);
+
+    let all = tree.query_layer_counts(
+        &Aabb::new(Point2::new(0.0, 0.0), Point2::new(1024.0, 1024.0)),
+        intersects,
+    );
+    assert_eq!(all.len(), layers[2] + 1);
+    assert_eq!(all.iter().sum::<usize>(), 60);
+    assert_eq!(all[layers[0]], 10);
+    assert_eq!(all[layers[1]], 20);
+    assert_eq!(all[layers[2]], 30);
+
+    // 只和第二行的节点相交，x在[0, 150)之间的5个
+    let counts = tree.query_layer_counts(
+        &Aabb::new(Point2::new(0.0, 320.0), Point2::new(149.0, 330.0)),
+        intersects,
+    );
+    assert_eq!(counts.iter().sum::<usize>(), 5);
+    assert_eq!(counts[layers[1]], 5);
+}
+
+#[test]
+fn test_stats() {
+    use crate::tree::TreeStats;
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(256f32, 256f32),
+        Vector2::new(4f32, 4f32),
+        4,
+        8,
+        0,
+    );
+    assert_eq!(
+        tree.stats(),
+        TreeStats {
+            branch_count: 1,
+            ..Default::default()
+        }
+    );
+    // 根空间外1个，第0层1个，左下角的子空间5个小节点
+    tree.add(
+        1,
+        Aabb::new(Point2::new(-10.0, 0.0), Point2::new(10.0, 10.0)),
+        1,
+    );
+    tree.add(
+        2,
+        Aabb::new(Point2::new(0.0, 0.0), Point2::new(1000.0, 1000.0)),
+        2,
+    );
+    for i in 3..8 {
+        let p = Point2::new(i as f32 * 10.0, 10.0);
+        tree.add(i, Aabb::new(p, p + Vector2::new(2.0, 2.0)), i);
+    }
+    tree.collect();
+    let stats = tree.stats();
+    assert_eq!(stats.branch_count, 1);
+    assert_eq!(stats.outer_count, 1);
+    assert_eq!(stats.max_list_len, 5);
+    assert_eq!(stats.max_layer, 1);
+    assert_eq!(stats.layer_counts, [1, 5]);
+
+    // 超过分裂阈值，左下角的子空间分裂成分支节点
+    for i in 8..20 {
+        let p = Point2::new(i as f32 * 10.0, 10.0);
+        tree.add(i, Aabb::new(p, p + Vector2::new(2.0, 2.0)), i);
+    }
+    tree.collect();
+    let stats = tree.stats();
+    assert!(stats.branch_count > 1);
+    assert!(stats.max_layer > 1);
+    assert!(stats.max_list_len < 17);
+    assert_eq!(stats.outer_count, 1);
+    assert_eq!(stats.layer_counts.iter().sum::<usize>(), 18);
+    assert_eq!(stats.layer_counts[0], 1);
+}
+
+#[test]
+fn test_ab_query_contains() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    // 完全在框内，但绑定较小
+    tree.add(
+        1,
+        Aabb::new(Point2::new(10.0, 10.0), Point2::new(20.0, 20.0)),
+        1,
+    );
+    // 部分在框内，绑定较大
+    tree.add(
+        2,
+        Aabb::new(Point2::new(90.0, 10.0), Point2::new(120.0, 20.0)),
+        2,
+    );
+    // 在框外
+    tree.add(
+        3,
+        Aabb::new(Point2::new(200.0, 10.0), Point2::new(220.0, 20.0)),
+        3,
+    );
+    let marquee = Aabb::new(Point2::new(0.0, 0.0), Point2::new(100.0, 100.0));
+    let mut args: AbQueryArgs<usize, usize> = AbQueryArgs::new(marquee, 0);
+    tree.query(&marquee, intersects, &mut args, ab_query_func);
+    assert_eq!(args.result, (2, 2));
+    let mut args: AbQueryArgs<usize, usize> = AbQueryArgs::new(marquee, 0);
+    tree.query(&marquee, intersects, &mut args, ab_query_contains_func);
+    assert_eq!(args.result, (1, 1));
+}
+
+#[cfg(debug_assertions)]
+#[test]
+fn test_validate_loose_progression() {
+    let tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(256f32, 256f32),
+        Vector2::new(4f32, 4f32),
+        0,
+        0,
+        0,
+    );
+    assert_eq!(tree.validate_loose_progression(), Ok(()));
+
+    // 子节点的松散值不变的错误实现，其余都委托给QuadHelper
+    struct BrokenHelper;
+    type Q = QuadHelper<f32>;
+    impl Helper<4> for BrokenHelper {
+        type Point = Point2<f32>;
+        type Vector = Vector2<f32>;
+        type Aabb = Aabb;
+        type Scalar = f32;
+
+        fn aabb_extents(aabb: &Aabb) -> Vector2<f32> {
+            Q::aabb_extents(aabb)
+        }
+        fn aabb_shift(aabb: &Aabb, distance: &Vector2<f32>) -> Aabb {
+            Q::aabb_shift(aabb, distance)
+        }
+        fn aabb_contains(aabb: &Aabb, other: &Aabb) -> bool {
+            Q::aabb_contains(aabb, other)
+        }
+        fn aabb_intersects(aabb: &Aabb, other: &Aabb) -> bool {
+            Q::aabb_intersects(aabb, other)
+        }
+        fn aabb_point_distance_sq(aabb: &Aabb, point: &Point2<f32>) -> f32 {
+            Q::aabb_point_distance_sq(aabb, point)
+        }
+        fn aabb_point_max_distance_sq(aabb: &Aabb, point: &Point2<f32>) -> f32 {
+            Q::aabb_point_max_distance_sq(aabb, point)
+        }
+        fn aabb_point_signed_distance(aabb: &Aabb, point: &Point2<f32>) -> f32 {
+            Q::aabb_point_signed_distance(aabb, point)
+        }
+        fn aabb_center_distance_sq(aabb: &Aabb, point: &Point2<f32>) -> f32 {
+            Q::aabb_center_distance_sq(aabb, point)
+        }
+        fn aabb_center(aabb: &Aabb) -> Point2<f32> {
+            Q::aabb_center(aabb)
+        }
+        fn aabb_merged(aabb: &Aabb, other: &Aabb) -> Aabb {
+            Q::aabb_merged(aabb, other)
+        }
+        fn aabb_is_valid(aabb: &Aabb) -> bool {
+            Q::aabb_is_valid(aabb)
+        }
+        fn point_distance_sq(a: &Point2<f32>, b: &Point2<f32>) -> f32 {
+            Q::point_distance_sq(a, b)
+        }
+        fn point_lerp(a: &Point2<f32>, b: &Point2<f32>, t: f32) -> Point2<f32> {
+            Q::point_lerp(a, b, t)
+        }
+        fn get_deap(
+            d: &mut Vector2<f32>,
+            loose_layer: usize,
+            max_loose: &Vector2<f32>,
+            deep: usize,
+            min_loose: &Vector2<f32>,
+        ) -> usize {
+            Q::get_deap(d, loose_layer, max_loose, deep, min_loose)
+        }
+        fn smaller_than_min_loose(d: &Vector2<f32>, min_loose: &Vector2<f32>) -> bool {
+            Q::smaller_than_min_loose(d, min_loose)
+        }
+        fn calc_layer(loose: &Vector2<f32>, el: &Vector2<f32>) -> usize {
+            Q::calc_layer(loose, el)
+        }
+        fn get_child(point: &Point2<f32>, aabb: &Aabb) -> u8 {
+            Q::get_child(point, aabb)
+        }
+        fn get_max_half_loose(aabb: &Aabb, loose: &Vector2<f32>) -> Point2<f32> {
+            Q::get_max_half_loose(aabb, loose)
+        }
+        fn make_childs(aabb: &Aabb, loose: &Vector2<f32>) -> [Aabb; 4] {
+            Q::make_childs(aabb, loose)
+        }
+        fn create_child(
+            aabb: &Aabb,
+            loose: &Vector2<f32>,
+            layer: usize,
+            loose_layer: usize,
+            min_loose: &Vector2<f32>,
+            child_index: u8,
+        ) -> (Aabb, Vector2<f32>) {
+            let (a, _) = Q::create_child(aabb, loose, layer, loose_layer, min_loose, child_index);
+            (a, *loose)
+        }
+    }
+    let tree: Tree<usize, BrokenHelper, usize, 4> = Tree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(256f32, 256f32),
+        Vector2::new(4f32, 4f32),
+        0,
+        0,
+        0,
+    );
+    assert!(tree.validate_loose_progression().is_err());
+}
+
+#[cfg(feature = "rayon")]
+#[test]
+fn test_par_query() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1022);
+    for i in 1..50000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..100f32);
+        let h = rng.gen_range(0.0..100f32);
+        tree.add(
+            i,
+            Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h)),
+            i,
+        );
+    }
+    tree.collect();
+    fn ab_func(arg: &mut (Aabb, Vec<usize>), id: usize, aabb: &Aabb, _: &usize) {
+        if intersects(&arg.0, aabb) {
+            arg.1.push(id);
+        }
+    }
+    for _ in 0..10 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let aabb = Aabb::new(Point2::new(x, y), Point2::new(x + 1000.0, y + 800.0));
+        let mut serial = (aabb, Vec::new());
+        tree.query(&aabb, intersects, &mut serial, ab_func);
+        let (_, mut par) = tree.par_query(
+            &aabb,
+            intersects,
+            || (aabb, Vec::new()),
+            ab_func,
+            |mut a, b| {
+                a.1.extend(b.1);
+                a
+            },
+        );
+        serial.1.sort();
+        par.sort();
+        assert_eq!(serial.1, par);
+    }
+}
+
+#[test]
+fn test_polyline() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    let unit =
+        |x: f32, y: f32| Aabb::new(Point2::new(x - 1.0, y - 1.0), Point2::new(x + 1.0, y + 1.0));
+    // 之字形路径 (0,0) -> (100,100) -> (200,0) -> (300,100)，宽度10
+    let path = vec![
+        Point2::new(0.0, 0.0),
+        Point2::new(100.0, 100.0),
+        Point2::new(200.0, 0.0),
+        Point2::new(300.0, 100.0),
+    ];
+    // 在路径上
+    tree.add(1, unit(50.0, 50.0), 1);
+    tree.add(2, unit(150.0, 50.0), 2);
+    tree.add(3, unit(250.0, 50.0), 3);
+    // 距离路径中心线约5.6，边缘在宽度内
+    tree.add(4, unit(58.0, 50.0), 4);
+    // 在拐角外侧，距离拐点3
+    tree.add(5, unit(100.0, 104.0), 5);
+    // 在拐角外侧，距离拐点5.5
+    tree.add(10, unit(100.0, 106.5), 10);
+    // 在之字形的凹处，离路径较远
+    tree.add(6, unit(100.0, 20.0), 6);
+    tree.add(7, unit(200.0, 80.0), 7);
+    // 在路径端点之外
+    tree.add(8, unit(310.0, 110.0), 8);
+    tree.add(9, unit(-3.0, -3.0), 9);
+    tree.collect();
+    let polyline = Polyline::new(path, 10.0);
+    let mut args: PolylineQueryArgs<usize, usize> = PolylineQueryArgs::new(polyline.clone());
+    tree.query(
+        &polyline,
+        polyline_branch_func,
+        &mut args,
+        polyline_ab_query_func,
+    );
+    let mut ids: Vec<usize> = args.result.iter().map(|r| r.0).collect();
+    ids.sort();
+    assert_eq!(ids, [1, 2, 3, 4, 5, 9]);
+
+    // 单点折线退化为圆
+    let p = [Point2::new(0.0, 0.0)];
+    assert!(polyline_intersects(&p, 10.0, &unit(4.0, 0.0)));
+    assert!(!polyline_intersects(&p, 10.0, &unit(5.0, 5.0)));
+    assert!(polyline_intersects(&p, 10.0, &unit(3.0, 3.0)));
+    assert!(!polyline_intersects(&[], 10.0, &unit(0.0, 0.0)));
+}
+
+#[test]
+fn test_query_collect() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(10f32, 10f32),
+        0,
+        0,
+        0,
+    );
+    for i in 1..400 {
+        let p = Point2::new((i % 20) as f32 * 10.0, (i / 20) as f32 * 10.0);
+        tree.add(i, Aabb::new(p, p + Vector2::new(5.0, 5.0)), i * 2);
+    }
+    tree.set_active(21, false);
+    tree.collect();
+    let region = Aabb::new(Point2::new(0.0, 0.0), Point2::new(29.0, 29.0));
+    let mut result = tree.query_collect(&region);
+    // 3x3个位置，id从1开始没有0号节点，其中一个未激活
+    assert_eq!(result.len(), 7);
+    assert!(!result.ids().contains(&21));
+    for (id, aabb, bind) in result.iter() {
+        assert_eq!(**bind, id * 2);
+        assert!(intersects(&region, aabb));
+    }
+    let point = Point2::new(26.0, 26.0);
+    result.sort_by_distance(&point, <QuadHelper>::aabb_point_distance_sq);
+    assert_eq!(result.ids()[0], 42);
+    let d: Vec<f32> = result
+        .iter()
+        .map(|r| <QuadHelper>::aabb_point_distance_sq(r.1, &point))
+        .collect();
+    assert!(d.windows(2).all(|w| w[0] <= w[1]));
+}
+
+#[test]
+fn test_rehome_changed_layers() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(256f32, 256f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        3,
+    );
+    assert_eq!(tree.get_deep(), 3);
+    let mut rng = Pcg32::seed_from_u64(1024);
+    let mut small = vec![];
+    let mut big = vec![];
+    for i in 1..1000 {
+        let p = Point2::new(rng.gen_range(0f32..900f32), rng.gen_range(0f32..900f32));
+        // 小节点受深度限制在第3层，大节点在第1层
+        let s = if i % 3 == 0 { 0.5 } else { 100.0 };
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i);
+        if i % 3 == 0 {
+            small.push(i);
+        } else {
+            big.push(i);
+        }
+    }
+    tree.collect();
+    // 参数没有改变，不需要重新放置
+    assert_eq!(tree.rehome_changed_layers(), 0);
+    assert!(tree.stats().max_layer <= 3);
+    let big_layer = tree.get_layer(&tree.get(big[0]).unwrap().0);
+
+    tree.set_deep(0);
+    assert!(tree.get_deep() > 3);
+    // 只有受深度限制的小节点被重新放置，大节点的层不变
+    assert_eq!(tree.rehome_changed_layers(), small.len());
+    assert_eq!(
+        tree.get_layer(&tree.get(small[0]).unwrap().0),
+        tree.get_deep()
+    );
+    assert_eq!(tree.get_layer(&tree.get(big[0]).unwrap().0), big_layer);
+    assert_eq!(tree.rehome_changed_layers(), 0);
+    let stats = tree.stats();
+    assert!(stats.max_layer > 3);
+    assert_eq!(stats.layer_counts.iter().sum::<usize>(), 999);
+    // 查询结果正确
+    for id in small.iter().chain(big.iter()).step_by(7) {
+        let aabb = tree.get(*id).unwrap().0;
+        let mut args = PointQueryArgs::<usize, usize>::new(aabb.center());
+        tree.query(
+            &aabb.center(),
+            contains_point,
+            &mut args,
+            point_ab_query_func,
+        );
+        assert!(args.result.iter().any(|r| r.0 == *id));
+    }
+}
+
+#[test]
+fn test_set_adjust() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        64,
+        128,
+        0,
+    );
+    for i in 1..101 {
+        let p = Point2::new(
+            (i % 10) as f32 * 100.0 + 10.0,
+            (i / 10) as f32 * 90.0 + 10.0,
+        );
+        tree.add(i, Aabb::new(p, p + Vector2::new(1f32, 1f32)), i);
+    }
+    tree.collect();
+    // 阈值高，根节点没有分化
+    assert_eq!(tree.stats().branch_count, 1);
+
+    tree.set_adjust(2, 4);
+    assert_eq!(tree.get_adjust(), (2, 4));
+    tree.collect();
+    let branch_count = tree.stats().branch_count;
+    assert!(branch_count > 1);
+    assert_eq!(tree.stats().layer_counts.iter().sum::<usize>(), 100);
+    // 阈值不变，重新评估不改变结构
+    tree.rebalance();
+    assert_eq!(tree.stats().branch_count, branch_count);
+
+    // 提高阈值后重新评估，分支节点收缩
+    tree.set_adjust(200, 0);
+    assert_eq!(tree.get_adjust(), (200, 200));
+    tree.rebalance();
+    assert!(tree.stats().branch_count < branch_count);
+    tree.set_adjust(0, 0);
+    assert_eq!(tree.get_adjust(), (4, 8));
+}
+
+#[test]
+fn test_rebalance() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        64,
+        128,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1025);
+    for i in 1..501 {
+        let p = Point2::new(rng.gen_range(0f32..990f32), rng.gen_range(0f32..990f32));
+        tree.add(i, Aabb::new(p, p + Vector2::new(2f32, 2f32)), i);
+    }
+    tree.collect();
+    // 直接修改阈值而不设置脏标记，模拟没有触发脏标记的失衡树
+    tree.adjust = (2, 4);
+    tree.collect();
+    let before = tree.stats();
+
+    let region = Aabb::new(Point2::new(200f32, 300f32), Point2::new(700f32, 600f32));
+    let query = |tree: &QuadTree<usize, usize>| {
+        let mut r = tree.query_collect(&region).ids();
+        r.sort();
+        r
+    };
+    let result = query(&tree);
+
+    tree.rebalance();
+    let after = tree.stats();
+    assert!(after.branch_count > before.branch_count);
+    assert!(after.max_list_len < before.max_list_len);
+    assert_eq!(after.layer_counts.iter().sum::<usize>(), 500);
+    assert_eq!(query(&tree), result);
+}
+
+#[test]
+fn test_collect_with_report() {
+    use crate::tree::CollectReport;
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    // 没有脏节点，全0
+    assert_eq!(tree.collect_with_report(), CollectReport::default());
+    let items = (1..41).map(|i| {
+        let p = Point2::new((i % 8) as f32 * 120.0 + 10.0, (i / 8) as f32 * 150.0 + 10.0);
+        (i, Aabb::new(p, p + Vector2::new(1f32, 1f32)), i)
+    });
+    tree.set_auto_collect(usize::MAX);
+    for (id, aabb, bind) in items {
+        tree.add(id, aabb, bind);
+    }
+    let report = tree.collect_with_report();
+    assert!(report.branches_created > 0);
+    assert_eq!(report.branches_destroyed, 0);
+    assert!(report.nodes_relocated > 0);
+    assert_eq!(tree.stats().branch_count, report.branches_created + 1);
+    // 整理后再次整理，全0
+    assert_eq!(tree.collect_with_report(), CollectReport::default());
+
+    // 删除大部分节点，触发收缩
+    for i in 1..37 {
+        tree.remove(i);
+    }
+    let report = tree.collect_with_report();
+    assert!(report.branches_destroyed > 0);
+    assert_eq!(report.branches_created, 0);
+}
+
+#[cfg(feature = "fixedbitset")]
+#[test]
+fn test_query_bitset() {
+    use fixedbitset::FixedBitSet;
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    for i in 0..100 {
+        let p = Point2::new(
+            (i % 10) as f32 * 100.0 + 10.0,
+            (i / 10) as f32 * 100.0 + 10.0,
+        );
+        tree.add(i, Aabb::new(p, p + Vector2::new(5f32, 5f32)), i);
+    }
+    tree.collect();
+    let region = Aabb::new(Point2::new(200f32, 300f32), Point2::new(520f32, 412f32));
+    let mut expect = tree.query_collect(&region).ids();
+    expect.sort();
+    assert_eq!(expect, vec![32, 33, 34, 35, 42, 43, 44, 45]);
+
+    let mut bits = FixedBitSet::with_capacity(10);
+    tree.query_bitset(&region, intersects, &mut bits);
+    assert!(bits.len() > 45);
+    assert_eq!(bits.ones().collect::<Vec<usize>>(), expect);
+}
+
+#[test]
+#[cfg(feature = "std")]
+fn test_query_grouped_by() {
+    let mut tree: QuadTree<usize, (usize, &str)> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let sheets = ["tree", "rock", "grass"];
+    for i in 0..100 {
+        let p = Point2::new(
+            (i % 10) as f32 * 100.0 + 10.0,
+            (i / 10) as f32 * 100.0 + 10.0,
+        );
+        tree.add(
+            i,
+            Aabb::new(p, p + Vector2::new(5f32, 5f32)),
+            (i, sheets[i % 3]),
+        );
+    }
+    tree.collect();
+    let region = Aabb::new(Point2::new(0f32, 0f32), Point2::new(520f32, 520f32));
+    let groups = tree.query_grouped_by(&region, |bind| bind.1);
+
+    // 手动分类
+    let ids = tree.query_collect(&region).ids();
+    assert_eq!(ids.len(), 36);
+    assert_eq!(groups.len(), 3);
+    for sheet in sheets {
+        let mut expect: Vec<usize> = ids
+            .iter()
+            .copied()
+            .filter(|id| sheets[id % 3] == sheet)
+            .collect();
+        expect.sort();
+        let mut group = groups[sheet].clone();
+        group.sort();
+        assert_eq!(group, expect);
+    }
+    assert!(tree
+        .query_grouped_by(
+            &Aabb::new(Point2::new(50f32, 50f32), Point2::new(60f32, 60f32)),
+            |bind| bind.1
+        )
+        .is_empty());
+}
+
+#[test]
+fn test_static_query() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    for i in 0..100 {
+        let p = Point2::new(
+            (i % 10) as f32 * 100.0 + 10.0,
+            (i / 10) as f32 * 100.0 + 10.0,
+        );
+        tree.add(i, Aabb::new(p, p + Vector2::new(5f32, 5f32)), i);
+    }
+    tree.collect();
+    let region = Aabb::new(Point2::new(100f32, 100f32), Point2::new(250f32, 250f32));
+    let handle = tree.register_static_query(region);
+    let sorted = |ids: &[usize]| {
+        let mut ids = ids.to_vec();
+        ids.sort();
+        ids
+    };
+    assert_eq!(
+        sorted(tree.query_static(handle).unwrap()),
+        vec![11, 12, 21, 22]
+    );
+    let is_dirty = |tree: &QuadTree<usize, usize>| tree.static_queries.get(handle).unwrap().dirty;
+
+    // 范围外的节点移动，缓存不失效
+    tree.shift(55, Vector2::new(10f32, 10f32));
+    tree.update(
+        77,
+        Aabb::new(Point2::new(800f32, 800f32), Point2::new(900f32, 900f32)),
+    );
+    tree.remove(99);
+    tree.add(
+        100,
+        Aabb::new(Point2::new(600f32, 600f32), Point2::new(610f32, 610f32)),
+        100,
+    );
+    assert!(!is_dirty(&tree));
+
+    // 范围内的节点移出，缓存失效并重新查询
+    tree.shift(11, Vector2::new(-100f32, 0f32));
+    assert!(is_dirty(&tree));
+    assert_eq!(sorted(tree.query_static(handle).unwrap()), vec![12, 21, 22]);
+    assert!(!is_dirty(&tree));
+    // 节点移入范围
+    tree.update(
+        55,
+        Aabb::new(Point2::new(200f32, 150f32), Point2::new(205f32, 155f32)),
+    );
+    assert_eq!(
+        sorted(tree.query_static(handle).unwrap()),
+        vec![12, 21, 22, 55]
+    );
+    tree.set_active(12, false);
+    assert_eq!(sorted(tree.query_static(handle).unwrap()), vec![21, 22, 55]);
+    tree.remove(21);
+    tree.add(
+        200,
+        Aabb::new(Point2::new(240f32, 240f32), Point2::new(260f32, 260f32)),
+        200,
+    );
+    assert_eq!(
+        sorted(tree.query_static(handle).unwrap()),
+        vec![22, 55, 200]
+    );
+    tree.clear();
+    assert!(tree.query_static(handle).unwrap().is_empty());
+
+    assert!(tree.unregister_static_query(handle));
+    assert!(tree.query_static(handle).is_none());
+    assert!(!tree.unregister_static_query(handle));
+}
+
+#[test]
+fn test_segment_query() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(
+            Point2::new(-1024f32, -1024f32),
+            Point2::new(4096f32, 4096f32),
+        ),
+        Vector2::new(1024f32, 1024f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    // 5x5的盒子网格，盒子大小10，间隔20，倒序添加
+    for id in (0..25).rev() {
+        let p = Point2::new((id % 5) as f32 * 20.0, (id / 5) as f32 * 20.0);
+        tree.add(id, Aabb::new(p, p + Vector2::new(10f32, 10f32)), id);
+    }
+    tree.collect();
+    // y = 3 + x / 2，只穿过(0, 0)、(2, 1)、(4, 2)的盒子
+    let segment = Segment::new(Point2::new(0f32, 3f32), Point2::new(100f32, 53f32));
+    let mut args = SegmentQueryArgs::new(segment.clone());
+    tree.query(
+        &segment,
+        segment_branch_func,
+        &mut args,
+        segment_ab_query_func,
+    );
+    args.sort_by_t();
+    assert_eq!(args.result, vec![(0, 0, 0.0), (7, 7, 0.4), (14, 14, 0.8)]);
+
+    // 精确判断
+    let b = Aabb::new(Point2::new(20f32, 0f32), Point2::new(30f32, 10f32));
+    assert!(!segment_intersects_aabb(&segment.p0, &segment.p1, &b));
+    assert!(segment_intersects_aabb(
+        &Point2::new(25f32, 5f32),
+        &Point2::new(25f32, 5f32),
+        &b
+    ));
+    // 只接触角点
+    assert!(segment_intersects_aabb(
+        &Point2::new(20f32, 20f32),
+        &Point2::new(40f32, 0f32),
+        &b
+    ));
+    assert!(!segment_intersects_aabb(
+        &Point2::new(21f32, 20f32),
+        &Point2::new(41f32, 0f32),
+        &b
+    ));
+}
+
+#[test]
+fn test_tree_group() {
+    use crate::tree::TreeGroup;
+
+    let new_tree = || -> QuadTree<usize, usize> {
+        QuadTree::new(
+            Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+            Vector2::new(100f32, 100f32),
+            Vector2::new(1f32, 1f32),
+            0,
+            0,
+            0,
+        )
+    };
+    let mut group = TreeGroup::new();
+    assert!(group.is_empty());
+    // 三个类别，id可以在不同的叉树中重复
+    for category in 0..3 {
+        let mut tree = new_tree();
+        for i in 0..20 {
+            let p = Point2::new(i as f32 * 50.0, category as f32 * 10.0);
+            tree.add(
+                i,
+                Aabb::new(p, p + Vector2::new(5f32, 5f32)),
+                category * 100 + i,
+            );
+        }
+        assert_eq!(group.push(tree), category);
+    }
+    assert_eq!(group.len(), 3);
+    group.get_mut(1).unwrap().remove(3);
+
+    let region = Aabb::new(Point2::new(100f32, 0f32), Point2::new(210f32, 100f32));
+    let result = group.query_ids(&region);
+    assert_eq!(result.len(), 8);
+    for index in 0..3 {
+        let mut ids: Vec<usize> = result
+            .iter()
+            .filter(|r| r.0 == index)
+            .map(|r| r.1)
+            .collect();
+        ids.sort();
+        let mut expect = group.get(index).unwrap().query_collect(&region).ids();
+        expect.sort();
+        assert_eq!(ids, expect);
+    }
+    assert_eq!(result.iter().filter(|r| r.0 == 1).count(), 2);
+
+    // 回调带有来源叉树的索引，绑定和索引对应，同Tree::query，由ab_func判断是否相交
+    let mut arg = (region, Vec::new());
+    group.query(
+        &region,
+        intersects,
+        &mut arg,
+        |arg, index, id, aabb, bind| {
+            assert_eq!(*bind, index * 100 + id);
+            if intersects(&arg.0, aabb) {
+                arg.1.push(*bind);
+            }
+        },
+    );
+    arg.1.sort();
+    assert_eq!(arg.1, vec![2, 3, 4, 102, 104, 202, 203, 204]);
+}
+
+#[test]
+fn test_drain() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1033);
+    for i in 0..500 {
+        // 有一些节点超出根空间，在outer上
+        let p = Point2::new(rng.gen_range(-50f32..1000f32), rng.gen_range(0f32..1000f32));
+        tree.add(i, Aabb::new(p, p + Vector2::new(3f32, 3f32)), i * 2);
+    }
+    // 留下一些未整理的脏节点
+    tree.collect();
+    for i in 0..100 {
+        tree.remove(i);
+        let p = Point2::new(i as f32 * 5.0, 500f32);
+        tree.add(i, Aabb::new(p, p + Vector2::new(1f32, 1f32)), i * 2);
+    }
+    assert!(!tree.outer.is_empty());
+
+    let mut items: Vec<(usize, Aabb, usize)> = tree.drain().collect();
+    items.sort_by_key(|item| item.0);
+    assert_eq!(items.len(), 500);
+    assert!(items
+        .iter()
+        .enumerate()
+        .all(|(i, item)| item.0 == i && item.2 == i * 2));
+    assert_eq!(
+        items[10].1,
+        Aabb::new(Point2::new(50f32, 500f32), Point2::new(51f32, 501f32))
+    );
+
+    // 叉树为空，只剩根空间，没有残留的脏节点
+    assert_eq!(tree.len(), 0);
+    assert!(tree.outer.is_empty());
+    assert_eq!(tree.stats().branch_count, 1);
+    assert_eq!(
+        tree.collect_with_report(),
+        crate::tree::CollectReport::default()
+    );
+    assert!(tree
+        .query_collect(&Aabb::new(
+            Point2::new(-100f32, -100f32),
+            Point2::new(2000f32, 2000f32)
+        ))
+        .is_empty());
+    // 可以继续使用
+    tree.add_batch(items.into_iter());
+    assert_eq!(tree.len(), 500);
+}
+
+#[test]
+fn test_flatten_below() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(10332);
+    for i in 0..1000 {
+        let p = Point2::new(rng.gen_range(0f32..1000f32), rng.gen_range(0f32..1000f32));
+        let s = rng.gen_range(1f32..40f32);
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i);
+    }
+    tree.collect();
+    let max_layer =
+        |tree: &QuadTree<usize, usize>| tree.slab.iter().map(|(_, b)| b.layer).max().unwrap();
+    assert!(max_layer(&tree) > 2);
+    let regions = [
+        Aabb::new(Point2::new(100f32, 100f32), Point2::new(300f32, 250f32)),
+        Aabb::new(Point2::new(500f32, 0f32), Point2::new(520f32, 1024f32)),
+        Aabb::new(Point2::new(-10f32, -10f32), Point2::new(2000f32, 2000f32)),
+    ];
+    let query = |tree: &QuadTree<usize, usize>, region: &Aabb| {
+        let mut ids = tree.query_collect(region).ids();
+        ids.sort();
+        ids
+    };
+    let before: Vec<Vec<usize>> = regions.iter().map(|r| query(&tree, r)).collect();
+    let branch_count = tree.stats().branch_count;
+
+    tree.flatten_below(2);
+    assert!(max_layer(&tree) <= 2);
+    assert!(tree.stats().branch_count < branch_count);
+    let stats = tree.stats();
+    assert_eq!(
+        stats.layer_counts.iter().sum::<usize>() + stats.outer_count,
+        1000
+    );
+    for (region, ids) in regions.iter().zip(before.iter()) {
+        assert_eq!(&query(&tree, region), ids);
+    }
+    // 压平后可以继续修改
+    for i in 0..100 {
+        tree.remove(i);
+    }
+    tree.collect();
+    assert_eq!(query(&tree, &regions[2]).len(), 900);
+}
+
+#[test]
+fn test_query_bounding_circle() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let region = Aabb::new(Point2::new(200f32, 300f32), Point2::new(600f32, 500f32));
+    assert!(tree.query_bounding_circle(&region, intersects).is_none());
+
+    let mut rng = Pcg32::seed_from_u64(1034);
+    for i in 0..1000 {
+        let p = Point2::new(rng.gen_range(0f32..1000f32), rng.gen_range(0f32..1000f32));
+        let s = rng.gen_range(1f32..20f32);
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i);
+    }
+    tree.collect();
+    let (center, radius) = tree.query_bounding_circle(&region, intersects).unwrap();
+    let items = tree.query_collect(&region);
+    assert!(!items.is_empty());
+    for id in items.ids() {
+        let d = (tree.get(id).unwrap().0.center() - center).norm();
+        assert!(d <= radius + 1e-3);
+    }
+    // 近似的最小外接圆不会比区域的外接圆大太多
+    let bound = region.loosened(20f32).extents().norm() / 2.0;
+    assert!(radius <= bound * 1.2);
+
+    // 只有一个节点时，圆心为节点中心，半径为0
+    let one = Aabb::new(Point2::new(-30f32, -30f32), Point2::new(-20f32, -10f32));
+    tree.add(1000, one, 0);
+    let (center, radius) = tree
+        .query_bounding_circle(
+            &Aabb::new(Point2::new(-40f32, -40f32), Point2::new(-1f32, -1f32)),
+            intersects,
+        )
+        .unwrap();
+    assert_eq!(center, one.center());
+    assert_eq!(radius, 0f32);
+}
+
+#[test]
+fn test_validate() {
+    use crate::tree::{AbNode, BranchKey, BranchNode, ChildNode, TreeError};
+    use pcg_rand::Pcg32;
+    use pi_link_list::Node;
+    use pi_null::Null;
+    use rand::{Rng, SeedableRng};
+
+    let build = || {
+        let mut tree: QuadTree<usize, usize> = QuadTree::new(
+            Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+            Vector2::new(100f32, 100f32),
+            Vector2::new(1f32, 1f32),
+            0,
+            0,
+            0,
+        );
+        let mut rng = Pcg32::seed_from_u64(10342);
+        for i in 0..1000 {
+            let p = Point2::new(rng.gen_range(-20f32..1000f32), rng.gen_range(0f32..1000f32));
+            let s = rng.gen_range(1f32..50f32);
+            tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i);
+        }
+        tree.collect();
+        for i in 0..200 {
+            tree.remove(i * 3);
+        }
+        tree.collect();
+        assert_eq!(tree.validate(), Ok(()));
+        tree
+    };
+    // 在分支节点上的ab节点
+    let linked = |tree: &QuadTree<usize, usize>| {
+        tree.ab_map
+            .iter()
+            .find(|(_, n)| !n.parent.is_null())
+            .map(|(id, _)| id)
+            .unwrap()
+    };
+    let outer = |tree: &QuadTree<usize, usize>| tree.outer.iter(&tree.ab_map).count();
+
+    // 节点从ab_map中删除，链表未修改
+    let mut tree = build();
+    let id = linked(&tree);
+    tree.ab_map.remove(id);
+    assert!(matches!(tree.validate(), Err(TreeError::MissingNode { id: i, .. }) if i == id));
+
+    // 节点只在ab_map中
+    let mut tree = build();
+    let aabb = Aabb::new(Point2::new(1f32, 1f32), Point2::new(2f32, 2f32));
+    tree.ab_map
+        .insert(5000, Node::new(AbNode::new(aabb, 0, 0, 4)));
+    assert_eq!(tree.validate(), Err(TreeError::UnlinkedNode { id: 5000 }));
+
+    // 错误的parent
+    let mut tree = build();
+    let id = linked(&tree);
+    let other = tree.root_key;
+    let node = unsafe { tree.ab_map.get_unchecked_mut(id) };
+    let expected = node.parent;
+    node.parent = if expected == other {
+        BranchKey::null()
+    } else {
+        other
+    };
+    let found = node.parent;
+    assert_eq!(
+        tree.validate(),
+        Err(TreeError::WrongParent {
+            id,
+            expected,
+            found
+        })
+    );
+
+    // 错误的parent_child
+    let mut tree = build();
+    let id = linked(&tree);
+    let node = unsafe { tree.ab_map.get_unchecked_mut(id) };
+    let expected = node.parent_child;
+    node.parent_child = (expected + 1) % 5;
+    assert_eq!(
+        tree.validate(),
+        Err(TreeError::WrongParentChild {
+            id,
+            expected,
+            found: (expected + 1) % 5
+        })
+    );
+
+    // 错误的layer
+    let mut tree = build();
+    let id = linked(&tree);
+    let node = unsafe { tree.ab_map.get_unchecked_mut(id) };
+    let branch = node.parent;
+    let branch_layer = tree.slab.get(branch).unwrap().layer;
+    node.layer = if node.parent_child == 4 {
+        branch_layer + 1
+    } else {
+        0
+    };
+    let layer = node.layer;
+    assert_eq!(
+        tree.validate(),
+        Err(TreeError::WrongLayer { branch, id, layer })
+    );
+
+    // 链表头记录的长度错误：复制outer的链表头，删除尾节点后再写回
+    let mut tree = build();
+    let count = outer(&tree);
+    assert!(count >= 3);
+    let tail = tree.outer.tail();
+    let copy = unsafe { std::ptr::read(&tree.outer) };
+    tree.outer.unlink(tail, &mut tree.ab_map);
+    unsafe { std::ptr::write(&mut tree.outer, copy) };
+    assert_eq!(
+        tree.validate(),
+        Err(TreeError::WrongLength {
+            branch: BranchKey::null(),
+            child: 4,
+            expected: count,
+            found: count - 1
+        })
+    );
+
+    // 错误的前后链接：头节点被移到链表尾，再写回原链表头
+    let mut tree = build();
+    let head = tree.outer.head();
+    let copy = unsafe { std::ptr::read(&tree.outer) };
+    tree.outer.unlink(head, &mut tree.ab_map);
+    tree.outer.link_before(head, Null::null(), &mut tree.ab_map);
+    unsafe { std::ptr::write(&mut tree.outer, copy) };
+    assert_eq!(
+        tree.validate(),
+        Err(TreeError::BrokenLink {
+            branch: BranchKey::null(),
+            id: head
+        })
+    );
+
+    // 节点同时在分支节点和outer上
+    let mut tree = build();
+    tree.outer.clear(&mut tree.ab_map);
+    let id = linked(&tree);
+    let node = unsafe { tree.ab_map.get_unchecked(id) };
+    let branch = tree.slab.get(node.parent).unwrap();
+    let list = match &branch.childs[node.parent_child as usize % 4] {
+        ChildNode::Ab(list) if node.parent_child < 4 => list,
+        _ => &branch.nodes,
+    };
+    let copy = unsafe { std::ptr::read(list) };
+    let first = copy.head();
+    unsafe { std::ptr::write(&mut tree.outer, copy) };
+    assert_eq!(
+        tree.validate(),
+        Err(TreeError::DuplicateNode {
+            branch: BranchKey::null(),
+            id: first
+        })
+    );
+
+    // 子分支节点的parent_child错误
+    let mut tree = build();
+    let child = tree
+        .slab
+        .iter()
+        .find(|(id, _)| *id != tree.root_key)
+        .map(|(id, _)| id)
+        .unwrap();
+    let b = tree.slab.get_mut(child).unwrap();
+    b.parent_child = (b.parent_child + 1) % 4;
+    assert_eq!(
+        tree.validate(),
+        Err(TreeError::WrongBranch { branch: child })
+    );
+
+    // 无法从根节点到达的分支节点
+    let mut tree = build();
+    let root = tree.root_key;
+    let branch = tree.slab.insert(BranchNode::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(500f32, 500f32)),
+        Vector2::new(50f32, 50f32),
+        1,
+        root,
+        0,
+    ));
+    assert_eq!(tree.validate(), Err(TreeError::OrphanBranch { branch }));
+}
+
+#[test]
+fn test_retain() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1036);
+    for i in 0..1000 {
+        // 有一些节点超出根空间，在outer上
+        let p = Point2::new(rng.gen_range(-50f32..1000f32), rng.gen_range(0f32..1000f32));
+        let s = rng.gen_range(1f32..30f32);
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i * 2);
+    }
+    tree.collect();
+    let all = Aabb::new(Point2::new(-100f32, -100f32), Point2::new(2000f32, 2000f32));
+    let region = Aabb::new(Point2::new(200f32, 100f32), Point2::new(600f32, 700f32));
+    let before = tree.query_collect(&region).ids();
+
+    tree.retain(|id, _, bind| {
+        assert_eq!(*bind, id * 2);
+        id % 2 == 0
+    });
+    assert_eq!(tree.len(), 500);
+    assert_eq!(tree.validate(), Ok(()));
+    let mut ids = tree.query_collect(&all).ids();
+    ids.sort();
+    assert_eq!(ids, (0..1000).step_by(2).collect::<Vec<usize>>());
+    // 区域内的偶数节点保留，奇数节点被移除
+    let mut expected: Vec<usize> = before.into_iter().filter(|id| id % 2 == 0).collect();
+    expected.sort();
+    let mut ids = tree.query_collect(&region).ids();
+    ids.sort();
+    assert_eq!(ids, expected);
+    assert!(tree.get(1).is_none() && tree.get(2).is_some());
+}
+
+#[test]
+fn test_query_summary() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let region = Aabb::new(Point2::new(200f32, 100f32), Point2::new(600f32, 700f32));
+    assert_eq!(tree.query_summary(&region, intersects), (0, None));
+
+    let mut rng = Pcg32::seed_from_u64(10362);
+    for i in 0..1000 {
+        let p = Point2::new(rng.gen_range(-50f32..1000f32), rng.gen_range(0f32..1000f32));
+        let s = rng.gen_range(1f32..30f32);
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i);
+    }
+    tree.collect();
+    let (count, bounds) = tree.query_summary(&region, intersects);
+    let items = tree.query_collect(&region);
+    assert_eq!(count, items.len());
+    let expected = items.iter().fold(None, |b: Option<Aabb>, item| {
+        Some(b.map_or(*item.1, |b| b.merged(item.1)))
+    });
+    assert_eq!(bounds, expected);
+    // 节点密集，并集几乎覆盖整个区域，边界上的节点使并集超出区域
+    let bounds = bounds.unwrap();
+    let inner = Aabb::new(
+        region.mins + Vector2::new(30f32, 30f32),
+        region.maxs - Vector2::new(30f32, 30f32),
+    );
+    assert!(bounds.contains(&inner));
+    assert!(!region.contains(&bounds));
+}
+
+#[test]
+fn test_invalid_aabb() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    for i in 0..100 {
+        let p = Point2::new((i % 10) as f32 * 100.0, (i / 10) as f32 * 100.0);
+        tree.add(i, Aabb::new(p, p + Vector2::new(10f32, 10f32)), i);
+    }
+    tree.collect();
+    let invalids = [
+        Aabb::new(Point2::new(f32::NAN, 0f32), Point2::new(10f32, 10f32)),
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(10f32, f32::NAN)),
+        Aabb::new(
+            Point2::new(f32::NEG_INFINITY, 0f32),
+            Point2::new(10f32, 10f32),
+        ),
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(f32::INFINITY, 10f32)),
+        // min大于max
+        Aabb::new(Point2::new(20f32, 0f32), Point2::new(10f32, 10f32)),
+        Aabb::new(Point2::new(0f32, 20f32), Point2::new(10f32, 10f32)),
+    ];
+    let all = Aabb::new(Point2::new(-10f32, -10f32), Point2::new(2000f32, 2000f32));
+    let snapshot = |tree: &QuadTree<usize, usize>| {
+        let mut items: Vec<(usize, Aabb)> = tree
+            .query_collect(&all)
+            .iter()
+            .map(|(id, ab, _)| (*id, **ab))
+            .collect();
+        items.sort_by_key(|item| item.0);
+        (items, tree.stats())
+    };
+    let before = snapshot(&tree);
+    for aabb in invalids.iter() {
+        assert!(!tree.add(1000, *aabb, 0));
+        assert!(!tree.update(5, *aabb));
+    }
+    assert!(!tree.shift(5, Vector2::new(f32::NAN, 0f32)));
+    assert!(!tree.shift(5, Vector2::new(0f32, f32::INFINITY)));
+    assert!(!tree.contains_key(1000));
+    assert_eq!(
+        tree.get(5).unwrap().0,
+        Aabb::new(Point2::new(500f32, 0f32), Point2::new(510f32, 10f32))
+    );
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(snapshot(&tree), before);
+    // 退化为点的aabb是有效的
+    let p = Point2::new(5f32, 5f32);
+    assert!(tree.update(5, Aabb::new(p, p)));
+}
+
+#[test]
+fn test_pin_to_root() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(10372);
+    for i in 0..1000 {
+        let p = Point2::new(rng.gen_range(0f32..990f32), rng.gen_range(0f32..990f32));
+        tree.add(i, Aabb::new(p, p + Vector2::new(2f32, 2f32)), i);
+    }
+    tree.collect();
+    let in_root = |tree: &QuadTree<usize, usize>, id: usize| {
+        let root = tree.slab.get(tree.root_key).unwrap();
+        root.nodes.iter(&tree.ab_map).any(|(k, _)| k == id)
+    };
+    let id = 7;
+    assert!(!in_root(&tree, id));
+    assert_eq!(tree.is_pinned(id), Some(false));
+    assert!(tree.pin_to_root(id));
+    assert!(!tree.pin_to_root(5000));
+    assert_eq!(tree.is_pinned(id), Some(true));
+    assert!(in_root(&tree, id));
+    assert_eq!(tree.validate(), Ok(()));
+
+    // 移动、整理、重新放置后仍在根节点上，和节点相交的任意范围都能查询到
+    for step in 0..50 {
+        let p = Point2::new(rng.gen_range(0f32..990f32), rng.gen_range(0f32..990f32));
+        if step % 2 == 0 {
+            tree.update(id, Aabb::new(p, p + Vector2::new(2f32, 2f32)));
+        } else {
+            let old = tree.get(id).unwrap().0.mins;
+            tree.shift(id, p - old);
+        }
+        tree.collect();
+        assert!(in_root(&tree, id));
+        let ab = tree.get(id).unwrap().0;
+        let s = rng.gen_range(0.5f32..50f32);
+        let region = Aabb::new(
+            ab.mins - Vector2::new(s, s),
+            ab.mins + Vector2::new(1f32, 1f32),
+        );
+        assert!(tree.query_collect(&region).ids().contains(&id));
+    }
+    assert_eq!(tree.rehome_changed_layers(), 0);
+    assert!(in_root(&tree, id));
+
+    // 超出根空间时在outer上，回来后仍在根节点上
+    tree.shift(id, Vector2::new(2000f32, 0f32));
+    assert!(!in_root(&tree, id));
+    tree.shift(id, Vector2::new(-2000f32, 0f32));
+    assert!(in_root(&tree, id));
+    assert_eq!(tree.validate(), Ok(()));
+
+    // 取消固定后按大小重新放置
+    assert!(tree.unpin(id));
+    assert_eq!(tree.is_pinned(id), Some(false));
+    assert!(!in_root(&tree, id));
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+#[test]
+fn test_accessors() {
+    let root = Aabb::new(Point2::new(-100f32, 0f32), Point2::new(900f32, 500f32));
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        root,
+        Vector2::new(100f32, 50f32),
+        Vector2::new(2f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    assert_eq!(tree.root_aabb(), &root);
+    assert_eq!(
+        tree.loose_params(),
+        (&Vector2::new(100f32, 50f32), &Vector2::new(2f32, 1f32))
+    );
+    let ab = Aabb::new(Point2::new(10f32, 20f32), Point2::new(15f32, 30f32));
+    tree.add(1, ab, 100);
+    assert_eq!(tree.get_aabb(1), Some(&ab));
+    assert_eq!(tree.get_aabb(2), None);
+    tree.shift(1, Vector2::new(5f32, 0f32));
+    assert_eq!(
+        tree.get_aabb(1),
+        Some(&Aabb::new(
+            Point2::new(15f32, 20f32),
+            Point2::new(20f32, 30f32)
+        ))
+    );
+}
+
+#[test]
+fn test_query_set() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    // 10x10的网格，间隔100
+    for i in 0..100 {
+        let p = Point2::new(
+            (i % 10) as f32 * 100.0 + 10.0,
+            (i / 10) as f32 * 100.0 + 10.0,
+        );
+        tree.add(i, Aabb::new(p, p + Vector2::new(5f32, 5f32)), i);
+    }
+    tree.collect();
+    let sorted = |mut ids: Vec<usize>| {
+        ids.sort();
+        ids
+    };
+    // 包含(0, 0)到(2, 2)的9个节点
+    let region = Aabb::new(Point2::new(0f32, 0f32), Point2::new(250f32, 250f32));
+    let mut set = tree.query_handle(&region);
+    assert_eq!(set.region(), &region);
+    assert_eq!(
+        sorted(set.ids().to_vec()),
+        vec![0, 1, 2, 10, 11, 12, 20, 21, 22]
+    );
+    assert_eq!(set.refresh(&tree), (vec![], vec![]));
+
+    // 11移出范围，55移入范围，22被移除，新增的200在范围内，12未激活
+    tree.shift(11, Vector2::new(500f32, 0f32));
+    tree.shift(55, Vector2::new(-400f32, -400f32));
+    tree.remove(22);
+    tree.add(
+        200,
+        Aabb::new(Point2::new(200f32, 0f32), Point2::new(205f32, 5f32)),
+        200,
+    );
+    tree.set_active(12, false);
+    tree.collect();
+    let (entered, left) = set.refresh(&tree);
+    assert_eq!(sorted(entered), vec![55, 200]);
+    assert_eq!(sorted(left), vec![11, 12, 22]);
+    assert_eq!(
+        sorted(set.ids().to_vec()),
+        vec![0, 1, 2, 10, 20, 21, 55, 200]
+    );
+    // 再次刷新，没有变化
+    assert_eq!(set.refresh(&tree), (vec![], vec![]));
+}
+
+#[test]
+fn test_take_changed_regions() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let add = |tree: &mut QuadTree<usize, usize>, id: usize, x: f32, y: f32| {
+        let p = Point2::new(x, y);
+        tree.add(id, Aabb::new(p, p + Vector2::new(2f32, 2f32)), id);
+    };
+    // 左下象限的4个子空间各2个节点，分裂出1个分支节点
+    let points = [
+        (100f32, 100f32),
+        (300f32, 100f32),
+        (100f32, 300f32),
+        (300f32, 300f32),
+    ];
+    for (i, (x, y)) in points.iter().enumerate() {
+        add(&mut tree, i * 2, *x, *y);
+        add(&mut tree, i * 2 + 1, x + 10.0, y + 10.0);
+    }
+    tree.collect();
+    assert_eq!(tree.stats().branch_count, 2);
+    let branch = tree.ab_map.get(6).unwrap().parent;
+    assert_ne!(branch, tree.root_key);
+    let branch_aabb = tree.slab.get(branch).unwrap().aabb;
+    for id in [1, 3, 5] {
+        tree.remove(id);
+    }
+    tree.collect();
+    assert_eq!(tree.stats().branch_count, 2);
+
+    // 未开启记录时不记录
+    assert!(tree.take_changed_regions().is_empty());
+    tree.set_track_changes(true);
+    // 没有结构变化的整理不记录
+    tree.shift(0, Vector2::new(1f32, 1f32));
+    tree.collect();
+    assert!(tree.take_changed_regions().is_empty());
+
+    // 节点移出分支空间，分支节点的节点数量达到收缩阈值，被合并
+    tree.update(
+        6,
+        Aabb::new(Point2::new(800f32, 800f32), Point2::new(802f32, 802f32)),
+    );
+    tree.collect();
+    assert_eq!(tree.stats().branch_count, 1);
+    // 节点移出分支空间时记录分支空间及根空间，合并时再记录分支空间
+    let root_aabb = tree.slab.get(tree.root_key).unwrap().aabb;
+    assert_eq!(
+        tree.take_changed_regions(),
+        vec![branch_aabb, root_aabb, branch_aabb]
+    );
+    assert!(tree.take_changed_regions().is_empty());
+
+    // 右上象限的节点达到分裂阈值，根节点被分裂
+    for i in 0..7 {
+        add(&mut tree, 100 + i, 700.0 + i as f32 * 40.0, 900.0);
+    }
+    tree.collect();
+    assert_eq!(tree.stats().branch_count, 2);
+    assert_eq!(tree.take_changed_regions(), vec![*tree.root_aabb()]);
+
+    // 关闭记录
+    tree.set_track_changes(false);
+    for i in 0..7 {
+        tree.remove(100 + i);
+    }
+    tree.collect();
+    assert_eq!(tree.stats().branch_count, 1);
+    assert!(tree.take_changed_regions().is_empty());
+}
+
+#[test]
+fn test_distance_query() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1042);
+    for i in 0..1000 {
+        let p = Point2::new(rng.gen_range(0f32..980f32), rng.gen_range(0f32..980f32));
+        let s = rng.gen_range(1f32..20f32);
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i);
+    }
+    tree.collect();
+    let region = Aabb::new(Point2::new(200f32, 300f32), Point2::new(500f32, 600f32));
+    let point = Point2::new(350f32, 450f32);
+    let mut args = DistanceQueryArgs::new(region, point);
+    tree.query(&region, intersects, &mut args, distance_ab_query_func);
+    assert!(args.result.len() > 10);
+    assert!(args.result.windows(2).all(|w| w[0].1 <= w[1].1));
+    // 和先查询再排序的结果一致
+    let mut expected: Vec<(usize, f32)> = tree
+        .query_collect(&region)
+        .iter()
+        .map(|(id, ab, _)| (*id, QuadHelper::<f32>::aabb_point_distance_sq(ab, &point)))
+        .collect();
+    expected.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap());
+    let mut ids: Vec<usize> = args.result.iter().map(|r| r.0).collect();
+    ids.sort();
+    let mut expected_ids: Vec<usize> = expected.iter().map(|r| r.0).collect();
+    expected_ids.sort();
+    assert_eq!(ids, expected_ids);
+    assert!(args
+        .result
+        .iter()
+        .zip(expected.iter())
+        .all(|(r, e)| r.1 == e.1 && r.2 == r.0));
+}
+
+#[test]
+fn test_swept_aabb_query() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    // 一排障碍物，x为[20k, 20k+10]，y为[50, 60]
+    for k in 0..10 {
+        let x = 20.0 * k as f32;
+        tree.add(
+            k,
+            Aabb::new(Point2::new(x, 50.0), Point2::new(x + 10.0, 60.0)),
+            k,
+        );
+    }
+    tree.collect();
+    // 10x10的盒子从(0, 0)沿对角线移动到(100, 100)
+    let start = Aabb::new(Point2::new(0f32, 0f32), Point2::new(10f32, 10f32));
+    let swept = SweptAabb::new(start, Vector2::new(100f32, 100f32));
+    let mut args = SweptAabbQueryArgs::new(swept.clone());
+    tree.query(
+        &swept,
+        swept_aabb_branch_func,
+        &mut args,
+        swept_aabb_ab_query_func,
+    );
+    let mut ids: Vec<usize> = args.result.iter().map(|r| r.0).collect();
+    ids.sort();
+    assert_eq!(ids, vec![2, 3]);
+    for r in args.result.iter() {
+        assert!(r.2 >= 0.0 && r.2 <= 1.0);
+    }
+    // 盒子在t=0.4时接触2号，t=0.5时接触3号
+    let t2 = args.result.iter().find(|r| r.0 == 2).unwrap().2;
+    let t3 = args.result.iter().find(|r| r.0 == 3).unwrap().2;
+    assert!((t2 - 0.4).abs() < 1e-5);
+    assert!((t3 - 0.5).abs() < 1e-5);
+    // 起点和终点的合并aabb会多报
+    let union = Aabb::new(Point2::new(0f32, 0f32), Point2::new(110f32, 110f32));
+    assert_eq!(tree.query_collect(&union).len(), 6);
+    // 错过所有障碍物的移动
+    let swept = SweptAabb::new(start, Vector2::new(100f32, 0f32));
+    let mut args = SweptAabbQueryArgs::new(swept.clone());
+    tree.query(
+        &swept,
+        swept_aabb_branch_func,
+        &mut args,
+        swept_aabb_ab_query_func,
+    );
+    assert!(args.result.is_empty());
+}
+
+#[test]
+fn test_shift_all() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(64f32, 64f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1044);
+    for i in 0..1000 {
+        let p = Point2::new(
+            rng.gen_range(-100f32..1100f32),
+            rng.gen_range(-100f32..1100f32),
+        );
+        let s = rng.gen_range(1f32..40f32);
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i);
+    }
+    tree.collect();
+    let region = Aabb::new(Point2::new(100f32, 200f32), Point2::new(600f32, 500f32));
+    let mut before: Vec<usize> = tree.query_collect(&region).iter().map(|r| r.0).collect();
+    before.sort();
+    assert!(!before.is_empty());
+    let branches = tree.slab.len();
+    let outer = tree.outer.len();
+
+    let delta = Vector2::new(-256f32, 512f32);
+    assert!(tree.shift_all(delta));
+    assert_eq!(tree.slab.len(), branches);
+    assert_eq!(tree.outer.len(), outer);
+    assert_eq!(
+        tree.root_aabb(),
+        &Aabb::new(Point2::new(-256f32, 512f32), Point2::new(768f32, 1536f32))
+    );
+    assert_eq!(tree.validate(), Ok(()));
+    let mut after: Vec<usize> = tree
+        .query_collect(&QuadHelper::<f32>::aabb_shift(&region, &delta))
+        .iter()
+        .map(|r| r.0)
+        .collect();
+    after.sort();
+    assert_eq!(before, after);
+
+    // 无效的平移不改变叉树
+    assert!(!tree.shift_all(Vector2::new(f32::NAN, 0f32)));
+    assert_eq!(tree.root_aabb().mins, Point2::new(-256f32, 512f32));
+}
+
+#[test]
+fn test_query_max_layer() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(256f32, 256f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1045);
+    // 偶数id为大物体，奇数id为小物体
+    for i in 0..2000 {
+        let s = if i % 2 == 0 {
+            rng.gen_range(100f32..200f32)
+        } else {
+            rng.gen_range(1f32..4f32)
+        };
+        let p = Point2::new(rng.gen_range(0f32..800f32), rng.gen_range(0f32..800f32));
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i);
+    }
+    tree.collect();
+    let max_layer = tree.get_layer(&Aabb::new(
+        Point2::new(0f32, 0f32),
+        Point2::new(100f32, 100f32),
+    ));
+    assert!(
+        tree.get_layer(&Aabb::new(Point2::new(0f32, 0f32), Point2::new(4f32, 4f32))) > max_layer
+    );
+    let region = Aabb::new(Point2::new(200f32, 200f32), Point2::new(700f32, 600f32));
+    fn collect(arg: &mut (Aabb, Vec<usize>), id: usize, aabb: &Aabb, _bind: &usize) {
+        if intersects(&arg.0, aabb) {
+            arg.1.push(id);
+        }
+    }
+    let mut args = (region, Vec::new());
+    tree.query_max_layer(&region, intersects, max_layer, &mut args, collect);
+    let mut ids = args.1;
+    ids.sort();
+    let mut expected: Vec<usize> = tree
+        .query_collect(&region)
+        .iter()
+        .filter(|r| r.0 % 2 == 0)
+        .map(|r| r.0)
+        .collect();
+    expected.sort();
+    assert!(!expected.is_empty());
+    assert_eq!(ids, expected);
+    // 层不限制时和普通查询一致
+    let mut args = (region, Vec::new());
+    tree.query_max_layer(&region, intersects, usize::MAX, &mut args, collect);
+    assert_eq!(args.1.len(), tree.query_collect(&region).len());
+}
+
+#[test]
+fn test_tree_builder() {
+    use crate::tree::TreeBuilder;
+
+    let root = Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32));
+    let max = Vector2::new(100f32, 100f32);
+    let min = Vector2::new(1f32, 1f32);
+    let expected: QuadTree<usize, usize> = QuadTree::new(root, max, min, 0, 0, 0);
+    let tree: QuadTree<usize, usize> = TreeBuilder::new()
+        .root(root)
+        .max_loose(max)
+        .min_loose(min)
+        .build()
+        .unwrap();
+    assert_eq!(tree.root_aabb(), expected.root_aabb());
+    assert_eq!(tree.loose_params(), expected.loose_params());
+    assert_eq!(tree.get_adjust(), expected.get_adjust());
+    assert_eq!(tree.get_auto_collect(), expected.get_auto_collect());
+    assert_eq!(tree.deep, expected.deep);
+    assert_eq!(
+        tree.get_layer(&Aabb::new(Point2::new(0f32, 0f32), Point2::new(3f32, 3f32))),
+        expected.get_layer(&Aabb::new(Point2::new(0f32, 0f32), Point2::new(3f32, 3f32)))
+    );
+
+    // 具名参数和new的位置参数一致
+    let expected: QuadTree<usize, usize> = QuadTree::new(root, max, min, 2, 6, 5);
+    let tree: QuadTree<usize, usize> = TreeBuilder::new()
+        .min_loose(min)
+        .deep(5)
+        .adjust(2, 6)
+        .max_loose(max)
+        .root(root)
+        .auto_collect(16)
+        .build()
+        .unwrap();
+    assert_eq!(tree.get_adjust(), expected.get_adjust());
+    assert_eq!(tree.deep, expected.deep);
+    assert_eq!(tree.get_auto_collect(), 16);
+
+    // 松散值使用默认值
+    let tree: QuadTree<usize, usize> = TreeBuilder::new().root(root).build().unwrap();
+    let extents = Vector2::new(1024f32, 1024f32);
+    assert_eq!(tree.loose_params(), (&extents, &extents));
+
+    // 没有根空间
+    assert!(TreeBuilder::<QuadHelper<f32>, 4>::new()
+        .build::<usize, usize>()
+        .is_none());
+}
+
+#[test]
+fn test_query_mut() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1048);
+    for i in 0..1000 {
+        let p = Point2::new(
+            rng.gen_range(-50f32..1050f32),
+            rng.gen_range(-50f32..1050f32),
+        );
+        let s = rng.gen_range(1f32..30f32);
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), 0);
+    }
+    tree.collect();
+    fn damage(arg: &mut Aabb, _id: usize, aabb: &Aabb, bind: &mut usize) {
+        if intersects(arg, aabb) {
+            *bind += 1;
+        }
+    }
+    let mut region = Aabb::new(Point2::new(300f32, 200f32), Point2::new(700f32, 450f32));
+    let arg = region;
+    tree.query_mut(&arg, intersects, &mut region, damage);
+    tree.query_mut(&arg, intersects, &mut region, damage);
+    let mut changed = 0;
+    for id in 0..1000 {
+        let (aabb, bind) = tree.get(id).unwrap();
+        let expected = if intersects(&region, aabb) { 2 } else { 0 };
+        assert_eq!(*bind, expected, "id: {}", id);
+        changed += expected / 2;
+    }
+    assert!(changed > 0 && changed < 1000);
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+#[test]
+fn test_collision_pairs_in() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(4096f32, 4096f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1050);
+    // 两个相距很远的节点群
+    for i in 0..600 {
+        let base = if i < 300 { 100f32 } else { 3000f32 };
+        let p = Point2::new(
+            base + rng.gen_range(0f32..300f32),
+            base + rng.gen_range(0f32..300f32),
+        );
+        let s = rng.gen_range(2f32..20f32);
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i);
+    }
+    tree.collect();
+    fn push(
+        arg: &mut Vec<(usize, usize)>,
+        a: usize,
+        _: &Aabb,
+        _: &usize,
+        b: usize,
+        _: &Aabb,
+        _: &usize,
+    ) {
+        arg.push((a.min(b), a.max(b)));
+    }
+    let mut all = Vec::new();
+    tree.collision_pairs(&mut all, push);
+    let region = Aabb::new(Point2::new(150f32, 150f32), Point2::new(300f32, 250f32));
+    let mut expected: Vec<(usize, usize)> = all
+        .into_iter()
+        .filter(|(a, b)| {
+            intersects(&region, &tree.get(*a).unwrap().0)
+                || intersects(&region, &tree.get(*b).unwrap().0)
+        })
+        .collect();
+    expected.sort();
+    assert!(!expected.is_empty());
+    let mut pairs = Vec::new();
+    tree.collision_pairs_in(&region, &mut pairs, push);
+    pairs.sort();
+    assert_eq!(pairs, expected);
+    // 远处节点群的碰撞对不会被报告
+    assert!(pairs.iter().all(|(a, b)| *a < 300 && *b < 300));
+}
+
+#[test]
+fn test_try_add() {
+    use crate::tree::AddError;
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let ab = Aabb::new(Point2::new(10f32, 10f32), Point2::new(20f32, 20f32));
+    assert_eq!(tree.try_add(1, ab, 1), Ok(()));
+    assert_eq!(tree.try_add(1, ab, 2), Err(AddError::DuplicateKey));
+    assert_eq!(tree.get(1), Some(&(ab, 1)));
+    let nan = Aabb::new(Point2::new(f32::NAN, 10f32), Point2::new(20f32, 20f32));
+    assert_eq!(tree.try_add(2, nan, 2), Err(AddError::InvalidAabb));
+    let inverted = Aabb::new(Point2::new(30f32, 10f32), Point2::new(20f32, 20f32));
+    assert_eq!(tree.try_add(2, inverted, 2), Err(AddError::InvalidAabb));
+    // id已存在时优先报告DuplicateKey
+    assert_eq!(tree.try_add(1, nan, 2), Err(AddError::DuplicateKey));
+    assert!(!tree.contains_key(2));
+    assert_eq!(tree.len(), 1);
+    // add和try_add一致
+    assert!(!tree.add(1, ab, 3));
+    assert!(tree.add(2, ab, 2));
+}
+
+#[test]
+fn test_locate() {
+    use crate::tree::Location;
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let root = tree.root_key;
+    // 小物体在根节点的子节点链表上，x在右半边y在下半边，子节点索引为1
+    let small = Aabb::new(Point2::new(900f32, 10f32), Point2::new(902f32, 12f32));
+    tree.add(1, small, 1);
+    let layer = tree.get_layer(&small);
+    assert!(layer > 0);
+    assert_eq!(
+        tree.locate(1),
+        Some(Location::Branch {
+            branch: root,
+            parent_child: 1,
+            layer,
+        })
+    );
+    // 大物体在根节点本层的nodes上
+    let big = Aabb::new(Point2::new(100f32, 100f32), Point2::new(900f32, 900f32));
+    tree.add(2, big, 2);
+    assert_eq!(
+        tree.locate(2),
+        Some(Location::Branch {
+            branch: root,
+            parent_child: 4,
+            layer: 0,
+        })
+    );
+    // 超出根空间的物体在outer上
+    let outside = Aabb::new(Point2::new(2000f32, 10f32), Point2::new(2002f32, 12f32));
+    tree.add(3, outside, 3);
+    assert_eq!(tree.locate(3), Some(Location::Outer { layer }));
+    assert_eq!(tree.locate(4), None);
+
+    // 分裂后小物体下降到子分支节点上
+    for i in 10..30 {
+        let x = 900f32 + (i - 10) as f32 * 0.5;
+        tree.add(
+            i,
+            Aabb::new(Point2::new(x, 10f32), Point2::new(x + 2.0, 12f32)),
+            i,
+        );
+    }
+    tree.collect();
+    match tree.locate(1) {
+        Some(Location::Branch {
+            branch, layer: l, ..
+        }) => {
+            assert_ne!(branch, root);
+            assert_eq!(l, layer);
+        }
+        other => panic!("{:?}", other),
+    }
+}
+
+#[test]
+fn test_add_if_free() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let building = |x: f32, y: f32| Aabb::new(Point2::new(x, y), Point2::new(x + 10.0, y + 10.0));
+    assert!(!tree.would_overlap(&building(100.0, 100.0)));
+    assert!(tree.add_if_free(1, building(100.0, 100.0), 1));
+    // 和1号重叠，被拒绝
+    assert!(tree.would_overlap(&building(105.0, 95.0)));
+    assert!(!tree.add_if_free(2, building(105.0, 95.0), 2));
+    assert!(!tree.contains_key(2));
+    // 边界接触也算相交
+    assert!(tree.would_overlap(&building(110.0, 100.0)));
+    assert!(tree.add_if_free(2, building(111.0, 100.0), 2));
+    // 分裂后仍能检查到深层及outer上的节点
+    for i in 0..50 {
+        assert!(tree.add_if_free(10 + i, building(300.0 + i as f32 * 12.0, 500.0), i));
+    }
+    assert!(tree.add_if_free(100, building(1020.0, 1020.0), 100));
+    tree.collect();
+    assert!(tree.would_overlap(&building(490.0, 495.0)));
+    assert!(tree.would_overlap(&building(1025.0, 1025.0)));
+    assert!(!tree.would_overlap(&building(600.0, 600.0)));
+    assert_eq!(tree.len(), 53);
+}
+
+#[test]
+fn test_reserve() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    tree.reserve(2000);
+    let (slab_capacity, map_capacity) = (tree.slab.capacity(), tree.ab_map.capacity());
+    assert!(map_capacity >= 2000);
+    assert!(slab_capacity >= 2000 / 8);
+    let mut rng = Pcg32::seed_from_u64(1056);
+    for i in 0..2000 {
+        let p = Point2::new(rng.gen_range(0f32..1000f32), rng.gen_range(0f32..1000f32));
+        let s = rng.gen_range(1f32..10f32);
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i);
+    }
+    tree.collect();
+    assert!(tree.slab.len() > 1);
+    assert_eq!(tree.slab.capacity(), slab_capacity);
+    assert_eq!(tree.ab_map.capacity(), map_capacity);
+}
+
+#[test]
+fn test_query_into() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    for i in 0..100 {
+        let x = (i % 10) as f32 * 20.0;
+        let y = (i / 10) as f32 * 20.0;
+        tree.add(
+            i,
+            Aabb::new(Point2::new(x, y), Point2::new(x + 5.0, y + 5.0)),
+            i,
+        );
+    }
+    tree.collect();
+    // 命中4x4=16个节点
+    let region = Aabb::new(Point2::new(0f32, 0f32), Point2::new(70f32, 70f32));
+    let mut expected: Vec<usize> = tree.query_collect(&region).iter().map(|r| r.0).collect();
+    expected.sort();
+    assert_eq!(expected.len(), 16);
+
+    let mut buf = [usize::MAX; 32];
+    let (len, truncated) = tree.query_into(&region, intersects, &mut buf);
+    assert_eq!((len, truncated), (16, false));
+    let mut ids = buf[..len].to_vec();
+    ids.sort();
+    assert_eq!(ids, expected);
+    assert!(buf[len..].iter().all(|id| *id == usize::MAX));
+
+    // 缓冲区正好能容纳全部结果
+    let mut buf = [0; 16];
+    assert_eq!(tree.query_into(&region, intersects, &mut buf), (16, false));
+
+    // 缓冲区小于命中数量时截断
+    let mut buf = [usize::MAX; 5];
+    let (len, truncated) = tree.query_into(&region, intersects, &mut buf);
+    assert_eq!((len, truncated), (5, true));
+    assert!(buf.iter().all(|id| expected.contains(id)));
+
+    let mut buf: [usize; 0] = [];
+    assert_eq!(tree.query_into(&region, intersects, &mut buf), (0, true));
+}
+
+#[test]
+fn test_iter_branches() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    assert_eq!(tree.iter_branches().count(), 1);
+    let mut rng = Pcg32::seed_from_u64(1059);
+    for i in 0..1000 {
+        let p = Point2::new(rng.gen_range(0f32..1000f32), rng.gen_range(0f32..1000f32));
+        let s = rng.gen_range(1f32..10f32);
+        tree.add(i, Aabb::new(p, p + Vector2::new(s, s)), i);
+    }
+    tree.collect();
+    let branches: Vec<_> = tree.iter_branches().collect();
+    assert_eq!(branches.len(), tree.slab.len());
+    assert!(branches.len() > 1);
+    let (key, aabb, loose, layer) = branches[0];
+    assert_eq!(key, tree.root_key);
+    assert_eq!(aabb, tree.root_aabb());
+    assert_eq!(loose, &Vector2::new(100f32, 100f32));
+    assert_eq!(layer, 0);
+    // 深度优先，父节点总是在子节点之前，子节点的层比父节点大1
+    for (i, (key, aabb, _, layer)) in branches.iter().enumerate().skip(1) {
+        let parent = tree.slab[*key].parent;
+        let p = branches.iter().position(|b| b.0 == parent).unwrap();
+        assert!(p < i);
+        assert_eq!(*layer, branches[p].3 + 1);
+        assert!(branches[p].1.contains(aabb));
+    }
+}
+
+#[test]
+fn test_update_full() {
+    use crate::tree::Location;
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let root = tree.root_key;
+    let small = Aabb::new(Point2::new(900f32, 10f32), Point2::new(902f32, 12f32));
+    tree.add(1, small, 10);
+    let layer = tree.get_layer(&small);
+    // 移动到左下的子节点，同时更新绑定
+    let moved = Aabb::new(Point2::new(10f32, 10f32), Point2::new(12f32, 12f32));
+    assert!(tree.update_full(1, moved, 20));
+    assert_eq!(tree.get(1), Some(&(moved, 20)));
+    assert_eq!(
+        tree.locate(1),
+        Some(Location::Branch {
+            branch: root,
+            parent_child: 0,
+            layer,
+        })
+    );
+    let mut args = AbQueryArgs::new(moved, 0);
+    tree.query(&moved, intersects, &mut args, ab_query_func);
+    assert_eq!(args.result, (1, 20));
+    // 移出根空间
+    let outside = Aabb::new(Point2::new(2000f32, 10f32), Point2::new(2002f32, 12f32));
+    assert!(tree.update_full(1, outside, 30));
+    assert_eq!(tree.get(1), Some(&(outside, 30)));
+    assert_eq!(tree.locate(1), Some(Location::Outer { layer }));
+    // 无效aabb及不存在的id不改变任何字段
+    let invalid = Aabb::new(Point2::new(f32::NAN, 0f32), Point2::new(1f32, 1f32));
+    assert!(!tree.update_full(1, invalid, 40));
+    assert_eq!(tree.get(1), Some(&(outside, 30)));
+    assert!(!tree.update_full(2, moved, 50));
+}
+
+#[test]
+fn test_query_sorted() {
+    fn collect(arg: &mut (Aabb, Vec<(usize, usize)>), id: usize, aabb: &Aabb, bind: &usize) {
+        if intersects(&arg.0, aabb) {
+            arg.1.push((id, *bind));
+        }
+    }
+    let new_tree = || -> QuadTree<usize, usize> {
+        QuadTree::new(
+            Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+            Vector2::new(100f32, 100f32),
+            Vector2::new(1f32, 1f32),
+            0,
+            0,
+            0,
+        )
+    };
+    let items: Vec<(usize, Aabb)> = (1..300)
+        .map(|i| {
+            let x = ((i * 37) % 1000) as f32;
+            let y = ((i * 91) % 1000) as f32;
+            let s = (i % 7 + 1) as f32 * 3.0;
+            (i, Aabb::new(Point2::new(x, y), Point2::new(x + s, y + s)))
+        })
+        .collect();
+    let mut a = new_tree();
+    for (id, ab) in items.iter() {
+        a.add(*id, *ab, id * 2);
+    }
+    let mut b = new_tree();
+    for (id, ab) in items.iter().rev() {
+        b.add(*id, *ab, id * 2);
+    }
+    let outside = Aabb::new(Point2::new(1100f32, 10f32), Point2::new(1110f32, 20f32));
+    a.add(1000, outside, 2000);
+    b.add(1000, outside, 2000);
+    for region in [
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1200f32, 1200f32)),
+        Aabb::new(Point2::new(200f32, 300f32), Point2::new(600f32, 500f32)),
+    ] {
+        let mut ra = (region, Vec::new());
+        a.query_sorted(&region, intersects, &mut ra, collect);
+        let mut rb = (region, Vec::new());
+        b.query_sorted(&region, intersects, &mut rb, collect);
+        assert!(!ra.1.is_empty());
+        assert_eq!(ra.1, rb.1);
+        assert!(ra.1.windows(2).all(|w| w[0].0 < w[1].0));
+        // 和普通查询的结果集合一致
+        let mut rq = (region, Vec::new());
+        a.query(&region, intersects, &mut rq, collect);
+        rq.1.sort();
+        assert_eq!(ra.1, rq.1);
+    }
+}
+
+#[test]
+fn test_intersects_closed() {
+    let a = Aabb::new(Point2::new(0f32, 0f32), Point2::new(10f32, 10f32));
+    // 共享x=10的边
+    let b = Aabb::new(Point2::new(10f32, 0f32), Point2::new(20f32, 10f32));
+    assert!(!intersects(&a, &b));
+    assert!(intersects_closed(&a, &b));
+    assert!(intersects_closed(&b, &a));
+    // 只共享一个角
+    let c = Aabb::new(Point2::new(10f32, 10f32), Point2::new(20f32, 20f32));
+    assert!(!intersects(&a, &c));
+    assert!(intersects_closed(&a, &c));
+    // 分离的不相交
+    let d = Aabb::new(Point2::new(10.5f32, 0f32), Point2::new(20f32, 10f32));
+    assert!(!intersects_closed(&a, &d));
+    assert!(!intersects_closed(&d, &a));
+}
+
+#[test]
+fn test_shrink_to_fit() {
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    fn collect(arg: &mut (Aabb, Vec<usize>), id: usize, aabb: &Aabb, _: &usize) {
+        if intersects(&arg.0, aabb) {
+            arg.1.push(id);
+        }
+    }
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1065);
+    let mut boxes = Vec::new();
+    for i in 0..3000 {
+        let p = Point2::new(rng.gen_range(0f32..1000f32), rng.gen_range(0f32..1000f32));
+        let s = rng.gen_range(1f32..10f32);
+        let ab = Aabb::new(p, p + Vector2::new(s, s));
+        boxes.push(ab);
+        tree.add(i, ab, i);
+    }
+    let outside = Aabb::new(Point2::new(1100f32, 10f32), Point2::new(1110f32, 20f32));
+    tree.add(10, outside, 10);
+    boxes[10] = outside;
+    tree.collect();
+    let (slab_capacity, map_capacity) = (tree.slab.capacity(), tree.ab_map.capacity());
+    assert!(tree.slab.len() > 100);
+    for i in 50..3000 {
+        tree.remove(i);
+    }
+    tree.collect();
+    assert_eq!(tree.slab.capacity(), slab_capacity);
+    // shrink_to_fit不改变分支节点，BranchKey仍然有效
+    let branches: Vec<_> = tree.iter_branches().map(|(key, ..)| key).collect();
+    tree.shrink_to_fit();
+    assert!(tree.ab_map.capacity() < map_capacity);
+    assert_eq!(tree.slab.capacity(), slab_capacity);
+    assert_eq!(
+        tree.iter_branches()
+            .map(|(key, ..)| key)
+            .collect::<Vec<_>>(),
+        branches
+    );
+    assert_eq!(tree.validate(), Ok(()));
+    // compact重建slab，收缩分支节点的容量
+    tree.compact();
+    assert!(tree.slab.capacity() < slab_capacity);
+    assert!(tree.slab.capacity() >= tree.slab.len());
+    assert_eq!(tree.len(), 50);
+    assert_eq!(tree.validate(), Ok(()));
+    for region in [
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(2000f32, 2000f32)),
+        Aabb::new(Point2::new(100f32, 200f32), Point2::new(600f32, 700f32)),
+    ] {
+        let mut result = (region, Vec::new());
+        tree.query(&region, intersects, &mut result, collect);
+        result.1.sort();
+        let expect: Vec<usize> = (0..50)
+            .filter(|i| intersects(&region, &boxes[*i]))
+            .collect();
+        assert_eq!(result.1, expect);
+    }
+    // 收缩后可以继续添加和移动
+    for (i, ab) in boxes.iter().enumerate().take(200).skip(50) {
+        tree.add(i, *ab, i);
+    }
+    tree.update(
+        0,
+        Aabb::new(Point2::new(500f32, 500f32), Point2::new(501f32, 501f32)),
+    );
+    tree.collect();
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+#[test]
+fn test_set_layer() {
+    use crate::tree::Location;
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let mut rng = Pcg32::seed_from_u64(1066);
+    for i in 0..1000 {
+        let p = Point2::new(rng.gen_range(0f32..990f32), rng.gen_range(0f32..990f32));
+        tree.add(i, Aabb::new(p, p + Vector2::new(2f32, 2f32)), i);
+    }
+    tree.collect();
+    let root = tree.root_key;
+    let in_root = Some(Location::Branch {
+        branch: root,
+        parent_child: 4,
+        layer: 0,
+    });
+    // 小物体强制在第0层，一直留在根节点上
+    let small = Aabb::new(Point2::new(500f32, 500f32), Point2::new(501f32, 501f32));
+    assert!(tree.get_layer(&small) > 0);
+    assert!(tree.add_with_layer(2000, small, 2000, 0));
+    assert!(!tree.add_with_layer(2000, small, 2000, 0));
+    assert_eq!(tree.locate(2000), in_root);
+    assert_eq!(tree.is_pinned(2000), Some(true));
+    for _ in 0..20 {
+        let p = Point2::new(rng.gen_range(0f32..990f32), rng.gen_range(0f32..990f32));
+        tree.update(2000, Aabb::new(p, p + Vector2::new(1f32, 1f32)));
+        tree.collect();
+        assert_eq!(tree.locate(2000), in_root);
+    }
+    assert_eq!(tree.validate(), Ok(()));
+
+    // 已有节点改为固定的中间层
+    let ab = tree.get(7).unwrap().0;
+    let natural = tree.get_layer(&ab);
+    assert!(natural > 1);
+    assert!(tree.set_layer(7, 1));
+    assert!(!tree.set_layer(5000, 1));
+    match tree.locate(7) {
+        Some(Location::Branch { branch, layer, .. }) => {
+            assert_eq!(layer, 1);
+            assert!(tree.slab.get(branch).unwrap().layer <= 1);
+        }
+        r => panic!("{:?}", r),
+    }
+    // 比按大小计算的层更深时限制为计算的层
+    assert!(tree.set_layer(7, 100));
+    assert!(matches!(tree.locate(7), Some(Location::Branch { layer, .. }) if layer == natural));
+    // 变大时退到按大小计算的较浅层，变回原大小后回到指定的层
+    assert!(natural > 2);
+    assert!(tree.set_layer(7, 2));
+    let big = Aabb::new(ab.mins, ab.mins + Vector2::new(300f32, 300f32));
+    assert!(tree.get_layer(&big) < 2);
+    assert!(tree.update(7, big));
+    assert!(
+        matches!(tree.locate(7), Some(Location::Branch { layer, .. }) if layer == tree.get_layer(&big))
+    );
+    assert!(tree.update(7, ab));
+    assert!(matches!(tree.locate(7), Some(Location::Branch { layer, .. }) if layer == 2));
+    assert!(tree.set_layer(7, 0));
+    assert_eq!(tree.locate(7), in_root);
+    // 取消固定后按大小重新放置
+    assert!(tree.unpin(7));
+    assert!(matches!(tree.locate(7), Some(Location::Branch { layer, .. }) if layer == natural));
+    let region = Aabb::new(ab.mins, ab.maxs);
+    assert!(tree.query_collect(&region).ids().contains(&7));
+    assert_eq!(tree.validate(), Ok(()));
+    // 扩大根空间时保留指定的层，而不是变大时退到的层
+    assert!(tree.set_layer(7, 2));
+    assert!(tree.update(7, big));
+    assert!(tree.grow_root(Aabb::new(
+        Point2::new(0f32, 0f32),
+        Point2::new(2000f32, 2000f32)
+    )));
+    assert_eq!(tree.is_pinned(7), Some(true));
+    assert!(tree.update(7, ab));
+    assert!(matches!(tree.locate(7), Some(Location::Branch { layer, .. }) if layer == 2));
+    assert_eq!(tree.validate(), Ok(()));
+}
+
+#[test]
+fn test_add_batch_branch_churn() {
+    use crate::tree::CollectReport;
+    use pcg_rand::Pcg32;
+    use rand::{Rng, SeedableRng};
+
+    let new_tree = || -> QuadTree<usize, usize> {
+        QuadTree::new(
+            Aabb::new(
+                Point2::new(-1024f32, -1024f32),
+                Point2::new(4096f32, 4096f32),
+            ),
+            Vector2::new(1024f32, 1024f32),
+            Vector2::new(10f32, 10f32),
+            0,
+            0,
+            0,
+        )
+    };
+    let mut rng = Pcg32::seed_from_u64(1011);
+    let mut abs = Vec::new();
+    for i in 0..20000 {
+        let x = rng.gen_range(-1500f32..4500f32);
+        let y = rng.gen_range(-1500f32..4500f32);
+        let w = rng.gen_range(0.0..100f32);
+        let h = rng.gen_range(0.0..100f32);
+        abs.push((
+            i,
+            Aabb::new(Point2::new(x, y), Point2::new(x + w, y + h)),
+            i,
+        ));
+    }
+    // 逐个添加，每次添加后都整理，相当于自动整理的阈值为1
+    let mut single = new_tree();
+    single.set_auto_collect(usize::MAX);
+    let mut single_report = CollectReport::default();
+    let mut single_collects = 0;
+    for (id, ab, bind) in abs.iter() {
+        single.add(*id, *ab, *bind);
+        let r = single.collect_with_report();
+        if r != CollectReport::default() {
+            single_collects += 1;
+        }
+        single_report += r;
+    }
+    assert_eq!(
+        single_report.branches_created - single_report.branches_destroyed,
+        single.stats().branch_count - 1
+    );
+    // add_batch只整理一次，创建的分支节点相同，但节点随分裂逐层下移，重新放置的次数更多
+    let mut batch = new_tree();
+    let (count, batch_report) = batch.add_batch_with_report(abs.iter().cloned());
+    assert_eq!(count, abs.len());
+    assert!(single_collects > 1);
+    assert_eq!(
+        batch_report.branches_created,
+        single_report.branches_created
+    );
+    assert_eq!(batch_report.branches_destroyed, 0);
+    assert_eq!(single_report.branches_destroyed, 0);
+    assert!(batch_report.nodes_relocated > single_report.nodes_relocated);
+    assert_eq!(batch.stats().branch_count, single.stats().branch_count);
+}
+
+#[test]
+fn test_inactive_skipped() {
+    use crate::tree::QueryAction;
+
+    fn pairs(tree: &QuadTree<usize, usize>) -> Vec<(usize, usize)> {
+        fn func(
+            arg: &mut Vec<(usize, usize)>,
+            a: usize,
+            _: &Aabb,
+            _: &usize,
+            b: usize,
+            _: &Aabb,
+            _: &usize,
+        ) {
+            arg.push((a.min(b), a.max(b)));
+        }
+        let mut result = Vec::new();
+        tree.collision_pairs(&mut result, func);
+        result.sort();
+        result
+    }
+    fn first(_: &mut (), id: usize, _: &Aabb, _: &usize) -> bool {
+        id == 9
+    }
+    fn accept(_: &mut (), _: usize, _: &Aabb, _: &usize) -> QueryAction {
+        QueryAction::Accept
+    }
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    for i in 0..10 {
+        let x = i as f32 * 10.0;
+        tree.add(
+            i,
+            Aabb::new(Point2::new(x, 0f32), Point2::new(x + 2.0, 2f32)),
+            i,
+        );
+    }
+    // 10和9相交，11和12相交
+    let boxes = [
+        ((91f32, 1f32), (93f32, 3f32)),
+        ((0f32, 50f32), (5f32, 55f32)),
+        ((3f32, 53f32), (8f32, 58f32)),
+    ];
+    for (i, ((x1, y1), (x2, y2))) in boxes.iter().enumerate() {
+        tree.add(
+            10 + i,
+            Aabb::new(Point2::new(*x1, *y1), Point2::new(*x2, *y2)),
+            10 + i,
+        );
+    }
+    let all = Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32));
+    let near = Point2::new(90.5f32, 1f32);
+    let far = Point2::new(0f32, 100f32);
+    assert_eq!(tree.query_count(&all, intersects), 13);
+    assert_eq!(tree.k_nearest(&near, 1)[0].0, 9);
+    assert_eq!(tree.query_farthest(&far).unwrap().0, 9);
+    assert_eq!(pairs(&tree), vec![(9, 10), (11, 12)]);
+    assert_eq!(tree.query_first(&all, intersects, &mut (), first), Some(9));
+
+    assert!(tree.set_active(9, false));
+    assert_eq!(tree.query_first(&all, intersects, &mut (), first), None);
+    assert!(!tree
+        .query_accept(&all, intersects, &mut (), accept)
+        .contains(&9));
+    #[cfg(feature = "std")]
+    {
+        let (sender, receiver) = std::sync::mpsc::sync_channel(32);
+        assert!(tree.query_stream(&all, intersects, &sender));
+        drop(sender);
+        let streamed: Vec<usize> = receiver.iter().map(|(id, _, _)| id).collect();
+        assert_eq!(streamed.len(), 12);
+        assert!(!streamed.contains(&9));
+    }
+    let mut out = [usize::MAX; 12];
+    assert_eq!(tree.query_into(&all, intersects, &mut out), (12, false));
+    assert!(!out.contains(&9));
+    assert_eq!(tree.query_count(&all, intersects), 12);
+    let nearest = tree.k_nearest(&near, 13);
+    assert_eq!(nearest.len(), 12);
+    assert_eq!(nearest[0].0, 10);
+    assert!(nearest.iter().all(|(id, _)| *id != 9));
+    assert_eq!(tree.two_nearest(&near), Some((10, 8)));
+    assert_eq!(tree.query_farthest(&far).unwrap().0, 10);
+    assert_eq!(pairs(&tree), vec![(11, 12)]);
+    let mut visited = Vec::new();
+    tree.for_each_in_mut(&all, |id, _, _| visited.push(id));
+    assert_eq!(visited.len(), 12);
+    assert!(!visited.contains(&9));
+    // 放置检查仍然把未激活的节点算作占用
+    let ab = tree.get(9).unwrap().0;
+    assert!(tree.would_overlap(&ab));
+}
+
+#[test]
+fn test_changed_regions_relocate() {
+    let mut tree: QuadTree<usize, usize> = QuadTree::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1024f32, 1024f32)),
+        Vector2::new(100f32, 100f32),
+        Vector2::new(1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    let ab = |x: f32, y: f32| Aabb::new(Point2::new(x, y), Point2::new(x + 2.0, y + 2.0));
+    // 左下象限的4个子空间各2个节点，分裂出1个分支节点
+    let points = [
+        (100f32, 100f32),
+        (300f32, 100f32),
+        (100f32, 300f32),
+        (300f32, 300f32),
+    ];
+    for (i, (x, y)) in points.iter().enumerate() {
+        tree.add(i * 2, ab(*x, *y), i * 2);
+        tree.add(i * 2 + 1, ab(x + 10.0, y + 10.0), i * 2 + 1);
+    }
+    tree.collect();
+    let branch = tree.ab_map.get(0).unwrap().parent;
+    assert_ne!(branch, tree.root_key);
+    let branch_aabb = tree.slab.get(branch).unwrap().aabb;
+    let root_aabb = tree.slab.get(tree.root_key).unwrap().aabb;
+    tree.set_track_changes(true);
+
+    // 跨越分支空间的边界，记录原来及现在所在的分支空间
+    assert!(tree.update(0, ab(800f32, 800f32)));
+    tree.collect();
+    assert_eq!(tree.stats().branch_count, 2);
+    assert_eq!(tree.take_changed_regions(), vec![branch_aabb, root_aabb]);
+    // 移回原来的分支空间
+    assert!(tree.shift(0, Vector2::new(-700f32, -700f32)));
+    assert_eq!(tree.take_changed_regions(), vec![root_aabb, branch_aabb]);
+    // 同一分支空间内换子空间，只记录该分支空间
+    assert!(tree.update(0, ab(450f32, 100f32)));
+    assert_eq!(tree.take_changed_regions(), vec![branch_aabb]);
+    // 在同一子空间内移动不记录
+    assert!(tree.shift(0, Vector2::new(5f32, 5f32)));
+    tree.collect();
+    assert!(tree.take_changed_regions().is_empty());
+    // 移出根空间时只记录原来的分支空间
+    assert!(tree.update(0, ab(2000f32, 2000f32)));
+    assert_eq!(tree.take_changed_regions(), vec![branch_aabb]);
+}
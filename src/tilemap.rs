@@ -11,8 +11,14 @@ use parry2d::math::Real;
 use pi_link_list::{Iter, LinkList, Node};
 use pi_null::*;
 use pi_slotmap::*;
+use alloc::collections::BinaryHeap;
+use crate::tree::Priority;
+#[cfg(not(feature = "std"))]
+use alloc::{vec, vec::Vec};
 
 type List<K, T> = LinkList<K, T, SecondaryMap<K, Node<K, T>>>;
+/// 瓦片内节点的迭代器
+pub type TileIter<'a, K, T> = Iter<'a, K, (Aabb, T), SecondaryMap<K, Node<K, (Aabb, T)>>>;
 
 pub struct MapInfo {
     // 场景的范围
@@ -27,6 +33,17 @@ pub struct MapInfo {
     size: Vector2<Real>,
 }
 impl MapInfo {
+    /// 场景的总大小
+    pub fn size(&self) -> Vector2<Real> {
+        self.size
+    }
+    /// 单个瓦片的世界空间大小，宽高可以不同
+    pub fn cell_size(&self) -> Vector2<Real> {
+        Vector2::new(
+            self.size.x / self.width as Real,
+            self.size.y / self.height as Real,
+        )
+    }
     /// 计算指定位置的瓦片坐标
     pub fn calc_tile_index(&self, loc: Point2<Real>) -> (usize, usize) {
         let x = if loc[0] <= self.bounds.mins[0] {
@@ -45,6 +62,17 @@ impl MapInfo {
         };
         (x, y)
     }
+    /// 计算指定位置的瓦片坐标，超出地图边界则返回None，而不是返回最近的边界瓦片
+    pub fn calc_tile_index_strict(&self, loc: Point2<Real>) -> Option<(usize, usize)> {
+        if loc[0] < self.bounds.mins[0]
+            || loc[0] > self.bounds.maxs[0]
+            || loc[1] < self.bounds.mins[1]
+            || loc[1] > self.bounds.maxs[1]
+        {
+            return None;
+        }
+        Some(self.calc_tile_index(loc))
+    }
     /// 获得指定坐标瓦片的tile_index
     pub fn tile_index(&self, x: usize, y: usize) -> usize {
         y * self.width + x
@@ -53,6 +81,57 @@ impl MapInfo {
     pub fn tile_xy(&self, tile_index: usize) -> (usize, usize) {
         (tile_index % self.width, tile_index / self.width)
     }
+    /// 获得指定瓦片在世界空间的aabb，是calc_tile_index的逆运算，边缘的瓦片延伸到地图边界
+    pub fn tile_aabb(&self, tile_index: usize) -> Aabb {
+        let (x, y) = self.tile_xy(tile_index);
+        let edge = |i: usize, count: usize, axis: usize| {
+            if i >= count {
+                self.bounds.maxs[axis]
+            } else {
+                self.bounds.mins[axis] + i as Real * self.size[axis] / count as Real
+            }
+        };
+        Aabb::new(
+            Point2::new(edge(x, self.width, 0), edge(y, self.height, 1)),
+            Point2::new(edge(x + 1, self.width, 0), edge(y + 1, self.height, 1)),
+        )
+    }
+    /// 获得指定瓦片上下左右4个方向的相邻瓦片，超出地图边界的被忽略
+    pub fn neighbors_4(&self, tile_index: usize) -> impl Iterator<Item = usize> + '_ {
+        self.neighbors(tile_index, &[(0, -1), (-1, 0), (1, 0), (0, 1)])
+    }
+    /// 获得指定瓦片周围8个方向的相邻瓦片，超出地图边界的被忽略
+    pub fn neighbors_8(&self, tile_index: usize) -> impl Iterator<Item = usize> + '_ {
+        self.neighbors(
+            tile_index,
+            &[
+                (-1, -1),
+                (0, -1),
+                (1, -1),
+                (-1, 0),
+                (1, 0),
+                (-1, 1),
+                (0, 1),
+                (1, 1),
+            ],
+        )
+    }
+    // 获得指定瓦片按偏移量的相邻瓦片
+    fn neighbors(
+        &self,
+        tile_index: usize,
+        offsets: &'static [(isize, isize)],
+    ) -> impl Iterator<Item = usize> + '_ {
+        let (x, y) = self.tile_xy(tile_index);
+        offsets.iter().filter_map(move |(dx, dy)| {
+            let (nx, ny) = (x as isize + dx, y as isize + dy);
+            if nx < 0 || ny < 0 || nx >= self.width as isize || ny >= self.height as isize {
+                None
+            } else {
+                Some(self.tile_index(nx as usize, ny as usize))
+            }
+        })
+    }
 }
 
 ///
@@ -72,6 +151,13 @@ pub struct TileMap<K: Key, T> {
     pub info: MapInfo,
     // 节点的最大半径
     pub node_max_half_size: Vector2<Real>,
+    // x、y方向上半径等于最大半径的节点数量，减为0时才需要重新扫描
+    max_half_size_count: (usize, usize),
+    // 重新扫描最大半径的次数，用于测试
+    #[cfg(test)]
+    rescan_count: usize,
+    // 严格模式，中心点超出地图边界的节点不能添加或移动
+    strict: bool,
 }
 
 impl<K: Key, T> TileMap<K, T> {
@@ -96,25 +182,91 @@ impl<K: Key, T> TileMap<K, T> {
             tiles,
             info,
             node_max_half_size: Vector2::zeros(),
+            max_half_size_count: (0, 0),
+            #[cfg(test)]
+            rescan_count: 0,
+            strict: false,
         }
     }
+    /// 获得是否为严格模式
+    pub fn get_strict(&self) -> bool {
+        self.strict
+    }
+    /// 设置严格模式，严格模式下中心点超出地图边界的节点，添加或移动时返回false，而不是放到最近的边界瓦片
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+    // 严格模式下，判断中心点是否超出地图边界
+    fn is_out_of_bounds(&self, loc: Point2<Real>) -> bool {
+        self.strict && self.info.calc_tile_index_strict(loc).is_none()
+    }
+    /// 获得瓦片图总的占有内存的字节数，按容量而不是长度计算
+    /// + 包括：TileMap结构体本身，tiles的所有瓦片链表头，ab_map的所有节点
+    /// + 每个槽位按值的大小加上u32的版本号估算，不包括绑定T在堆上分配的内存
+    pub fn mem_size(&self) -> usize {
+        ::core::mem::size_of::<Self>()
+            + self.tiles.capacity() * ::core::mem::size_of::<List<K, (Aabb, T)>>()
+            + self.ab_map.capacity()
+                * (::core::mem::size_of::<Node<K, (Aabb, T)>>() + ::core::mem::size_of::<u32>())
+    }
     /// 获得节点最大半径
     pub fn get_node_max_half_size(&self) -> &Vector2<Real> {
         &self.node_max_half_size
     }
     /// 设置节点最大半径
+    /// + 决定最大半径的节点被移除或缩小后，最大半径会按剩余节点重新计算
     pub fn set_node_max_half_size(&mut self, half_size: Vector2<Real>) {
         self.node_max_half_size = half_size;
+        let mut count = (0, 0);
+        for (_, node) in self.ab_map.iter() {
+            let size = node.0.half_extents();
+            count.0 += (size.x == half_size.x) as usize;
+            count.1 += (size.y == half_size.y) as usize;
+        }
+        self.max_half_size_count = count;
     }
     /// 更新节点最大半径
     fn update_node_max_half_size(&mut self, aabb: Aabb) {
-        let size = aabb.half_extents();
-        if size.x > self.node_max_half_size.x {
-            self.node_max_half_size.x = size.x;
+        count_max_half_size(
+            &mut self.node_max_half_size,
+            &mut self.max_half_size_count,
+            aabb.half_extents(),
+        );
+    }
+    // 节点被移除或缩小后，如果等于最大半径的节点都没有了，则重新扫描所有节点计算最大半径
+    // 移动节点时，新的半径先计入，所以大小不变的节点不会引起扫描
+    fn shrink_node_max_half_size(&mut self, old: Vector2<Real>) {
+        if self.release_node_max_half_size(old) {
+            self.rescan_node_max_half_size();
+        }
+    }
+    // 从最大半径的计数中减去原半径，返回是否需要重新扫描
+    fn release_node_max_half_size(&mut self, old: Vector2<Real>) -> bool {
+        let count = &mut self.max_half_size_count;
+        let mut rescan = false;
+        if old.x == self.node_max_half_size.x {
+            count.0 = count.0.saturating_sub(1);
+            rescan |= count.0 == 0;
         }
-        if size.y > self.node_max_half_size.y {
-            self.node_max_half_size.y = size.y;
+        if old.y == self.node_max_half_size.y {
+            count.1 = count.1.saturating_sub(1);
+            rescan |= count.1 == 0;
         }
+        rescan
+    }
+    // 重新扫描所有节点计算最大半径及其计数，代价为O(节点数量)，换取查询时更小的扩大范围
+    fn rescan_node_max_half_size(&mut self) {
+        #[cfg(test)]
+        {
+            self.rescan_count += 1;
+        }
+        let mut max = Vector2::zeros();
+        let mut count = (0, 0);
+        for (_, node) in self.ab_map.iter() {
+            count_max_half_size(&mut max, &mut count, node.0.half_extents());
+        }
+        self.node_max_half_size = max;
+        self.max_half_size_count = count;
     }
     /// 获得指定位置的瓦片，超出地图边界则返回最近的边界瓦片
     pub fn get_tile_index(&self, loc: Point2<Real>) -> usize {
@@ -132,8 +284,8 @@ impl<K: Key, T> TileMap<K, T> {
         let list = &self.tiles[tile_index];
         (list.len(), list.iter(&self.ab_map))
     }
-    /// 获得指定范围的tile数量和迭代器
-    pub fn query_iter(&self, aabb: &Aabb) -> (usize, QueryIter) {
+    /// 获得指定范围扩大节点最大半径后，所覆盖瓦片的起止坐标(x_start, y_start, x_end, y_end)
+    fn query_band(&self, aabb: &Aabb) -> (usize, usize, usize, usize) {
         // 获得min所在瓦片
         let (x_start, y_start) = self
             .info
@@ -142,6 +294,11 @@ impl<K: Key, T> TileMap<K, T> {
         let (x_end, y_end) = self
             .info
             .calc_tile_index(aabb.maxs + self.node_max_half_size);
+        (x_start, y_start, x_end, y_end)
+    }
+    /// 获得指定范围的tile数量和迭代器
+    pub fn query_iter(&self, aabb: &Aabb) -> (usize, QueryIter) {
+        let (x_start, y_start, x_end, y_end) = self.query_band(aabb);
         (
             (x_end - x_start + 1) * (y_end - y_start + 1),
             QueryIter {
@@ -154,6 +311,79 @@ impl<K: Key, T> TileMap<K, T> {
             },
         )
     }
+    /// 获得指定范围的tile数量和瓦片坐标(x, y)的迭代器
+    pub fn query_iter_xy(&self, aabb: &Aabb) -> (usize, impl Iterator<Item = (usize, usize)>) {
+        let (count, it) = self.query_iter(aabb);
+        let width = self.info.width;
+        (
+            count,
+            it.map(move |tile_index| (tile_index % width, tile_index / width)),
+        )
+    }
+    /// 获得aabb按motion移动一帧扫过的所有瓦片，包括起止位置及中间经过的瓦片，和query_iter一样扩大节点最大半径
+    /// + 逐行计算扫掠体和该行相交的时间区间，再得到该时间区间内aabb在该行覆盖的x范围
+    /// + motion为0时，和query_iter的瓦片相同
+    pub fn swept_tiles(&self, aabb: &Aabb, motion: Vector2<Real>) -> impl Iterator<Item = usize> {
+        let mins = aabb.mins - self.node_max_half_size;
+        let maxs = aabb.maxs + self.node_max_half_size;
+        let (_, y_start) = self
+            .info
+            .calc_tile_index(mins + motion.inf(&Vector2::zeros()));
+        let (_, y_end) = self
+            .info
+            .calc_tile_index(maxs + motion.sup(&Vector2::zeros()));
+        let tile_h = self.info.cell_size().y;
+        let mut tiles = Vec::new();
+        for y in y_start..=y_end {
+            // 边界行向外无限延伸，和calc_tile_index的处理一致
+            let y0 = if y == 0 {
+                Real::NEG_INFINITY
+            } else {
+                self.info.bounds.mins.y + y as Real * tile_h
+            };
+            let y1 = if y == self.info.height - 1 {
+                Real::INFINITY
+            } else {
+                self.info.bounds.mins.y + (y + 1) as Real * tile_h
+            };
+            // aabb和该行相交的时间区间[t0, t1]
+            let (t0, t1) = if motion.y > 0.0 {
+                ((y0 - maxs.y) / motion.y, (y1 - mins.y) / motion.y)
+            } else if motion.y < 0.0 {
+                ((y1 - mins.y) / motion.y, (y0 - maxs.y) / motion.y)
+            } else if mins.y <= y1 && maxs.y >= y0 {
+                (0.0, 1.0)
+            } else {
+                continue;
+            };
+            let (t0, t1) = (t0.max(0.0), t1.min(1.0));
+            if t0 > t1 {
+                continue;
+            }
+            let (d0, d1) = (motion.x * t0, motion.x * t1);
+            let (x_start, _) = self
+                .info
+                .calc_tile_index(Point2::new(mins.x + d0.min(d1), mins.y));
+            let (x_end, _) = self
+                .info
+                .calc_tile_index(Point2::new(maxs.x + d0.max(d1), mins.y));
+            for x in x_start..=x_end {
+                tiles.push(self.info.tile_index(x, y));
+            }
+        }
+        tiles.into_iter()
+    }
+    /// 获得指定范围的瓦片迭代器，返回瓦片坐标及该瓦片的节点迭代器(x, y, iter)
+    pub fn query_tiles<'a>(
+        &'a self,
+        aabb: &Aabb,
+    ) -> impl Iterator<Item = (usize, usize, TileIter<'a, K, T>)> {
+        let (_, it) = self.query_iter_xy(aabb);
+        it.map(move |(x, y)| {
+            let list = &self.tiles[self.info.tile_index(x, y)];
+            (x, y, list.iter(&self.ab_map))
+        })
+    }
     /// 查询空间内及相交的ab节点
     pub fn query<A>(
         &self,
@@ -170,9 +400,324 @@ impl<K: Key, T> TileMap<K, T> {
         }
     }
 
+    /// 查询和指定范围真正相交的节点的id
+    /// + 瓦片范围内的节点还要精确判断aabb是否相交，每个节点按中心点只在一个瓦片中，所以id不会重复
+    pub fn query_ids(&self, aabb: &Aabb) -> Vec<K> {
+        let mut ids = Vec::new();
+        let (_, tile_it) = self.query_iter(aabb);
+        for tile_index in tile_it {
+            let (_, it) = self.get_tile_iter(tile_index);
+            for (id, node) in it {
+                if aabb.intersects(&node.0) {
+                    ids.push(id);
+                }
+            }
+        }
+        ids
+    }
+
+    /// 查询空间内及相交的ab节点，绑定不满足pred的节点会被跳过，不会回调ab_func
+    pub fn query_filtered<A>(
+        &self,
+        aabb: &Aabb,
+        pred: impl Fn(&T) -> bool,
+        arg: &mut A,
+        ab_func: fn(arg: &mut A, id: K, aabb: &Aabb, bind: &T),
+    ) {
+        let (_, tile_it) = self.query_iter(aabb);
+        for tile_index in tile_it {
+            let (_, it) = self.get_tile_iter(tile_index);
+            for (id, node) in it {
+                if pred(&node.1) {
+                    ab_func(arg, id, &node.0, &node.1);
+                }
+            }
+        }
+    }
+
+    /// 查询范围从old_aabb变为new_aabb时，进入和离开查询范围的ab节点
+    /// + 只遍历两个范围所覆盖瓦片的对称差，新增瓦片内的节点回调added_func，移出瓦片内的节点回调removed_func
+    pub fn query_delta<A, B>(
+        &self,
+        old_aabb: &Aabb,
+        new_aabb: &Aabb,
+        added_arg: &mut A,
+        added_func: fn(arg: &mut A, id: K, aabb: &Aabb, bind: &T),
+        removed_arg: &mut B,
+        removed_func: fn(arg: &mut B, id: K, aabb: &Aabb, bind: &T),
+    ) {
+        let old = self.query_band(old_aabb);
+        let new = self.query_band(new_aabb);
+        self.query_band_diff(new, old, added_arg, added_func);
+        self.query_band_diff(old, new, removed_arg, removed_func);
+    }
+    // 遍历在瓦片范围a内，但不在瓦片范围b内的瓦片中的节点
+    fn query_band_diff<A>(
+        &self,
+        a: (usize, usize, usize, usize),
+        b: (usize, usize, usize, usize),
+        arg: &mut A,
+        ab_func: fn(arg: &mut A, id: K, aabb: &Aabb, bind: &T),
+    ) {
+        for y in a.1..=a.3 {
+            for x in a.0..=a.2 {
+                if x >= b.0 && x <= b.2 && y >= b.1 && y <= b.3 {
+                    continue;
+                }
+                for (id, node) in self.tiles[self.info.tile_index(x, y)].iter(&self.ab_map) {
+                    ab_func(arg, id, &node.0, &node.1);
+                }
+            }
+        }
+    }
+
+    /// 查询离指定点最近的节点，距离为点到节点aabb的最近距离，距离相同则返回key最小的节点
+    /// + 从点所在瓦片开始逐圈向外搜索，找到节点后，直到下一圈不可能更近时停止
+    pub fn nearest(&self, point: Point2<Real>) -> Option<K> {
+        let (cx, cy) = self.info.calc_tile_index(point);
+        let cell = self.info.cell_size();
+        let (tile_w, tile_h) = (cell.x, cell.y);
+        let max_ring = (self.info.width.max(self.info.height)) as isize;
+        let mut best: Option<(Real, K)> = None;
+        for r in 0..max_ring {
+            if let Some((dist, _)) = best {
+                // 第r圈瓦片内节点中心点到点至少间隔r-1个瓦片，再减去节点最大半径
+                let gap_x = (r - 1) as Real * tile_w - self.node_max_half_size.x;
+                let gap_y = (r - 1) as Real * tile_h - self.node_max_half_size.y;
+                let gap = gap_x.min(gap_y);
+                if gap > 0.0 && gap * gap > dist {
+                    break;
+                }
+            }
+            self.nearest_ring(
+                cx as isize,
+                cy as isize,
+                r,
+                &point,
+                &mut |d, id| match best {
+                    Some((dist, key)) if (dist, key) <= (d, id) => (),
+                    _ => best = Some((d, id)),
+                },
+            );
+        }
+        best.map(|(_, id)| id)
+    }
+
+    /// 查询离指定点最近的k个节点，按aabb到点的距离从近到远返回，距离相同的id小的在前
+    /// + 从点所在的瓦片向外逐圈查找，用大根堆保留当前最近的k个节点
+    /// + 下一圈瓦片内节点的最近可能距离（扣除节点最大半径）超过第k近的距离时停止
+    pub fn k_nearest(&self, point: Point2<Real>, k: usize) -> Vec<K> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let (cx, cy) = self.info.calc_tile_index(point);
+        let cell = self.info.cell_size();
+        let (tile_w, tile_h) = (cell.x, cell.y);
+        let max_ring = (self.info.width.max(self.info.height)) as isize;
+        let mut heap: BinaryHeap<(Priority<Real, ()>, K)> = BinaryHeap::with_capacity(k + 1);
+        for r in 0..max_ring {
+            if heap.len() == k {
+                let gap_x = (r - 1) as Real * tile_w - self.node_max_half_size.x;
+                let gap_y = (r - 1) as Real * tile_h - self.node_max_half_size.y;
+                let gap = gap_x.min(gap_y);
+                if gap > 0.0 && gap * gap > heap.peek().unwrap().0 .0 {
+                    break;
+                }
+            }
+            self.nearest_ring(cx as isize, cy as isize, r, &point, &mut |d, id| {
+                heap.push((Priority(d, ()), id));
+                if heap.len() > k {
+                    heap.pop();
+                }
+            });
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|(_, id)| id)
+            .collect()
+    }
+
+    /// 查询指定范围覆盖的瓦片中，节点数量最少的k个瓦片，返回(瓦片索引, 节点数量)
+    /// + 按节点数量从少到多排序，数量相同则瓦片索引小的在前，用于分散地放置新节点
+    pub fn emptiest_tiles_in(&self, aabb: &Aabb, k: usize) -> Vec<(usize, usize)> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let (x_start, y_start) = self.info.calc_tile_index(aabb.mins);
+        let (x_end, y_end) = self.info.calc_tile_index(aabb.maxs);
+        // 大根堆，只保留当前最空的k个瓦片
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for y in y_start..=y_end {
+            for x in x_start..=x_end {
+                let tile = self.info.tile_index(x, y);
+                heap.push((self.tiles[tile].len(), tile));
+                if heap.len() > k {
+                    heap.pop();
+                }
+            }
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|(count, tile)| (tile, count))
+            .collect()
+    }
+
+    /// 计算影响力图，返回每个瓦片累加的影响力值
+    /// + 每个节点对瓦片中心点到节点中心点距离不超过radius的瓦片，累加falloff(距离)
+    /// + 按瓦片遍历节点，每个节点只检查其中心点扩大radius后覆盖的瓦片
+    pub fn stamp_influence(&self, radius: Real, falloff: fn(dist: Real) -> Real) -> Vec<Real> {
+        let mut field = vec![0.0; self.info.amount];
+        let cell = self.info.cell_size();
+        let (tile_w, tile_h) = (cell.x, cell.y);
+        let r = Vector2::new(radius, radius);
+        for list in &self.tiles {
+            for (_, node) in list.iter(&self.ab_map) {
+                let center = node.0.center();
+                let (x_start, y_start) = self.info.calc_tile_index(center - r);
+                let (x_end, y_end) = self.info.calc_tile_index(center + r);
+                for y in y_start..=y_end {
+                    let cy = self.info.bounds.mins.y + (y as Real + 0.5) * tile_h;
+                    for x in x_start..=x_end {
+                        let cx = self.info.bounds.mins.x + (x as Real + 0.5) * tile_w;
+                        let dist = (Point2::new(cx, cy) - center).norm();
+                        if dist <= radius {
+                            field[self.info.tile_index(x, y)] += falloff(dist);
+                        }
+                    }
+                }
+            }
+        }
+        field
+    }
+
+    /// 获得圆周经过的瓦片，不包括完全在圆内或圆外的瓦片，超出地图边界的瓦片被裁剪
+    /// + 逐行计算圆和该行相交的瓦片区间，区间两端远角不在圆内的瓦片即为圆周经过的瓦片
+    pub fn circle_perimeter_tiles(
+        &self,
+        center: Point2<Real>,
+        radius: Real,
+    ) -> impl Iterator<Item = usize> {
+        let mut tiles = Vec::new();
+        if radius < 0.0 {
+            return tiles.into_iter();
+        }
+        let cell = self.info.cell_size();
+        let (tile_w, tile_h) = (cell.x, cell.y);
+        let mins = self.info.bounds.mins;
+        // 瓦片的远角到圆心的距离不小于半径，则圆周经过该瓦片
+        let crossed = |x: isize, y: isize| {
+            let x0 = mins.x + x as Real * tile_w - center.x;
+            let y0 = mins.y + y as Real * tile_h - center.y;
+            let dx = x0.abs().max((x0 + tile_w).abs());
+            let dy = y0.abs().max((y0 + tile_h).abs());
+            dx * dx + dy * dy >= radius * radius
+        };
+        let (w, h) = (self.info.width as isize, self.info.height as isize);
+        let y_start = (((center.y - radius - mins.y) / tile_h).floor() as isize).max(0);
+        let y_end = (((center.y + radius - mins.y) / tile_h).floor() as isize).min(h - 1);
+        for y in y_start..=y_end {
+            let y0 = mins.y + y as Real * tile_h;
+            let dy = (y0 - center.y).max(center.y - y0 - tile_h).max(0.0);
+            if dy > radius {
+                continue;
+            }
+            let half = (radius * radius - dy * dy).sqrt();
+            let x_start = ((center.x - half - mins.x) / tile_w).floor() as isize;
+            let x_end = ((center.x + half - mins.x) / tile_w).floor() as isize;
+            // 圆内的瓦片在区间中间连续，只需从两端向内查找
+            let mut left = x_start;
+            while left <= x_end && crossed(left, y) {
+                left += 1;
+            }
+            let mut right = x_end;
+            while right >= left && crossed(right, y) {
+                right -= 1;
+            }
+            for x in (x_start..left).chain(right + 1..=x_end) {
+                if x >= 0 && x < w {
+                    tiles.push(self.info.tile_index(x as usize, y as usize));
+                }
+            }
+        }
+        tiles.into_iter()
+    }
+    /// 获得和圆环相交的瓦片，圆环为到center的距离在inner_radius和outer_radius之间的区域，超出地图边界的瓦片被裁剪
+    /// + 内半径小于0时按0处理，外半径小于内半径时没有瓦片
+    /// + 瓦片的aabb和外圆相交，并且没有完全在内圆内（远角到圆心的距离大于内半径），则返回该瓦片
+    /// + 结果是瓦片粒度的，是保守的，返回的瓦片内可能只有部分区域在圆环内
+    pub fn ring_query_iter(
+        &self,
+        center: Point2<Real>,
+        inner_radius: Real,
+        outer_radius: Real,
+    ) -> impl Iterator<Item = usize> + '_ {
+        let r = Vector2::new(outer_radius, outer_radius).sup(&Vector2::zeros());
+        // 多取一圈瓦片，包含正好接触外圆的瓦片，由过滤条件精确判断
+        let (x_start, y_start) = self.info.calc_tile_index(center - r);
+        let (x_end, y_end) = self.info.calc_tile_index(center + r);
+        let (x_start, y_start) = (x_start.saturating_sub(1), y_start.saturating_sub(1));
+        let x_end = (x_end + 1).min(self.info.width - 1);
+        let y_end = (y_end + 1).min(self.info.height - 1);
+        let inner = inner_radius.max(0.0);
+        let (inner_sq, outer_sq) = (inner * inner, outer_radius * outer_radius);
+        // 外半径小于内半径时圆环为空
+        let empty = outer_radius < inner;
+        let info = &self.info;
+        (y_start..=y_end)
+            .flat_map(move |y| (x_start..=x_end).map(move |x| info.tile_index(x, y)))
+            .filter(move |&tile_index| {
+                let ab = info.tile_aabb(tile_index);
+                let near = (ab.mins - center)
+                    .sup(&(center - ab.maxs))
+                    .sup(&Vector2::zeros())
+                    .norm_squared();
+                let far = (ab.mins - center)
+                    .abs()
+                    .sup(&(ab.maxs - center).abs())
+                    .norm_squared();
+                !empty && near <= outer_sq && far > inner_sq
+            })
+    }
+    // 检查以(cx, cy)为中心，第r圈的瓦片内的节点，用节点aabb到点的距离的平方及id回调f
+    fn nearest_ring<F: FnMut(Real, K)>(
+        &self,
+        cx: isize,
+        cy: isize,
+        r: isize,
+        point: &Point2<Real>,
+        f: &mut F,
+    ) {
+        let (w, h) = (self.info.width as isize, self.info.height as isize);
+        for y in (cy - r).max(0)..=(cy + r).min(h - 1) {
+            // 中间行只有左右两端的瓦片在圈上
+            let step = if y == cy - r || y == cy + r || r == 0 {
+                1
+            } else {
+                2 * r
+            };
+            let mut x = cx - r;
+            while x <= cx + r {
+                if x >= 0 && x < w {
+                    let tile = self.info.tile_index(x as usize, y as usize);
+                    for (id, node) in self.tiles[tile].iter(&self.ab_map) {
+                        let d = (node.0.mins - point)
+                            .sup(&(point - node.0.maxs))
+                            .sup(&Vector2::zeros())
+                            .norm_squared();
+                        f(d, id);
+                    }
+                }
+                x += step;
+            }
+        }
+    }
+
     /// 指定id，在地图中添加一个aabb单元及其绑定
     pub fn add(&mut self, id: K, aabb: Aabb, bind: T) -> bool {
         let center = aabb.center();
+        if self.is_out_of_bounds(center) {
+            return false;
+        }
         // 获得所在瓦片
         let tile_index = self.get_tile_index(center);
         // // 不在网格范围内
@@ -191,6 +736,24 @@ impl<K: Key, T> TileMap<K, T> {
     pub fn iter(&self) -> pi_slotmap::secondary::Iter<K, Node<K, (Aabb, T)>> {
         self.ab_map.iter()
     }
+    /// 按瓦片顺序迭代所有的节点，返回(瓦片, id, aabb, 绑定)，同一瓦片的节点相邻
+    pub fn iter_by_tile(&self) -> impl Iterator<Item = (usize, K, &Aabb, &T)> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .flat_map(move |(tile_index, list)| {
+                list.iter(&self.ab_map)
+                    .map(move |(id, node)| (tile_index, id, &node.0, &node.1))
+            })
+    }
+    /// 迭代所有非空的瓦片，返回(瓦片, 节点数量, 节点列表)，跳过空瓦片，用于导出或调试地图状态
+    pub fn iter_tiles(&self) -> impl Iterator<Item = (usize, usize, &List<K, (Aabb, T)>)> {
+        self.tiles
+            .iter()
+            .enumerate()
+            .filter(|(_, list)| !list.is_empty())
+            .map(|(tile_index, list)| (tile_index, list.len(), list))
+    }
     /// 获取指定id的aabb及其绑定
     pub fn get(&self, id: K) -> Option<&(Aabb, T)> {
         match self.ab_map.get(id) {
@@ -224,6 +787,9 @@ impl<K: Key, T> TileMap<K, T> {
 
     /// 更新指定id的aabb
     pub fn update(&mut self, id: K, aabb: Aabb) -> bool {
+        if self.is_out_of_bounds(aabb.center()) {
+            return false;
+        }
         let node = match self.ab_map.get_mut(id) {
             Some(n) => n,
             _ => return false,
@@ -232,20 +798,26 @@ impl<K: Key, T> TileMap<K, T> {
         let (new_x, new_y) = self.info.calc_tile_index(aabb.center());
         // 获得原来所在瓦片的位置
         let (x, y) = self.info.calc_tile_index(node.0.center());
+        let old = node.0.half_extents();
         node.0 = aabb;
         self.move_from_to(id, x, y, new_x, new_y);
         self.update_node_max_half_size(aabb);
+        self.shrink_node_max_half_size(old);
         true
     }
 
     /// 移动指定id的相对位置
     pub fn shift(&mut self, id: K, distance: Vector2<Real>) -> bool {
+        let strict = self.strict;
         let node = match self.ab_map.get_mut(id) {
             Some(n) => n,
             _ => return false,
         };
         // 新aabb
         let aabb = Aabb::new(node.0.mins + distance, node.0.maxs + distance);
+        if strict && self.info.calc_tile_index_strict(aabb.center()).is_none() {
+            return false;
+        }
         // 获得新的所在瓦片
         let (new_x, new_y) = self.info.calc_tile_index(aabb.center());
         // 获得原来所在瓦片
@@ -256,6 +828,9 @@ impl<K: Key, T> TileMap<K, T> {
     }
     /// 移动指定id的绝对位置
     pub fn move_to(&mut self, id: K, loc: Point2<Real>) -> bool {
+        if self.is_out_of_bounds(loc) {
+            return false;
+        }
         let node = match self.ab_map.get_mut(id) {
             Some(n) => n,
             _ => return false,
@@ -297,7 +872,39 @@ impl<K: Key, T> TileMap<K, T> {
         };
         let tile_index = self.get_tile_index(node.0.center());
         self.tiles[tile_index].unlink(id, &mut self.ab_map);
-        self.ab_map.remove(id).map(|n| n.take())
+        let r = self.ab_map.remove(id).map(|n| n.take());
+        if let Some((aabb, _)) = &r {
+            self.shrink_node_max_half_size(aabb.half_extents());
+        }
+        r
+    }
+    /// 移除指定范围内的所有节点，返回被移除的节点
+    /// + 和add一致，只按节点的中心点判断是否在范围内，中心点在范围外的节点即使和范围相交也不会被移除
+    pub fn remove_region(&mut self, aabb: &Aabb) -> Vec<(K, (Aabb, T))> {
+        let mut ids = Vec::new();
+        let (_, tile_it) = self.query_iter(aabb);
+        for tile_index in tile_it {
+            for (id, node) in self.tiles[tile_index].iter(&self.ab_map) {
+                if aabb.contains_local_point(&node.0.center()) {
+                    ids.push((tile_index, id));
+                }
+            }
+        }
+        let mut result = Vec::with_capacity(ids.len());
+        let mut rescan = false;
+        for (tile_index, id) in ids {
+            self.tiles[tile_index].unlink(id, &mut self.ab_map);
+            if let Some(n) = self.ab_map.remove(id) {
+                let n = n.take();
+                rescan |= self.release_node_max_half_size(n.0.half_extents());
+                result.push((id, n));
+            }
+        }
+        // 全部移除后只扫描一次
+        if rescan {
+            self.rescan_node_max_half_size();
+        }
+        result
     }
     /// 获得指定id的所在的tile
     pub fn get_tile_index_by_id(&self, id: K) -> usize {
@@ -313,6 +920,135 @@ impl<K: Key, T> TileMap<K, T> {
     pub fn len(&self) -> usize {
         self.ab_map.len()
     }
+    /// 预留additional个节点的容量，避免大量添加时多次重新分配
+    /// + ab_map按id的索引存储，id连续分配（比如来自SlotMap）时才能避免重新分配
+    pub fn reserve(&mut self, additional: usize) {
+        self.ab_map.set_capacity(self.ab_map.len() + additional);
+    }
+    /// 获得不重新分配时能容纳的节点数量
+    pub fn capacity(&self) -> usize {
+        self.ab_map.capacity()
+    }
+}
+
+///
+/// 分层瓦片地图，由细瓦片图和粗瓦片图组成
+///
+/// + 半径的x或y超过阈值的大节点放在粗瓦片图，其余的小节点放在细瓦片图
+/// + 大节点不会扩大细瓦片图的节点最大半径，小范围查询时扫描的瓦片更少
+///
+pub struct LayeredTileMap<K: Key, T> {
+    // 存放小节点的细瓦片图
+    fine: TileMap<K, T>,
+    // 存放大节点的粗瓦片图
+    coarse: TileMap<K, T>,
+    // 大节点的半径阈值
+    threshold: Real,
+}
+
+impl<K: Key, T> LayeredTileMap<K, T> {
+    ///
+    /// 新建一个分层瓦片地图
+    ///
+    /// 需传入根节点（即全场景），细瓦片图和粗瓦片图的宽度和高度，及大节点的半径阈值
+    pub fn new(
+        bounds: Aabb,
+        fine_width: usize,
+        fine_height: usize,
+        coarse_width: usize,
+        coarse_height: usize,
+        threshold: Real,
+    ) -> Self {
+        LayeredTileMap {
+            fine: TileMap::new(bounds, fine_width, fine_height),
+            coarse: TileMap::new(bounds, coarse_width, coarse_height),
+            threshold,
+        }
+    }
+    /// 获得大节点的半径阈值
+    pub fn get_threshold(&self) -> Real {
+        self.threshold
+    }
+    /// 获得存放小节点的细瓦片图
+    pub fn fine(&self) -> &TileMap<K, T> {
+        &self.fine
+    }
+    /// 获得存放大节点的粗瓦片图
+    pub fn coarse(&self) -> &TileMap<K, T> {
+        &self.coarse
+    }
+    // 判断aabb是否为大节点
+    fn is_large(&self, aabb: &Aabb) -> bool {
+        let half = aabb.half_extents();
+        half.x > self.threshold || half.y > self.threshold
+    }
+    /// 指定id，在地图中添加一个aabb单元及其绑定
+    pub fn add(&mut self, id: K, aabb: Aabb, bind: T) -> bool {
+        if self.contains_key(id) {
+            return false;
+        }
+        if self.is_large(&aabb) {
+            self.coarse.add(id, aabb, bind)
+        } else {
+            self.fine.add(id, aabb, bind)
+        }
+    }
+    /// 获取指定id的aabb及其绑定
+    pub fn get(&self, id: K) -> Option<&(Aabb, T)> {
+        self.fine.get(id).or_else(|| self.coarse.get(id))
+    }
+    /// 检查是否包含某个key
+    pub fn contains_key(&self, id: K) -> bool {
+        self.fine.contains_key(id) || self.coarse.contains_key(id)
+    }
+    /// 更新aabb，大小跨越阈值的节点会移到另一层
+    pub fn update(&mut self, id: K, aabb: Aabb) -> bool {
+        let large = self.is_large(&aabb);
+        let (from, to) = if self.fine.contains_key(id) {
+            if !large {
+                return self.fine.update(id, aabb);
+            }
+            (&mut self.fine, &mut self.coarse)
+        } else if self.coarse.contains_key(id) {
+            if large {
+                return self.coarse.update(id, aabb);
+            }
+            (&mut self.coarse, &mut self.fine)
+        } else {
+            return false;
+        };
+        // 大小跨越了阈值，移到另一层
+        match from.remove(id) {
+            Some((_, bind)) => to.add(id, aabb, bind),
+            _ => false,
+        }
+    }
+    /// 移除指定id的aabb及其绑定
+    pub fn remove(&mut self, id: K) -> Option<(Aabb, T)> {
+        self.fine.remove(id).or_else(|| self.coarse.remove(id))
+    }
+    /// 查询空间内及相交的ab节点，合并两层的结果
+    pub fn query<A>(
+        &self,
+        aabb: &Aabb,
+        arg: &mut A,
+        ab_func: fn(arg: &mut A, id: K, aabb: &Aabb, bind: &T),
+    ) {
+        self.fine.query(aabb, arg, ab_func);
+        self.coarse.query(aabb, arg, ab_func);
+    }
+    /// 获得查询指定范围时两层总共需要扫描的瓦片数量
+    pub fn query_tile_count(&self, aabb: &Aabb) -> usize {
+        self.fine.query_iter(aabb).0 + self.coarse.query_iter(aabb).0
+    }
+    /// 获得节点数量
+    pub fn len(&self) -> usize {
+        self.fine.len() + self.coarse.len()
+    }
+    /// 是否没有节点
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
 }
 
 #[derive(Debug, Clone, Default)]
@@ -343,6 +1079,22 @@ impl Iterator for QueryIter {
     }
 }
 
+// 将节点半径计入最大半径，分别记录x、y方向上等于最大半径的节点数量
+fn count_max_half_size(max: &mut Vector2<Real>, count: &mut (usize, usize), size: Vector2<Real>) {
+    if size.x > max.x {
+        max.x = size.x;
+        count.0 = 1;
+    } else if size.x == max.x {
+        count.0 += 1;
+    }
+    if size.y > max.y {
+        max.y = size.y;
+        count.1 = 1;
+    } else if size.y == max.y {
+        count.1 += 1;
+    }
+}
+
 #[test]
 fn test1() {
     use pi_slotmap::{DefaultKey, SlotMap};
@@ -477,3 +1229,927 @@ fn test1() {
     }
     //assert_eq!(args.result(), [1, 3, 4]);
 }
+
+#[test]
+fn test_query_delta() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        10,
+        10,
+    );
+    // 每个瓦片中心放一个节点，id为瓦片索引
+    for y in 0..10 {
+        for x in 0..10 {
+            let center = Point2::new(x as f32 * 100.0 + 50.0, y as f32 * 100.0 + 50.0);
+            let half = Vector2::new(5.0, 5.0);
+            map.add(y * 10 + x, Aabb::new(center - half, center + half), 0);
+        }
+    }
+    fn ab_func(arg: &mut Vec<usize>, id: usize, _: &Aabb, _: &usize) {
+        arg.push(id);
+    }
+    // 向右平移一个瓦片，瓦片范围从x:1~4变为x:2~5
+    let old_aabb = Aabb::new(Point2::new(150.0, 150.0), Point2::new(450.0, 450.0));
+    let new_aabb = Aabb::new(Point2::new(250.0, 150.0), Point2::new(550.0, 450.0));
+    let (mut added, mut removed) = (Vec::new(), Vec::new());
+    map.query_delta(
+        &old_aabb,
+        &new_aabb,
+        &mut added,
+        ab_func,
+        &mut removed,
+        ab_func,
+    );
+    added.sort();
+    removed.sort();
+    assert_eq!(added, vec![15, 25, 35, 45]);
+    assert_eq!(removed, vec![11, 21, 31, 41]);
+
+    // 范围不变，没有进出的节点
+    let (mut added, mut removed) = (Vec::new(), Vec::new());
+    map.query_delta(
+        &old_aabb,
+        &old_aabb,
+        &mut added,
+        ab_func,
+        &mut removed,
+        ab_func,
+    );
+    assert!(added.is_empty() && removed.is_empty());
+}
+
+#[test]
+fn test_mem_size() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        10,
+        10,
+    );
+    let size = map.mem_size();
+    assert!(size >= 100 * std::mem::size_of::<List<usize, (Aabb, usize)>>());
+    for i in 0..1000 {
+        map.add(
+            i,
+            Aabb::new(Point2::new(1.0, 1.0), Point2::new(2.0, 2.0)),
+            i,
+        );
+    }
+    assert!(map.mem_size() >= size + 1000 * std::mem::size_of::<Node<usize, (Aabb, usize)>>());
+}
+
+#[test]
+fn test_query_filtered() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        10,
+        10,
+    );
+    // 绑定为阵营，偶数id为0，奇数id为1
+    for i in 0..100 {
+        let center = Point2::new(
+            (i % 10) as f32 * 100.0 + 50.0,
+            (i / 10) as f32 * 100.0 + 50.0,
+        );
+        let half = Vector2::new(5.0, 5.0);
+        map.add(i, Aabb::new(center - half, center + half), i % 2);
+    }
+    fn ab_func(arg: &mut Vec<usize>, id: usize, _: &Aabb, _: &usize) {
+        arg.push(id);
+    }
+    let aabb = Aabb::new(Point2::new(0.0, 0.0), Point2::new(350.0, 150.0));
+    let mut all = Vec::new();
+    map.query(&aabb, &mut all, ab_func);
+    let mut ids = Vec::new();
+    map.query_filtered(&aabb, |camp| *camp == 1, &mut ids, ab_func);
+    all.retain(|id| id % 2 == 1);
+    all.sort();
+    ids.sort();
+    assert!(!ids.is_empty());
+    assert_eq!(ids, all);
+}
+
+#[test]
+fn test_strict() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        10,
+        10,
+    );
+    assert_eq!(
+        map.info.calc_tile_index_strict(Point2::new(-1.0, 50.0)),
+        None
+    );
+    assert_eq!(
+        map.info.calc_tile_index_strict(Point2::new(1000.0, 1000.0)),
+        Some((9, 9))
+    );
+    let outside = Aabb::new(Point2::new(1100.0, 50.0), Point2::new(1110.0, 60.0));
+    let inside = Aabb::new(Point2::new(950.0, 50.0), Point2::new(960.0, 60.0));
+    // 非严格模式下，超出边界的节点被放到边界瓦片
+    assert!(map.add(1, outside, 1));
+    assert_eq!(map.get_tile_index_by_id(1), 9);
+
+    map.set_strict(true);
+    assert!(!map.add(2, outside, 2));
+    assert!(!map.contains_key(2));
+    assert_eq!(map.get_tile_iter(9).0, 1);
+    assert!(map.add(2, inside, 2));
+    assert!(!map.update(2, outside));
+    assert!(!map.shift(2, Vector2::new(200.0, 0.0)));
+    assert!(!map.move_to(2, Point2::new(500.0, -10.0)));
+    assert_eq!(map.get(2).unwrap().0, inside);
+    assert!(map.move_to(2, Point2::new(500.0, 500.0)));
+    assert_eq!(map.get_tile_index_by_id(2), 55);
+}
+
+#[test]
+fn test_nearest() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        10,
+        10,
+    );
+    assert_eq!(map.nearest(Point2::new(500.0, 500.0)), None);
+    // 每隔3个瓦片放一个节点，大部分瓦片为空
+    let half = Vector2::new(5.0, 5.0);
+    let mut id = 1;
+    for y in (0..10).step_by(3) {
+        for x in (0..10).step_by(3) {
+            let center = Point2::new(x as f32 * 100.0 + 50.0, y as f32 * 100.0 + 50.0);
+            map.add(id, Aabb::new(center - half, center + half), id);
+            id += 1;
+        }
+    }
+    // 一个大节点，中心在瓦片(5, 5)，但边缘延伸到瓦片(4, 4)
+    map.add(
+        id,
+        Aabb::new(Point2::new(430.0, 430.0), Point2::new(590.0, 590.0)),
+        id,
+    );
+    let brute = |p: Point2<f32>| {
+        let mut best: Option<(f32, usize)> = None;
+        for (id, node) in map.iter() {
+            let d = (node.0.mins - p)
+                .sup(&(p - node.0.maxs))
+                .sup(&Vector2::zeros())
+                .norm_squared();
+            match best {
+                Some(b) if b <= (d, id) => (),
+                _ => best = Some((d, id)),
+            }
+        }
+        best.map(|b| b.1)
+    };
+    let probes = [
+        Point2::new(50.0, 50.0),
+        Point2::new(150.0, 150.0),
+        Point2::new(420.0, 420.0),
+        Point2::new(999.0, 1.0),
+        Point2::new(760.0, 240.0),
+        Point2::new(-300.0, 500.0),
+        Point2::new(1500.0, 1500.0),
+    ];
+    for p in probes {
+        assert_eq!(map.nearest(p), brute(p), "{:?}", p);
+    }
+    assert_eq!(map.nearest(Point2::new(420.0, 420.0)), Some(id));
+    // 距离相同时返回key最小的
+    assert_eq!(map.nearest(Point2::new(200.0, 50.0)), Some(1));
+}
+
+#[test]
+fn test_remove_region() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        10,
+        10,
+    );
+    // 每个瓦片的中心放一个节点
+    let half = Vector2::new(5.0, 5.0);
+    for i in 0..100 {
+        let center = Point2::new(
+            (i % 10) as f32 * 100.0 + 50.0,
+            (i / 10) as f32 * 100.0 + 50.0,
+        );
+        map.add(i + 1, Aabb::new(center - half, center + half), i);
+    }
+    // 中心点在范围外，但aabb和范围相交
+    map.add(
+        101,
+        Aabb::new(Point2::new(80.0, 150.0), Point2::new(119.0, 160.0)),
+        101,
+    );
+    // 移除瓦片(1, 1)到(3, 3)的3x3区域
+    let region = Aabb::new(Point2::new(100.0, 100.0), Point2::new(399.0, 399.0));
+    let mut removed = map.remove_region(&region);
+    assert_eq!(removed.len(), 9);
+    assert_eq!(map.len(), 92);
+    removed.sort_by_key(|r| r.0);
+    assert_eq!(removed[0].0, 12);
+    assert_eq!(removed[0].1 .1, 11);
+    for (id, (aabb, _)) in &removed {
+        assert!(!map.contains_key(*id));
+        let (x, y) = map.info.calc_tile_index(aabb.center());
+        assert!((1..=3).contains(&x) && (1..=3).contains(&y));
+        assert_eq!(map.get_tile_iter(map.info.tile_index(x, y)).0, 0);
+    }
+    assert!(map.contains_key(101));
+    assert_eq!(map.get_tile_iter(map.info.tile_index(0, 1)).0, 2);
+    assert!(map.remove_region(&region).is_empty());
+}
+
+#[test]
+fn test_neighbors() {
+    let map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 500f32)),
+        10,
+        5,
+    );
+    let info = &map.info;
+    // 中间瓦片(4, 2)
+    let center = info.tile_index(4, 2);
+    assert_eq!(
+        info.neighbors_4(center).collect::<Vec<_>>(),
+        [14, 23, 25, 34]
+    );
+    assert_eq!(
+        info.neighbors_8(center).collect::<Vec<_>>(),
+        [13, 14, 15, 23, 25, 33, 34, 35]
+    );
+    // 上边缘瓦片(4, 0)
+    assert_eq!(info.neighbors_4(4).collect::<Vec<_>>(), [3, 5, 14]);
+    assert_eq!(info.neighbors_8(4).collect::<Vec<_>>(), [3, 5, 13, 14, 15]);
+    // 右边缘瓦片(9, 2)
+    assert_eq!(info.neighbors_4(29).collect::<Vec<_>>(), [19, 28, 39]);
+    assert_eq!(info.neighbors_8(29).count(), 5);
+    // 四个角
+    assert_eq!(info.neighbors_4(0).collect::<Vec<_>>(), [1, 10]);
+    assert_eq!(info.neighbors_8(0).collect::<Vec<_>>(), [1, 10, 11]);
+    assert_eq!(info.neighbors_4(9).collect::<Vec<_>>(), [8, 19]);
+    assert_eq!(info.neighbors_8(40).collect::<Vec<_>>(), [30, 31, 41]);
+    assert_eq!(info.neighbors_8(49).collect::<Vec<_>>(), [38, 39, 48]);
+}
+
+#[test]
+fn test_iter_by_tile() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        10,
+        10,
+    );
+    let half = Vector2::new(5.0, 5.0);
+    for i in 1..300 {
+        let center = Point2::new(((i * 37) % 1000) as f32, ((i * 91) % 1000) as f32);
+        map.add(i, Aabb::new(center - half, center + half), i * 2);
+    }
+    let items: Vec<(usize, usize, Aabb, usize)> = map
+        .iter_by_tile()
+        .map(|(tile, id, aabb, bind)| (tile, id, *aabb, *bind))
+        .collect();
+    assert_eq!(items.len(), map.len());
+    // 按瓦片分组，瓦片号不递减
+    assert!(items.windows(2).all(|w| w[0].0 <= w[1].0));
+    let mut ids: Vec<usize> = items.iter().map(|r| r.1).collect();
+    ids.sort();
+    assert_eq!(ids, (1..300).collect::<Vec<usize>>());
+    for (tile, id, aabb, bind) in items {
+        assert_eq!(map.get_tile_index_by_id(id), tile);
+        assert_eq!(map.get(id).unwrap(), &(aabb, bind));
+    }
+}
+
+#[test]
+fn test_query_ids() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        10,
+        10,
+    );
+    // 一个大节点使瓦片范围扩大
+    map.add(
+        1,
+        Aabb::new(Point2::new(500.0, 500.0), Point2::new(600.0, 600.0)),
+        1,
+    );
+    let half = Vector2::new(5.0, 5.0);
+    // 和查询范围相交
+    map.add(
+        2,
+        Aabb::new(
+            Point2::new(195.0, 150.0) - half,
+            Point2::new(195.0, 150.0) + half,
+        ),
+        2,
+    );
+    map.add(
+        3,
+        Aabb::new(
+            Point2::new(150.0, 150.0) - half,
+            Point2::new(150.0, 150.0) + half,
+        ),
+        3,
+    );
+    // 在瓦片范围内，但和查询范围不相交
+    map.add(
+        4,
+        Aabb::new(
+            Point2::new(250.0, 150.0) - half,
+            Point2::new(250.0, 150.0) + half,
+        ),
+        4,
+    );
+    map.add(
+        5,
+        Aabb::new(
+            Point2::new(150.0, 50.0) - half,
+            Point2::new(150.0, 50.0) + half,
+        ),
+        5,
+    );
+    let aabb = Aabb::new(Point2::new(100.0, 100.0), Point2::new(200.0, 200.0));
+
+    fn ab_func(arg: &mut Vec<usize>, id: usize, _: &Aabb, _: &usize) {
+        arg.push(id);
+    }
+    let mut band = Vec::new();
+    map.query(&aabb, &mut band, ab_func);
+    band.sort();
+    assert_eq!(band, [2, 3, 4, 5]);
+
+    let mut ids = map.query_ids(&aabb);
+    ids.sort();
+    assert_eq!(ids, [2, 3]);
+}
+
+#[test]
+fn test_emptiest_tiles_in() {
+    use pi_slotmap::{DefaultKey, SlotMap};
+
+    let mut map: TileMap<DefaultKey, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(100f32, 100f32)),
+        10,
+        10,
+    );
+    let mut slot_map = SlotMap::new();
+    // 瓦片(x, y)中放入x + y个节点
+    for y in 0..10 {
+        for x in 0..10 {
+            let p = Point2::new(x as f32 * 10.0 + 5.0, y as f32 * 10.0 + 5.0);
+            for _ in 0..x + y {
+                let id = slot_map.insert(());
+                map.add(id, Aabb::new(p, p), 0);
+            }
+        }
+    }
+    // 范围覆盖瓦片x: 2..=4, y: 3..=5
+    let region = Aabb::new(Point2::new(25f32, 35f32), Point2::new(45f32, 55f32));
+    let tiles = map.emptiest_tiles_in(&region, 3);
+    assert_eq!(
+        tiles,
+        vec![
+            (map.info.tile_index(2, 3), 5),
+            (map.info.tile_index(3, 3), 6),
+            (map.info.tile_index(2, 4), 6)
+        ]
+    );
+    // k大于瓦片数量，返回范围内全部瓦片
+    let tiles = map.emptiest_tiles_in(&region, 20);
+    assert_eq!(tiles.len(), 9);
+    assert_eq!(tiles.last(), Some(&(map.info.tile_index(4, 5), 9)));
+    assert!(map.emptiest_tiles_in(&region, 0).is_empty());
+}
+
+#[test]
+fn test_stamp_influence() {
+    use pi_slotmap::{DefaultKey, SlotMap};
+
+    let mut map: TileMap<DefaultKey, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(90f32, 90f32)),
+        9,
+        9,
+    );
+    let mut slot_map = SlotMap::new();
+    // 一个节点在中心瓦片(4, 4)的中心
+    let p = Point2::new(45f32, 45f32);
+    map.add(slot_map.insert(()), Aabb::new(p, p), 0);
+    let field = map.stamp_influence(25.0, |dist| 25.0 - dist);
+    let value = |x: usize, y: usize| field[map.info.tile_index(x, y)];
+
+    assert_eq!(value(4, 4), 25.0);
+    assert_eq!(value(5, 4), 15.0);
+    assert_eq!(value(4, 6), 5.0);
+    // 上下左右及对角对称
+    for (dx, dy) in [(1, 0), (2, 0), (1, 1), (2, 1)] {
+        let v = value(4 + dx, 4 + dy);
+        assert!(v > 0.0);
+        assert_eq!(value(4 - dx, 4 - dy), v);
+        assert_eq!(value(4 + dy, 4 - dx), v);
+        assert_eq!(value(4 - dy, 4 + dx), v);
+    }
+    // 超出半径的瓦片没有影响
+    assert_eq!(value(7, 4), 0.0);
+    assert_eq!(value(2, 2), 0.0);
+    assert_eq!(field.iter().filter(|v| **v > 0.0).count(), 21);
+
+    // 多个节点的影响力累加
+    map.add(slot_map.insert(()), Aabb::new(p, p), 0);
+    let field = map.stamp_influence(25.0, |dist| 25.0 - dist);
+    assert_eq!(field[map.info.tile_index(4, 4)], 50.0);
+}
+
+#[test]
+fn test_layered_tile_map() {
+    use pcg_rand::Pcg32;
+    use pi_slotmap::{DefaultKey, SlotMap};
+    use rand::{Rng, SeedableRng};
+
+    let bounds = Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32));
+    let mut layered: LayeredTileMap<DefaultKey, usize> =
+        LayeredTileMap::new(bounds, 100, 100, 10, 10, 20.0);
+    let mut single: TileMap<DefaultKey, usize> = TileMap::new(bounds, 100, 100);
+    let mut slot_map = SlotMap::new();
+    let mut rng = Pcg32::seed_from_u64(1027);
+    let mut ids = Vec::new();
+    for i in 0..500 {
+        let p = Point2::new(rng.gen_range(0f32..990f32), rng.gen_range(0f32..990f32));
+        // 大部分是小节点，少量是大建筑
+        let s = if i % 50 == 0 { 200.0 } else { 2.0 };
+        let aabb = Aabb::new(p, p + Vector2::new(s, s));
+        let id = slot_map.insert(());
+        assert!(layered.add(id, aabb, i));
+        single.add(id, aabb, i);
+        ids.push(id);
+    }
+    assert!(!layered.add(ids[0], bounds, 0));
+    assert_eq!(layered.len(), 500);
+    assert_eq!(layered.coarse().len(), 10);
+    assert!(layered.fine().get_node_max_half_size().x <= 20.0);
+
+    let query = |layered: &LayeredTileMap<DefaultKey, usize>, region: &Aabb| {
+        let mut args = (*region, Vec::new());
+        layered.query(region, &mut args, |args, id, aabb, _| {
+            if args.0.intersects(aabb) {
+                args.1.push(id);
+            }
+        });
+        args.1.sort();
+        args.1
+    };
+    let region = Aabb::new(Point2::new(400f32, 400f32), Point2::new(450f32, 450f32));
+    let mut expect = single.query_ids(&region);
+    expect.sort();
+    assert_eq!(query(&layered, &region), expect);
+    // 大节点不扩大细瓦片图的查询范围，扫描的瓦片更少
+    assert!(layered.query_tile_count(&region) < single.query_iter(&region).0);
+
+    // 跨越阈值的更新移到另一层
+    let p = Point2::new(420f32, 420f32);
+    assert!(layered.update(ids[1], Aabb::new(p, p + Vector2::new(100f32, 100f32))));
+    assert_eq!(layered.coarse().len(), 11);
+    assert!(layered.coarse().contains_key(ids[1]));
+    assert_eq!(layered.get(ids[1]).unwrap().1, 1);
+    assert!(layered.update(ids[0], Aabb::new(p, p + Vector2::new(1f32, 1f32))));
+    assert_eq!(layered.coarse().len(), 10);
+    assert!(query(&layered, &region).contains(&ids[0]));
+    assert!(query(&layered, &region).contains(&ids[1]));
+    assert_eq!(layered.remove(ids[1]).unwrap().1, 1);
+    assert!(!layered.contains_key(ids[1]));
+    assert!(!layered.update(ids[1], region));
+    assert_eq!(layered.len(), 499);
+    assert!(!layered.is_empty());
+}
+
+#[test]
+fn test_circle_perimeter_tiles() {
+    use pi_slotmap::DefaultKey;
+
+    let map: TileMap<DefaultKey, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(100f32, 100f32)),
+        10,
+        10,
+    );
+    // 逐个瓦片判断圆周是否经过：最近点在圆内且远角不在圆内
+    let brute = |center: Point2<f32>, radius: f32| {
+        let mut tiles = Vec::new();
+        for y in 0..10 {
+            for x in 0..10 {
+                let mins = Point2::new(x as f32 * 10.0, y as f32 * 10.0);
+                let maxs = mins + Vector2::new(10f32, 10f32);
+                let near = (mins - center).sup(&(center - maxs)).sup(&Vector2::zeros());
+                let far = (mins - center).abs().sup(&(maxs - center).abs());
+                if near.norm() <= radius && far.norm() >= radius {
+                    tiles.push(map.info.tile_index(x, y));
+                }
+            }
+        }
+        tiles
+    };
+    let center = Point2::new(50f32, 50f32);
+    let mut tiles: Vec<usize> = map.circle_perimeter_tiles(center, 25.0).collect();
+    tiles.sort();
+    assert_eq!(tiles, brute(center, 25.0));
+    // 圆周经过的瓦片，不包括圆内的瓦片
+    assert!(tiles.contains(&map.info.tile_index(7, 5)));
+    assert!(tiles.contains(&map.info.tile_index(2, 4)));
+    assert!(!tiles.contains(&map.info.tile_index(4, 4)));
+    assert!(!tiles.contains(&map.info.tile_index(5, 5)));
+    assert!(!tiles.contains(&map.info.tile_index(8, 5)));
+    // 每个瓦片只出现一次
+    let len = tiles.len();
+    tiles.dedup();
+    assert_eq!(tiles.len(), len);
+
+    // 超出地图边界的部分被裁剪
+    let center = Point2::new(3f32, 96f32);
+    let mut tiles: Vec<usize> = map.circle_perimeter_tiles(center, 33.3).collect();
+    tiles.sort();
+    assert_eq!(tiles, brute(center, 33.3));
+    assert!(!tiles.is_empty());
+    assert_eq!(map.circle_perimeter_tiles(center, -1.0).count(), 0);
+}
+
+#[test]
+fn test_node_max_half_size_shrink() {
+    use pi_slotmap::{DefaultKey, SlotMap};
+
+    let mut map: TileMap<DefaultKey, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        100,
+        100,
+    );
+    let mut slot_map = SlotMap::new();
+    for i in 0..20 {
+        let p = Point2::new(i as f32 * 40.0 + 10.0, 500f32);
+        map.add(
+            slot_map.insert(()),
+            Aabb::new(p, p + Vector2::new(4f32, 6f32)),
+            i,
+        );
+    }
+    assert_eq!(map.get_node_max_half_size(), &Vector2::new(2f32, 3f32));
+    let region = Aabb::new(Point2::new(100f32, 100f32), Point2::new(110f32, 110f32));
+    let tile_count = map.query_iter(&region).0;
+
+    // 添加巨大的节点后查询范围扩大
+    let huge = slot_map.insert(());
+    let p = Point2::new(300f32, 300f32);
+    map.add(huge, Aabb::new(p, p + Vector2::new(400f32, 200f32)), 100);
+    assert_eq!(map.get_node_max_half_size(), &Vector2::new(200f32, 100f32));
+    assert!(map.query_iter(&region).0 > tile_count);
+    // 移除后恢复为剩余节点的最大半径
+    map.remove(huge);
+    assert_eq!(map.get_node_max_half_size(), &Vector2::new(2f32, 3f32));
+    assert_eq!(map.query_iter(&region).0, tile_count);
+
+    // 缩小决定最大半径的节点
+    map.add(huge, Aabb::new(p, p + Vector2::new(400f32, 200f32)), 100);
+    map.update(huge, Aabb::new(p, p + Vector2::new(8f32, 2f32)));
+    assert_eq!(map.get_node_max_half_size(), &Vector2::new(4f32, 3f32));
+    // 不是决定最大半径的节点被移除，最大半径不变
+    let small = map.iter().map(|(id, _)| id).find(|id| *id != huge).unwrap();
+    map.remove(small);
+    assert_eq!(map.get_node_max_half_size(), &Vector2::new(4f32, 3f32));
+    // 按范围移除
+    map.remove_region(&Aabb::new(p, p + Vector2::new(10f32, 10f32)));
+    assert_eq!(map.get_node_max_half_size(), &Vector2::new(2f32, 3f32));
+}
+
+#[test]
+fn test_query_tiles() {
+    use pi_slotmap::{DefaultKey, SlotMap};
+
+    let mut map: TileMap<DefaultKey, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(100f32, 100f32)),
+        10,
+        10,
+    );
+    let mut slot_map = SlotMap::new();
+    // 瓦片(x, y)中放入x个节点
+    for y in 0..10 {
+        for x in 0..10 {
+            let p = Point2::new(x as f32 * 10.0 + 5.0, y as f32 * 10.0 + 5.0);
+            for _ in 0..x {
+                map.add(slot_map.insert(()), Aabb::new(p, p), y * 10 + x);
+            }
+        }
+    }
+    let region = Aabb::new(Point2::new(25f32, 35f32), Point2::new(58f32, 41f32));
+    let (count, it) = map.query_iter(&region);
+    let (count_xy, it_xy) = map.query_iter_xy(&region);
+    assert_eq!(count, 8);
+    assert_eq!(count_xy, count);
+    let band: Vec<(usize, usize)> = it.map(|tile_index| map.info.tile_xy(tile_index)).collect();
+    assert_eq!(it_xy.collect::<Vec<_>>(), band);
+
+    let mut n = 0;
+    for (i, (x, y, it)) in map.query_tiles(&region).enumerate() {
+        assert_eq!((x, y), band[i]);
+        let binds: Vec<usize> = it.map(|(_, node)| node.1).collect();
+        assert_eq!(binds.len(), x);
+        assert!(binds.iter().all(|b| *b == y * 10 + x));
+        n += 1;
+    }
+    assert_eq!(n, count);
+}
+
+#[test]
+fn test_swept_tiles() {
+    use pi_slotmap::DefaultKey;
+
+    let map: TileMap<DefaultKey, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(100f32, 100f32)),
+        10,
+        10,
+    );
+    let xy = |tiles: Vec<usize>| {
+        let mut tiles: Vec<(usize, usize)> =
+            tiles.into_iter().map(|t| map.info.tile_xy(t)).collect();
+        tiles.sort_by_key(|(x, y)| (*y, *x));
+        tiles
+    };
+    let aabb = Aabb::new(Point2::new(12f32, 12f32), Point2::new(16f32, 16f32));
+    // 没有移动，和query_iter相同
+    let (_, it) = map.query_iter(&aabb);
+    assert_eq!(
+        map.swept_tiles(&aabb, Vector2::zeros())
+            .collect::<Vec<usize>>(),
+        it.collect::<Vec<usize>>()
+    );
+    assert_eq!(
+        xy(map.swept_tiles(&aabb, Vector2::zeros()).collect()),
+        vec![(1, 1)]
+    );
+
+    // 沿对角线移动，经过对角线上的瓦片及斜穿时接触的相邻瓦片
+    let tiles = xy(map.swept_tiles(&aabb, Vector2::new(30f32, 30f32)).collect());
+    assert_eq!(
+        tiles,
+        vec![
+            (1, 1),
+            (2, 1),
+            (1, 2),
+            (2, 2),
+            (3, 2),
+            (2, 3),
+            (3, 3),
+            (4, 3),
+            (3, 4),
+            (4, 4)
+        ]
+    );
+    // 反向移动得到相同的瓦片
+    let end = Aabb::new(Point2::new(42f32, 42f32), Point2::new(46f32, 46f32));
+    assert_eq!(
+        xy(map
+            .swept_tiles(&end, Vector2::new(-30f32, -30f32))
+            .collect()),
+        tiles
+    );
+
+    // 只在行内移动，不需要经过其它行
+    let tiles = xy(map.swept_tiles(&aabb, Vector2::new(50f32, 2f32)).collect());
+    assert_eq!(tiles, (1..7).map(|x| (x, 1)).collect::<Vec<_>>());
+    // 移动到地图外，被限制在边界瓦片
+    let tiles = xy(map
+        .swept_tiles(&aabb, Vector2::new(-100f32, 0f32))
+        .collect());
+    assert_eq!(tiles, vec![(0, 1), (1, 1)]);
+}
+
+#[test]
+fn test_tile_aabb() {
+    let bounds = Aabb::new(
+        Point2::new(-123.5f32, 40f32),
+        Point2::new(877.25f32, 533f32),
+    );
+    let map: TileMap<usize, usize> = TileMap::new(bounds, 13, 7);
+    let info = &map.info;
+    for tile in 0..info.amount {
+        let ab = info.tile_aabb(tile);
+        let (x, y) = info.tile_xy(tile);
+        assert_eq!(info.calc_tile_index(ab.center()), (x, y));
+        // 相邻瓦片共享边界
+        if x + 1 < info.width {
+            assert_eq!(ab.maxs.x, info.tile_aabb(tile + 1).mins.x);
+        }
+        if y + 1 < info.height {
+            assert_eq!(ab.maxs.y, info.tile_aabb(tile + info.width).mins.y);
+        }
+    }
+    // 边缘的瓦片延伸到地图边界
+    assert_eq!(info.tile_aabb(0).mins, bounds.mins);
+    assert_eq!(info.tile_aabb(info.amount - 1).maxs, bounds.maxs);
+    assert_eq!(info.tile_aabb(info.tile_index(12, 0)).maxs.x, bounds.maxs.x);
+}
+
+#[test]
+fn test_iter_tiles() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(100f32, 100f32)),
+        10,
+        10,
+    );
+    assert_eq!(map.iter_tiles().count(), 0);
+    let tile = |x: f32, y: f32| Aabb::new(Point2::new(x, y), Point2::new(x + 1.0, y + 1.0));
+    map.add(1, tile(5.0, 5.0), 1);
+    map.add(2, tile(6.0, 4.0), 2);
+    map.add(3, tile(55.0, 75.0), 3);
+    map.add(4, tile(94.0, 94.0), 4);
+    let tiles: Vec<(usize, usize, Vec<usize>)> = map
+        .iter_tiles()
+        .map(|(tile_index, len, list)| {
+            let mut ids: Vec<usize> = list.iter(&map.ab_map).map(|(id, _)| id).collect();
+            ids.sort();
+            (tile_index, len, ids)
+        })
+        .collect();
+    assert_eq!(
+        tiles,
+        vec![(0, 2, vec![1, 2]), (75, 1, vec![3]), (99, 1, vec![4])]
+    );
+    map.remove(3);
+    assert_eq!(
+        map.iter_tiles().map(|r| r.0).collect::<Vec<usize>>(),
+        vec![0, 99]
+    );
+}
+
+#[test]
+fn test_ring_query_iter() {
+    let map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(100f32, 100f32)),
+        10,
+        10,
+    );
+    let center = Point2::new(50f32, 50f32);
+    let tiles: Vec<usize> = map.ring_query_iter(center, 15.0, 30.0).collect();
+    // 完全在内圆内的瓦片被排除
+    for (x, y) in [(4, 4), (5, 4), (4, 5), (5, 5)] {
+        assert!(!tiles.contains(&map.info.tile_index(x, y)));
+    }
+    // 和内圆相交的瓦片，及正好接触外圆的瓦片被包含
+    for (x, y) in [(3, 4), (6, 6), (7, 5), (8, 5), (2, 4)] {
+        assert!(tiles.contains(&map.info.tile_index(x, y)), "{:?}", (x, y));
+    }
+    assert!(!tiles.contains(&map.info.tile_index(9, 5)));
+    assert!(!tiles.contains(&map.info.tile_index(8, 8)));
+
+    let brute = |inner: f32, outer: f32| -> Vec<usize> {
+        (0..map.info.amount)
+            .filter(|&t| {
+                let ab = map.info.tile_aabb(t);
+                let near = (ab.mins - center)
+                    .sup(&(center - ab.maxs))
+                    .sup(&Vector2::zeros())
+                    .norm();
+                let far = (ab.mins - center)
+                    .abs()
+                    .sup(&(ab.maxs - center).abs())
+                    .norm();
+                near <= outer && far > inner
+            })
+            .collect()
+    };
+    for (inner, outer) in [
+        (0.0, 5.0),
+        (15.0, 30.0),
+        (20.0, 45.0),
+        (40.0, 200.0),
+        (0.0, 0.0),
+    ] {
+        let mut tiles: Vec<usize> = map.ring_query_iter(center, inner, outer).collect();
+        tiles.sort();
+        assert_eq!(tiles, brute(inner, outer), "{:?}", (inner, outer));
+    }
+    // 内半径大于外半径时为空
+    assert_eq!(map.ring_query_iter(center, 30.0, 20.0).count(), 0);
+}
+
+#[test]
+fn test_reserve() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(100f32, 100f32)),
+        10,
+        10,
+    );
+    let before = map.capacity();
+    map.reserve(5000);
+    let capacity = map.capacity();
+    assert!(capacity >= 5000 && capacity > before);
+    for i in 0..5000 {
+        let x = (i % 100) as f32;
+        let y = (i / 50) as f32;
+        map.add(
+            i,
+            Aabb::new(Point2::new(x, y), Point2::new(x + 0.5, y + 0.5)),
+            i,
+        );
+    }
+    assert_eq!(map.len(), 5000);
+    assert_eq!(map.capacity(), capacity);
+    // 已有节点时按剩余数量预留
+    map.reserve(100);
+    assert!(map.capacity() >= 5100);
+}
+
+#[test]
+fn test_k_nearest() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(100f32, 100f32)),
+        10,
+        10,
+    );
+    let mut boxes = Vec::new();
+    for i in 0..400 {
+        let x = (i % 20) as f32 * 5.0 + 0.3;
+        let y = (i / 20) as f32 * 5.0 + 0.7;
+        let ab = Aabb::new(Point2::new(x, y), Point2::new(x + 1.0, y + 1.0));
+        boxes.push(ab);
+        map.add(i, ab, i);
+    }
+    let brute = |p: Point2<f32>, k: usize| {
+        let mut all: Vec<(f32, usize)> = boxes
+            .iter()
+            .enumerate()
+            .map(|(i, ab)| {
+                let d = (ab.mins - p).sup(&(p - ab.maxs)).sup(&Vector2::zeros());
+                (d.norm_squared(), i)
+            })
+            .collect();
+        all.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        all.truncate(k);
+        all.into_iter().map(|(_, i)| i).collect::<Vec<usize>>()
+    };
+    for p in [
+        Point2::new(50.0, 50.0),
+        Point2::new(0.0, 0.0),
+        Point2::new(97.2, 13.4),
+        Point2::new(-20.0, 120.0),
+    ] {
+        for k in [0, 1, 3, 10, 37, 400, 500] {
+            assert_eq!(map.k_nearest(p, k), brute(p, k), "{:?}", (p, k));
+        }
+    }
+    assert_eq!(
+        map.k_nearest(Point2::new(50.0, 50.0), 1),
+        map.nearest(Point2::new(50.0, 50.0))
+            .into_iter()
+            .collect::<Vec<usize>>()
+    );
+}
+
+#[test]
+fn test_cell_size() {
+    let map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(-50f32, 10f32), Point2::new(150f32, 70f32)),
+        8,
+        3,
+    );
+    let size = map.info.size();
+    assert_eq!(size, Vector2::new(200f32, 60f32));
+    let cell = map.info.cell_size();
+    assert_eq!(cell, Vector2::new(25f32, 20f32));
+    assert_eq!(
+        cell.component_mul(&Vector2::new(map.info.width as f32, map.info.height as f32)),
+        size
+    );
+}
+
+#[test]
+fn test_node_max_half_size_no_rescan() {
+    let mut map: TileMap<usize, usize> = TileMap::new(
+        Aabb::new(Point2::new(0f32, 0f32), Point2::new(1000f32, 1000f32)),
+        100,
+        100,
+    );
+    let size = Vector2::new(4f32, 6f32);
+    for i in 0..200 {
+        let p = Point2::new((i % 20) as f32 * 40.0 + 10.0, (i / 20) as f32 * 40.0 + 10.0);
+        map.add(i, Aabb::new(p, p + size), i);
+    }
+    // 大小相同的节点每帧移动，不需要重新扫描
+    for frame in 0..10 {
+        for i in 0..200 {
+            let p = Point2::new(
+                (i % 20) as f32 * 40.0 + frame as f32,
+                (i / 20) as f32 * 40.0 + 10.0,
+            );
+            map.update(i, Aabb::new(p, p + size));
+        }
+    }
+    assert_eq!(map.rescan_count, 0);
+    assert_eq!(map.get_node_max_half_size(), &Vector2::new(2f32, 3f32));
+    // 移除部分等于最大半径的节点，还有其他节点时不需要重新扫描
+    for i in 0..199 {
+        map.remove(i);
+    }
+    assert_eq!(map.rescan_count, 0);
+    assert_eq!(map.get_node_max_half_size(), &Vector2::new(2f32, 3f32));
+    // 最大的节点只在一个方向上缩小，需要重新扫描
+    let p = Point2::new(500f32, 500f32);
+    map.add(300, Aabb::new(p, p + Vector2::new(10f32, 2f32)), 300);
+    assert_eq!(map.get_node_max_half_size(), &Vector2::new(5f32, 3f32));
+    map.update(300, Aabb::new(p, p + Vector2::new(6f32, 2f32)));
+    assert_eq!(map.rescan_count, 1);
+    assert_eq!(map.get_node_max_half_size(), &Vector2::new(3f32, 3f32));
+}
@@ -19,20 +19,36 @@
 //!         node.layer<parent.layer. node.parent_child<N
 //!     更新节点就是在这3个位置上挪动
 
-use std::mem;
+use alloc::collections::BinaryHeap;
+#[cfg(not(feature = "std"))]
+use alloc::{format, string::String, vec, vec::Vec};
+use core::cmp::{Ordering, Reverse};
+#[cfg(feature = "std")]
+use core::hash::Hash;
+use core::mem;
+use core::ops::AddAssign;
+#[cfg(feature = "std")]
+use std::collections::HashMap;
+#[cfg(feature = "std")]
+use std::sync::mpsc::SyncSender;
 
+#[cfg(feature = "fixedbitset")]
+use fixedbitset::FixedBitSet;
+use num_traits::{Float, One, Zero};
 use pi_link_list::{LinkList, Node};
 use pi_null::Null;
 use pi_slotmap::{new_key_type, Key, SecondaryMap, SlotMap};
 
 new_key_type! {
     pub struct BranchKey;
+    pub struct QueryHandle;
 }
 
 pub trait Helper<const N: usize> {
     type Point;
     type Vector: Clone;
     type Aabb: Clone;
+    type Scalar: Float;
 
     /// 获得AABB的差
     fn aabb_extents(aabb: &Self::Aabb) -> Self::Vector;
@@ -42,6 +58,24 @@ pub trait Helper<const N: usize> {
     fn aabb_contains(aabb: &Self::Aabb, other: &Self::Aabb) -> bool;
     /// 判断2个aabb是否相交
     fn aabb_intersects(aabb: &Self::Aabb, other: &Self::Aabb) -> bool;
+    /// 计算点到aabb的最近距离的平方，点在aabb内则为0
+    fn aabb_point_distance_sq(aabb: &Self::Aabb, point: &Self::Point) -> Self::Scalar;
+    /// 计算点到aabb的最远距离的平方
+    fn aabb_point_max_distance_sq(aabb: &Self::Aabb, point: &Self::Point) -> Self::Scalar;
+    /// 计算点到aabb表面的有符号距离，点在aabb内则为负
+    fn aabb_point_signed_distance(aabb: &Self::Aabb, point: &Self::Point) -> Self::Scalar;
+    /// 计算点到aabb中心的距离的平方
+    fn aabb_center_distance_sq(aabb: &Self::Aabb, point: &Self::Point) -> Self::Scalar;
+    /// 获得aabb的中心点
+    fn aabb_center(aabb: &Self::Aabb) -> Self::Point;
+    /// 获得包含2个aabb的最小aabb
+    fn aabb_merged(aabb: &Self::Aabb, other: &Self::Aabb) -> Self::Aabb;
+    /// 判断aabb是否有效，所有坐标都是有限值，并且每个轴上min不大于max
+    fn aabb_is_valid(aabb: &Self::Aabb) -> bool;
+    /// 计算2个点的距离的平方
+    fn point_distance_sq(a: &Self::Point, b: &Self::Point) -> Self::Scalar;
+    /// 在2个点之间插值，t为0时返回a，t为1时返回b
+    fn point_lerp(a: &Self::Point, b: &Self::Point, t: Self::Scalar) -> Self::Point;
     /// 计算叉树的深度
     fn get_deap(
         d: &mut Self::Vector,
@@ -73,7 +107,7 @@ pub trait Helper<const N: usize> {
     ) -> (Self::Aabb, Self::Vector);
 }
 
-const DEEP_MAX: usize = 16;
+pub(crate) const DEEP_MAX: usize = 16;
 const ADJUST_MIN: usize = 4;
 const ADJUST_MAX: usize = 8;
 const AUTO_COLLECT: usize = 1024;
@@ -83,6 +117,16 @@ type List<K, H, T, const N: usize> = LinkList<
     AbNode<<H as Helper<N>>::Aabb, T>,
     SecondaryMap<K, Node<K, AbNode<<H as Helper<N>>::Aabb, T>>>,
 >;
+/// 查询回调的返回值，同时控制是否接受该节点及是否继续查询
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QueryAction {
+    Reject,        // 不接受该节点，继续查询
+    Accept,        // 接受该节点，继续查询
+    AcceptAndStop, // 接受该节点，并停止查询
+}
+/// 碰撞对的回调函数，参数依次为：查询参数，a的id、aabb、绑定，b的id、aabb、绑定
+pub type CollisionFunc<A, K, Aabb, T> =
+    fn(arg: &mut A, a_id: K, a_aabb: &Aabb, a_bind: &T, b_id: K, b_aabb: &Aabb, b_bind: &T);
 ///
 /// 叉树结构体
 ///
@@ -94,15 +138,17 @@ type List<K, H, T, const N: usize> = LinkList<
 pub struct Tree<K: Key, H: Helper<N>, T, const N: usize> {
     pub slab: SlotMap<BranchKey, BranchNode<K, H, T, N>>, //所有分支节点（分支节点中包含该层ab节点列表）
     pub ab_map: SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>, //所有存储ab碰撞单位的节点
-    max_loose: H::Vector,                                 //最大松散值，第一层的松散大小
-    min_loose: H::Vector,                                 //最小松散值
-    root_key: BranchKey,
+    pub(crate) max_loose: H::Vector,                      //最大松散值，第一层的松散大小
+    pub(crate) min_loose: H::Vector,                      //最小松散值
+    pub(crate) root_key: BranchKey,
     pub outer: List<K, H, T, N>, // 和根空间不包含（相交或在外）的ab节点列表，及节点数量。 该AbNode的parent为Null
     pub dirty: (Vec<Vec<BranchKey>>, DirtyState), // 脏的BranchNode节点, 及脏节点状态
-    adjust: (usize, usize), //小于min，节点收缩; 大于max，节点分化。默认(4, 8)
+    pub(crate) adjust: (usize, usize), //小于min，节点收缩; 大于max，节点分化。默认(4, 8)
     loose_layer: usize,     // 最小松散值所在的深度
-    deep: usize,        // 最大深度, 推荐12-16, 最小松散值设置的好，不设置最大深度也是可以的
+    pub(crate) deep: usize, // 最大深度, 推荐12-16, 最小松散值设置的好，不设置最大深度也是可以的
     auto_collect: usize, // 自动整理的阈值，默认为1024
+    pub(crate) static_queries: SlotMap<QueryHandle, StaticQuery<K, H::Aabb>>, // 注册的静态查询，不序列化
+    changed_regions: Option<Vec<H::Aabb>>, // 整理时发生分裂或收缩，及节点移入移出的分支空间，None表示不记录，不序列化
 }
 
 impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
@@ -171,16 +217,26 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
                 },
             ),
             auto_collect: AUTO_COLLECT,
+            static_queries: SlotMap::with_key(),
+            changed_regions: None,
         };
     }
 
-    // /// 获得叉树总的占有内存的字节数
-    // pub fn mem_size(&self) -> usize {
-    //     self.slab.mem_size()
-    //         + self.ab_map.mem_size()
-    //         + self.outer.len() * std::mem::size_of::<usize>()
-
-    // }
+    /// 获得叉树总的占有内存的字节数，按容量而不是长度计算
+    /// + 包括：Tree结构体本身（含outer链表头），slab的所有分支节点，ab_map的所有ab节点，dirty的脏列表
+    /// + 每个槽位按值的大小加上u32的版本号估算，不包括绑定T在堆上分配的内存
+    pub fn mem_size(&self) -> usize {
+        let mut size = mem::size_of::<Self>()
+            + self.slab.capacity()
+                * (mem::size_of::<BranchNode<K, H, T, N>>() + mem::size_of::<u32>())
+            + self.ab_map.capacity()
+                * (mem::size_of::<Node<K, AbNode<H::Aabb, T>>>() + mem::size_of::<u32>())
+            + self.dirty.0.capacity() * mem::size_of::<Vec<BranchKey>>();
+        for vec in &self.dirty.0 {
+            size += vec.capacity() * mem::size_of::<BranchKey>();
+        }
+        size
+    }
     /// 获得自动整理的次数
     pub fn get_auto_collect(&self) -> usize {
         self.auto_collect
@@ -193,6 +249,76 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
     pub fn get_adjust(&self) -> (usize, usize) {
         (self.adjust.0, self.adjust.1)
     }
+    /// 设置节点收缩和分化的阈值，和new一样，0则使用默认值，max不小于min
+    /// + 所有分支节点被标记为脏，下次整理时按新阈值收缩或分化
+    pub fn set_adjust(&mut self, adjust_min: usize, adjust_max: usize) {
+        let adjust_min = if adjust_min == 0 {
+            ADJUST_MIN
+        } else {
+            adjust_min
+        };
+        let adjust_max = if adjust_max == 0 {
+            ADJUST_MAX
+        } else {
+            adjust_max
+        };
+        self.adjust = (adjust_min, adjust_max.max(adjust_min));
+        self.set_all_dirty();
+    }
+    /// 重新评估所有分支节点，按当前阈值立即收缩或分化
+    /// + 用于多次shift等操作后的维护，不改变查询结果，不应每帧调用
+    pub fn rebalance(&mut self) {
+        self.set_all_dirty();
+        self.collect();
+    }
+    // 将所有分支节点标记为脏，已有的脏标记可能不在脏列表中，先清除再重新放入
+    fn set_all_dirty(&mut self) {
+        for (id, branch) in self.slab.iter_mut() {
+            branch.dirty = false;
+            set_dirty(&mut branch.dirty, branch.layer, id, &mut self.dirty);
+        }
+    }
+    /// 获得叉树的最大深度
+    pub fn get_deep(&self) -> usize {
+        self.deep
+    }
+    /// 获得根空间的aabb
+    pub fn root_aabb(&self) -> &H::Aabb {
+        &unsafe { self.slab.get_unchecked(self.root_key) }.aabb
+    }
+    /// 获得最大及最小松散值
+    pub fn loose_params(&self) -> (&H::Vector, &H::Vector) {
+        (&self.max_loose, &self.min_loose)
+    }
+    /// 设置叉树的深度限制，和new一样，0或超过最大值则不限制，实际深度还受最小松散值的约束
+    /// + 深度改变后，小于最小松散值的节点所在的层随之改变，需要调用rehome_changed_layers重新放置
+    pub fn set_deep(&mut self, deep: usize) {
+        let deep = if deep > DEEP_MAX || deep == 0 {
+            DEEP_MAX
+        } else {
+            deep
+        };
+        let root = unsafe { self.slab.get_unchecked(self.root_key) };
+        let mut d = H::aabb_extents(&root.aabb);
+        self.deep = H::get_deap(
+            &mut d,
+            self.loose_layer,
+            &self.max_loose,
+            deep,
+            &self.min_loose,
+        );
+    }
+    /// 参数改变后，只重新放置层发生变化的ab节点，返回重新放置的节点数量
+    /// + 重新计算每个节点的get_layer，和记录的layer不同才重新放置，其余节点保持不动
+    pub fn rehome_changed_layers(&mut self) -> usize {
+        let changed: Vec<(K, H::Aabb)> = self
+            .ab_map
+            .iter()
+            .filter(|(_, node)| self.node_layer(node.forced_layer, &node.value.0) != node.layer)
+            .map(|(id, node)| (id, node.value.0.clone()))
+            .collect();
+        self.update_batch(changed.into_iter())
+    }
 
     /// 获得该aabb对应的层
     pub fn get_layer(&self, aabb: &H::Aabb) -> usize {
@@ -204,16 +330,47 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         H::calc_layer(&self.max_loose, &d)
     }
 
+    // 节点应在的层，指定了层的节点取指定的层和按aabb大小计算的层中较浅的
+    fn node_layer(&self, forced_layer: Option<usize>, aabb: &H::Aabb) -> usize {
+        let layer = self.get_layer(aabb);
+        forced_layer.map_or(layer, |forced| forced.min(layer))
+    }
+
     /// 指定id，在叉树中添加一个aabb单元及其绑定
+    /// + id已存在，或aabb无效（包含NaN、无穷值或min大于max）时返回false，叉树不变
     pub fn add(&mut self, id: K, aabb: H::Aabb, bind: T) -> bool {
+        self.try_add(id, aabb, bind).is_ok()
+    }
+
+    /// 指定id，在叉树中添加一个aabb单元及其绑定，失败时返回原因，叉树不变
+    pub fn try_add(&mut self, id: K, aabb: H::Aabb, bind: T) -> Result<(), AddError> {
+        self.add2(id, aabb, bind, None)
+    }
+
+    /// 指定id及层，在叉树中添加一个aabb单元及其绑定，节点固定在指定的层上，层的限制同set_layer
+    /// + id已存在，或aabb无效时返回false，叉树不变
+    pub fn add_with_layer(&mut self, id: K, aabb: H::Aabb, bind: T, layer: usize) -> bool {
+        self.add2(id, aabb, bind, Some(layer)).is_ok()
+    }
+
+    // 添加节点，指定层时固定在该层上
+    fn add2(
+        &mut self,
+        id: K,
+        aabb: H::Aabb,
+        bind: T,
+        force_layer: Option<usize>,
+    ) -> Result<(), AddError> {
         if self.ab_map.contains_key(id) {
-            return false;
+            return Err(AddError::DuplicateKey);
         }
-        let layer = self.get_layer(&aabb);
-        self.ab_map.insert(
-            id,
-            Node::new(AbNode::new(aabb.clone(), bind, layer, N as u8)),
-        );
+        if !H::aabb_is_valid(&aabb) {
+            return Err(AddError::InvalidAabb);
+        }
+        let layer = self.node_layer(force_layer, &aabb);
+        let mut ab = AbNode::new(aabb.clone(), bind, layer, N as u8);
+        ab.forced_layer = force_layer;
+        self.ab_map.insert(id, Node::new(ab));
         let root = unsafe { self.slab.get_unchecked_mut(self.root_key) };
         if H::aabb_contains(&root.aabb, &aabb) {
             // root的ab内
@@ -222,7 +379,121 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
             // 和根空间相交或在其外的ab节点, 该AbNode的parent为0
             self.outer.link_before(id, K::null(), &mut self.ab_map);
         }
-        true
+        self.invalidate_static(&aabb);
+        Ok(())
+    }
+
+    /// 预留additional个ab节点的容量，及估算的分支节点的容量，避免批量添加时多次重新分配
+    /// + ab_map按id的索引存储，id连续分配（比如来自SlotMap）时才能避免重新分配
+    /// + 分支节点数量按additional / 分裂阈值估算，每个分支节点平均容纳分裂阈值个ab节点
+    pub fn reserve(&mut self, additional: usize) {
+        self.ab_map.set_capacity(self.ab_map.len() + additional);
+        self.slab.reserve(additional / self.adjust.1);
+    }
+
+    /// 释放ab_map及脏列表多余的容量，用于大量节点移除后（比如卸载场景）回收内存
+    /// + ab_map按id的索引存储，只能收缩到最大id的索引，id不变
+    /// + 不改变slab，所有的BranchKey仍然有效。SlotMap释放的槽位仍保留在空闲链表中，需要收缩slab时使用compact
+    pub fn shrink_to_fit(&mut self) {
+        // 从最大的id开始插入，只分配一次
+        let nodes: Vec<_> = self.ab_map.drain().collect();
+        let mut ab_map = SecondaryMap::with_capacity(0);
+        for (id, node) in nodes.into_iter().rev() {
+            ab_map.insert(id, node);
+        }
+        self.ab_map = ab_map;
+        for vec in self.dirty.0.iter_mut() {
+            vec.shrink_to_fit();
+        }
+    }
+
+    /// 整理后按当前的分支节点重建slab，并释放所有多余的容量
+    /// + 根节点及所有分支节点的BranchKey都会改变，之前取得的BranchKey（比如iter_branches返回的）全部失效
+    /// + 重建过程中会临时占用一份节点的内存
+    pub fn compact(&mut self) {
+        self.collect();
+        let len = self.slab.len();
+        let mut old = mem::replace(&mut self.slab, SlotMap::with_capacity_and_key(len));
+        let mut keys: SecondaryMap<BranchKey, BranchKey> = SecondaryMap::with_capacity(old.len());
+        let mut stack = vec![self.root_key];
+        while let Some(old_key) = stack.pop() {
+            let node = old.remove(old_key).unwrap();
+            for child in node.childs.iter().rev() {
+                if let ChildNode::Branch(branch) = child {
+                    stack.push(*branch);
+                }
+            }
+            keys.insert(old_key, self.slab.insert(node));
+        }
+        self.root_key = keys[self.root_key];
+        for (_, node) in self.slab.iter_mut() {
+            if !node.parent.is_null() {
+                node.parent = keys[node.parent];
+            }
+            for child in node.childs.iter_mut() {
+                if let ChildNode::Branch(branch) = child {
+                    *branch = keys[*branch];
+                }
+            }
+        }
+        for (_, node) in self.ab_map.iter_mut() {
+            if !node.parent.is_null() {
+                node.parent = keys[node.parent];
+            }
+        }
+        self.shrink_to_fit();
+    }
+
+    /// 判断指定的aabb是否和已有的ab节点相交，用于放置前检查是否被占用
+    /// + 使用Helper的aabb_intersects判断，边界接触也算相交，未激活的节点也算占用
+    pub fn would_overlap(&self, aabb: &H::Aabb) -> bool {
+        !self.query_until(
+            true,
+            aabb,
+            H::aabb_intersects,
+            &mut aabb.clone(),
+            Self::not_overlap,
+        )
+    }
+
+    // 和查询的aabb不相交时继续查询
+    fn not_overlap(arg: &mut H::Aabb, _id: K, aabb: &H::Aabb, _bind: &T) -> bool {
+        !H::aabb_intersects(arg, aabb)
+    }
+
+    /// 只有aabb不和任何已有的ab节点相交时才添加，返回是否添加
+    /// + 相交的判断和would_overlap相同，id已存在或aabb无效时也返回false
+    pub fn add_if_free(&mut self, id: K, aabb: H::Aabb, bind: T) -> bool {
+        if self.would_overlap(&aabb) {
+            return false;
+        }
+        self.add(id, aabb, bind)
+    }
+
+    /// 批量添加aabb单元及其绑定，添加过程中不自动整理，全部添加后整理一次，返回成功添加的数量
+    /// + 根据迭代器的size_hint预先分配ab_map的容量
+    /// + 节省的是整理的次数，创建的分支节点和逐个添加时相同（添加不会销毁分支节点）
+    /// + 节点在最后一次整理中随分裂逐层下移，重新放置的次数比逐个添加并整理时多
+    pub fn add_batch(&mut self, iter: impl Iterator<Item = (K, H::Aabb, T)>) -> usize {
+        self.add_batch_with_report(iter).0
+    }
+
+    /// 同add_batch，同时返回最后一次整理的报告
+    pub fn add_batch_with_report(
+        &mut self,
+        iter: impl Iterator<Item = (K, H::Aabb, T)>,
+    ) -> (usize, CollectReport) {
+        let (lower, _) = iter.size_hint();
+        self.ab_map.set_capacity(self.ab_map.len() + lower);
+        let auto_collect = mem::replace(&mut self.auto_collect, usize::MAX);
+        let mut count = 0;
+        for (id, aabb, bind) in iter {
+            if self.add(id, aabb, bind) {
+                count += 1;
+            }
+        }
+        self.auto_collect = auto_collect;
+        (count, self.collect_with_report())
     }
 
     /// ab节点下降
@@ -265,6 +536,25 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         }
     }
 
+    /// 获取指定id的aabb
+    pub fn get_aabb(&self, id: K) -> Option<&H::Aabb> {
+        self.ab_map.get(id).map(|node| &node.value.0)
+    }
+
+    /// 获取指定id在叉树中的位置，id不存在时返回None，用于调试及建立邻接缓存
+    pub fn locate(&self, id: K) -> Option<Location> {
+        let node = self.ab_map.get(id)?;
+        Some(if node.parent.is_null() {
+            Location::Outer { layer: node.layer }
+        } else {
+            Location::Branch {
+                branch: node.parent,
+                parent_child: node.parent_child,
+                layer: node.layer,
+            }
+        })
+    }
+
     /// 获取指定id的aabb及其绑定
     pub unsafe fn get_unchecked(&self, id: K) -> &(H::Aabb, T) {
         &self.ab_map.get_unchecked(id).value
@@ -284,24 +574,108 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         &mut node.value.1
     }
 
+    /// 设置指定id的节点是否激活，未激活的节点仍在树中，但不会被query查询到
+    /// + 返回false表示id不存在
+    pub fn set_active(&mut self, id: K, active: bool) -> bool {
+        match self.ab_map.get_mut(id) {
+            Some(node) => {
+                if node.active != active {
+                    node.active = active;
+                    let aabb = node.value.0.clone();
+                    self.invalidate_static(&aabb);
+                }
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// 获得指定id的节点是否激活，id不存在返回None
+    pub fn is_active(&self, id: K) -> Option<bool> {
+        self.ab_map.get(id).map(|node| node.active)
+    }
+
+    /// 将指定id的节点固定在根节点的nodes列表上，不论大小，之后的更新也保持在根节点上，直到取消固定
+    /// + 用于玩家等总是需要被查询到的节点，每次查询都会直接检查根节点的nodes列表
+    /// + aabb超出根空间时和其他节点一样放在outer上，回到根空间内时再回到根节点上
+    /// + 返回false表示id不存在
+    pub fn pin_to_root(&mut self, id: K) -> bool {
+        let layer = unsafe { self.slab.get_unchecked(self.root_key) }.layer;
+        self.set_forced_layer(id, Some(layer))
+    }
+
+    /// 将指定id的节点固定在指定的层上，之后的更新也保持在该层，直到取消固定
+    /// + 记录指定的层，每次更新时取指定的层和按aabb大小计算的层中较浅的，节点不会超出分支空间的松散范围，aabb变小后回到指定的层
+    /// + 层越浅，节点所在的分支空间越大，跨越分支边界时的重新放置越少，适合快速移动的物体，但被查询扫描的次数越多
+    /// + 返回false表示id不存在
+    pub fn set_layer(&mut self, id: K, layer: usize) -> bool {
+        self.set_forced_layer(id, Some(layer))
+    }
+
+    /// 取消节点在根节点或指定层上的固定，节点按aabb的大小重新放置，返回false表示id不存在
+    pub fn unpin(&mut self, id: K) -> bool {
+        self.set_forced_layer(id, None)
+    }
+
+    /// 获得指定id的节点是否固定在根节点或指定层上，id不存在返回None
+    pub fn is_pinned(&self, id: K) -> Option<bool> {
+        self.ab_map.get(id).map(|node| node.forced_layer.is_some())
+    }
+
+    // 记录指定的层，并按该层重新放置节点，None表示取消固定
+    fn set_forced_layer(&mut self, id: K, forced_layer: Option<usize>) -> bool {
+        let layer = match self.ab_map.get(id) {
+            Some(node) => self.node_layer(forced_layer, &node.value.0),
+            _ => return false,
+        };
+        let node = unsafe { self.ab_map.get_unchecked_mut(id) };
+        node.forced_layer = forced_layer;
+        node.layer = layer;
+        let (old_p, old_c, aabb) = (node.parent, node.parent_child, node.value.0.clone());
+        self.update1(id, layer, old_p, old_c, &aabb);
+        true
+    }
+
     /// 检查是否包含某个key
     pub fn contains_key(&self, id: K) -> bool {
         self.ab_map.contains_key(id)
     }
 
     /// 更新指定id的aabb
+    /// + id不存在，或aabb无效（包含NaN、无穷值或min大于max）时返回false，叉树不变
     pub fn update(&mut self, id: K, aabb: H::Aabb) -> bool {
-        let layer = self.get_layer(&aabb);
-        if let Some(node) = self.ab_map.get_mut(id) {
-            node.layer = layer;
-            node.value.0 = aabb.clone();
-            let old_p = node.parent;
-            let old_c = node.parent_child;
-            self.update1(id, layer, old_p, old_c, &aabb);
-            true
-        } else {
-            false
+        if !H::aabb_is_valid(&aabb) {
+            return false;
+        }
+        let forced_layer = match self.ab_map.get(id) {
+            Some(node) => node.forced_layer,
+            _ => return false,
+        };
+        // 固定层的节点保持固定的层，但不会比按aabb大小计算的层更深
+        let layer = self.node_layer(forced_layer, &aabb);
+        let node = unsafe { self.ab_map.get_unchecked_mut(id) };
+        node.layer = layer;
+        let old = mem::replace(&mut node.value.0, aabb.clone());
+        let old_p = node.parent;
+        let old_c = node.parent_child;
+        self.update1(id, layer, old_p, old_c, &aabb);
+        self.invalidate_static(&old);
+        self.invalidate_static(&aabb);
+        true
+    }
+
+    /// 批量更新aabb，更新过程中不自动整理，全部更新后整理一次，返回找到并更新的id数量
+    pub fn update_batch(&mut self, iter: impl Iterator<Item = (K, H::Aabb)>) -> usize {
+        let auto_collect = mem::replace(&mut self.auto_collect, usize::MAX);
+        let mut count = 0;
+        for (id, aabb) in iter {
+            if self.update(id, aabb) {
+                count += 1;
+            }
         }
+        self.auto_collect = auto_collect;
+        self.collect();
+        count
     }
 
     /// 更新aabb
@@ -314,6 +688,27 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
     ///         node.layer<parent.layer. node.parent_child<N
     /// 更新节点就是在这3个位置上挪动
     fn update1(&mut self, id: K, layer: usize, old_p: BranchKey, old_c: u8, aabb: &H::Aabb) {
+        if self.changed_regions.is_none() {
+            return self.update2(id, layer, old_p, old_c, aabb);
+        }
+        let old_region = self.slab.get(old_p).map(|branch| branch.aabb.clone());
+        self.update2(id, layer, old_p, old_c, aabb);
+        // 节点换了位置，记录原来及现在所在的分支空间
+        let node = unsafe { self.ab_map.get_unchecked(id) };
+        if node.parent == old_p && node.parent_child == old_c {
+            return;
+        }
+        let new_region = if node.parent == old_p {
+            None
+        } else {
+            self.slab.get(node.parent).map(|branch| branch.aabb.clone())
+        };
+        let regions = self.changed_regions.as_mut().unwrap();
+        regions.extend(old_region);
+        regions.extend(new_region);
+    }
+    // 将节点挪动到新的位置
+    fn update2(&mut self, id: K, layer: usize, old_p: BranchKey, old_c: u8, aabb: &H::Aabb) {
         if old_p.is_null() {
             // 边界外物体更新
             let root = unsafe { self.slab.get_unchecked_mut(self.root_key) };
@@ -421,20 +816,47 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         list.link_before(id, K::null(), ab_map);
     }
     /// 移动指定id的aabb，性能比update要略好
+    /// + id不存在，或移动后的aabb无效（包含NaN或无穷值）时返回false，叉树不变
     pub fn shift(&mut self, id: K, distance: H::Vector) -> bool {
         if let Some(node) = self.ab_map.get_mut(id) {
             let aabb = H::aabb_shift(&node.value.0, &distance);
+            if !H::aabb_is_valid(&aabb) {
+                return false;
+            }
             let layer = node.layer;
-            node.value.0 = aabb.clone();
+            let old = mem::replace(&mut node.value.0, aabb.clone());
             let old_p = node.parent;
             let old_c = node.parent_child;
             self.update1(id, layer, old_p, old_c, &aabb);
+            self.invalidate_static(&old);
+            self.invalidate_static(&aabb);
             true
         } else {
             false
         }
     }
 
+    /// 将整个叉树平移，用于浮动原点的场景重新居中
+    /// + 根空间、所有分支节点及ab节点的aabb原地平移，相对位置不变，所以不会分裂或收缩，叉树结构保持不变
+    /// + 平移后的根空间无效（包含NaN或无穷值）时返回false，叉树不变
+    /// + 静态查询的区域不平移，缓存全部失效
+    pub fn shift_all(&mut self, distance: H::Vector) -> bool {
+        let root = unsafe { &self.slab.get_unchecked(self.root_key).aabb };
+        if !H::aabb_is_valid(&H::aabb_shift(root, &distance)) {
+            return false;
+        }
+        for (_, branch) in self.slab.iter_mut() {
+            branch.aabb = H::aabb_shift(&branch.aabb, &distance);
+        }
+        for (_, node) in self.ab_map.iter_mut() {
+            node.value.0 = H::aabb_shift(&node.value.0, &distance);
+        }
+        for (_, query) in self.static_queries.iter_mut() {
+            query.dirty = true;
+        }
+        true
+    }
+
     /// 更新指定id的绑定
     pub fn update_bind(&mut self, id: K, bind: T) -> bool {
         match self.ab_map.get_mut(id) {
@@ -446,6 +868,29 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         }
     }
 
+    /// 同时更新指定id的aabb及绑定，只查找一次节点，适合每帧都移动并改变状态的节点
+    /// + id不存在，或aabb无效时返回false，aabb及绑定都不变
+    pub fn update_full(&mut self, id: K, aabb: H::Aabb, bind: T) -> bool {
+        if !H::aabb_is_valid(&aabb) {
+            return false;
+        }
+        let forced_layer = match self.ab_map.get(id) {
+            Some(node) => node.forced_layer,
+            _ => return false,
+        };
+        let layer = self.node_layer(forced_layer, &aabb);
+        let node = unsafe { self.ab_map.get_unchecked_mut(id) };
+        node.layer = layer;
+        node.value.1 = bind;
+        let old = mem::replace(&mut node.value.0, aabb.clone());
+        let old_p = node.parent;
+        let old_c = node.parent_child;
+        self.update1(id, layer, old_p, old_c, &aabb);
+        self.invalidate_static(&old);
+        self.invalidate_static(&aabb);
+        true
+    }
+
     /// 移除指定id的aabb及其绑定
     pub fn remove(&mut self, id: K) -> Option<(H::Aabb, T)> {
         let (parent, parent_child) = match self.ab_map.get(id) {
@@ -463,14 +908,162 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
             // 表示在outer上
             self.outer.unlink(id, &mut self.ab_map);
         }
-        Some(self.ab_map.remove(id).unwrap().take().value)
+        let value = self.ab_map.remove(id).unwrap().take().value;
+        self.invalidate_static(&value.0);
+        Some(value)
+    }
+
+    /// 只保留f返回true的ab节点，其余节点被移除，全部移除后整理一次
+    pub fn retain(&mut self, mut f: impl FnMut(K, &H::Aabb, &T) -> bool) {
+        let ids: Vec<K> = self
+            .ab_map
+            .iter()
+            .filter(|(id, node)| !f(*id, &node.value.0, &node.value.1))
+            .map(|(id, _)| id)
+            .collect();
+        for id in ids {
+            self.remove(id);
+        }
+        self.collect();
+    }
+
+    /// 清空叉树，只保留根空间，保留slab和ab_map已分配的容量
+    /// + 根空间的范围、松散值、收缩及分裂的阈值、深度保持不变
+    pub fn clear(&mut self) {
+        let root = unsafe { self.slab.get_unchecked(self.root_key) };
+        let aabb = root.aabb.clone();
+        self.ab_map.clear();
+        self.reset_root(aabb);
+    }
+
+    /// 取出所有的ab节点，叉树被清空，和clear一样只保留根空间
+    pub fn drain(&mut self) -> impl Iterator<Item = (K, H::Aabb, T)> {
+        let mut nodes = Vec::with_capacity(self.ab_map.len());
+        for (id, node) in self.ab_map.drain() {
+            let (aabb, bind) = node.take().value;
+            nodes.push((id, aabb, bind));
+        }
+        let root = unsafe { self.slab.get_unchecked(self.root_key) };
+        let aabb = root.aabb.clone();
+        self.reset_root(aabb);
+        nodes.into_iter()
+    }
+
+    /// 注册静态查询，缓存和指定范围相交的ab节点的id，返回查询句柄
+    /// + 用于每帧都查询的固定范围，如触发器。只有和范围相交的节点被添加、移动、移除或激活改变时，缓存才失效
+    /// + 每次修改节点都要检查所有静态查询的范围，注册的静态查询不宜过多
+    pub fn register_static_query(&mut self, region: H::Aabb) -> QueryHandle {
+        let mut result = Vec::new();
+        self.for_each_by(&|ab| H::aabb_intersects(&region, ab), &mut |id, _, _| {
+            result.push(id)
+        });
+        self.static_queries.insert(StaticQuery {
+            region,
+            result,
+            dirty: false,
+        })
+    }
+
+    /// 注销静态查询，返回句柄是否存在
+    pub fn unregister_static_query(&mut self, handle: QueryHandle) -> bool {
+        self.static_queries.remove(handle).is_some()
+    }
+
+    /// 获得静态查询的结果，缓存失效时重新查询，句柄不存在返回None
+    pub fn query_static(&mut self, handle: QueryHandle) -> Option<&[K]> {
+        let query = self.static_queries.get(handle)?;
+        if query.dirty {
+            let mut result = Vec::new();
+            let region = &query.region;
+            self.for_each_by(&|ab| H::aabb_intersects(region, ab), &mut |id, _, _| {
+                result.push(id)
+            });
+            let query = unsafe { self.static_queries.get_unchecked_mut(handle) };
+            query.result = result;
+            query.dirty = false;
+        }
+        Some(&unsafe { self.static_queries.get_unchecked(handle) }.result)
+    }
+
+    // 节点在指定aabb处发生了变化，使和aabb相交的静态查询的缓存失效
+    fn invalidate_static(&mut self, aabb: &H::Aabb) {
+        for (_, query) in self.static_queries.iter_mut() {
+            if !query.dirty && H::aabb_intersects(&query.region, aabb) {
+                query.dirty = true;
+            }
+        }
+    }
+
+    /// 扩大根空间，新的根空间必须包含原根空间，否则返回false
+    /// + 所有节点按新的根空间重新插入，原来在outer上的节点如果被新的根空间包含，会下降到树中
+    /// + 根空间的BranchKey保持不变，深度限制按根空间扩大的层数相应增加
+    pub fn grow_root(&mut self, new_root: H::Aabb) -> bool {
+        let old = unsafe { &self.slab.get_unchecked(self.root_key).aabb };
+        if !H::aabb_contains(&new_root, old) {
+            return false;
+        }
+        let grow = H::calc_layer(&H::aabb_extents(&new_root), &H::aabb_extents(old));
+        let mut d = H::aabb_extents(&new_root);
+        self.deep = H::get_deap(
+            &mut d,
+            self.loose_layer,
+            &self.max_loose,
+            (self.deep + grow).min(DEEP_MAX),
+            &self.min_loose,
+        );
+        let mut nodes = Vec::with_capacity(self.ab_map.len());
+        let mut inactive = Vec::new();
+        let mut pinned = Vec::new();
+        for (id, node) in self.ab_map.drain() {
+            let ab = node.take();
+            if !ab.active {
+                inactive.push(id);
+            }
+            if let Some(layer) = ab.forced_layer {
+                pinned.push((id, layer));
+            }
+            nodes.push((id, ab.value.0, ab.value.1));
+        }
+        self.reset_root(new_root);
+        self.add_batch(nodes.into_iter());
+        for id in inactive {
+            self.set_active(id, false);
+        }
+        for (id, layer) in pinned {
+            self.set_layer(id, layer);
+        }
+        true
+    }
+
+    // 只保留根节点，并用指定的aabb重置根节点，要求ab_map已经清空
+    // 脏列表中被删除的分支节点一并清除，静态查询的缓存全部失效
+    fn reset_root(&mut self, aabb: H::Aabb) {
+        let root_key = self.root_key;
+        self.slab.retain(|key, _| key == root_key);
+        let root = unsafe { self.slab.get_unchecked_mut(root_key) };
+        *root = BranchNode::new(aabb, self.max_loose.clone(), 0, BranchKey::null(), 0);
+        self.outer = LinkList::new();
+        for vec in &mut self.dirty.0 {
+            vec.clear();
+        }
+        self.dirty.1 = DirtyState::new();
+        for (_, query) in self.static_queries.iter_mut() {
+            query.dirty = true;
+        }
     }
 
     /// 整理方法，只有整理方法才会创建或销毁BranchNode
     pub fn collect(&mut self) {
+        self.collect_with_report();
+    }
+
+    /// 整理方法，返回整理过程中创建和销毁的BranchNode数量，及重新放置的ab节点次数
+    /// + 没有脏节点时直接返回全0，全0表示结构没有改变
+    pub fn collect_with_report(&mut self) -> CollectReport {
+        let mut report = CollectReport::default();
         let state = mem::replace(&mut self.dirty.1, DirtyState::new());
         if state.dirty_count == 0 {
-            return;
+            return report;
         }
         for i in state.min_layer..state.max_layer {
             let vec = unsafe { self.dirty.0.get_unchecked_mut(i) };
@@ -480,7 +1073,7 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
             }
             for j in 0..c {
                 let branch_id = unsafe { vec.get_unchecked(j) };
-                Self::collect1(
+                let (r, region) = Self::collect1(
                     &mut self.slab,
                     &mut self.ab_map,
                     &self.adjust,
@@ -489,12 +1082,75 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
                     self.loose_layer,
                     &self.min_loose,
                 );
+                report += r;
+                if let (Some(regions), Some(region)) = (&mut self.changed_regions, region) {
+                    regions.push(region);
+                }
             }
             vec.clear();
         }
+        report
+    }
+
+    /// 设置是否记录整理时发生分裂或收缩，及更新时有节点移入移出的分支空间，关闭时清空已记录的空间
+    /// + 记录的空间会一直累积，需要定期调用take_changed_regions取出
+    pub fn set_track_changes(&mut self, enable: bool) {
+        match (enable, &self.changed_regions) {
+            (true, None) => self.changed_regions = Some(Vec::new()),
+            (false, _) => self.changed_regions = None,
+            _ => (),
+        }
+    }
+
+    /// 取出上次取出后，整理时发生分裂或收缩，及更新时有节点移入移出的分支空间，需要先用set_track_changes开启记录
+    /// + 分裂时为被分裂的分支空间，收缩时为被合并的最外层的分支空间，可据此只更新受影响区域的缓存
+    /// + 节点被更新到其他位置时，记录原来及现在所在的分支空间，在outer上的一侧不记录；同一分支空间内换子空间只记录一次
+    pub fn take_changed_regions(&mut self) -> Vec<H::Aabb> {
+        match &mut self.changed_regions {
+            Some(regions) => mem::take(regions),
+            None => Vec::new(),
+        }
+    }
+
+    /// 脏计数达到指定阈值才进行整理，返回是否进行了整理
+    pub fn collect_if_beneficial(&mut self, min_dirty: usize) -> bool {
+        if self.dirty.1.dirty_count == 0 || self.dirty.1.dirty_count < min_dirty {
+            return false;
+        }
+        self.collect();
+        true
+    }
+
+    /// 压平叉树，比layer深的分支节点全部合并到第layer层的祖先分支节点的ab节点列表中，并删除这些分支节点
+    /// + 用于得到较浅的叉树，进行低成本的粗略查询
+    /// + 之后的整理仍可能按分化阈值重新分裂，如需保持可调用set_deep限制深度
+    pub fn flatten_below(&mut self, layer: usize) {
+        let targets: Vec<BranchKey> = self
+            .slab
+            .iter()
+            .filter(|(_, branch)| branch.layer == layer)
+            .map(|(id, _)| id)
+            .collect();
+        for branch_id in targets {
+            for i in 0..N {
+                let child = match unsafe { self.slab.get_unchecked(branch_id) }.childs[i] {
+                    ChildNode::Branch(child) => child,
+                    _ => continue,
+                };
+                let list = Self::take_subtree(&mut self.slab, &mut self.ab_map, child);
+                for (_, node) in list.iter_mut(&mut self.ab_map) {
+                    node.parent = branch_id;
+                    node.parent_child = i as u8;
+                }
+                let branch = unsafe { self.slab.get_unchecked_mut(branch_id) };
+                branch.childs[i] = ChildNode::Ab(list);
+            }
+        }
     }
 
     /// 整理方法，只有整理方法才会创建或销毁BranchNode
+    // 整理分支节点，返回整理的结果，及发生分裂或收缩的分支空间
+    // 收缩时为被合并的最外层的分支空间，分裂时为被分裂的分支空间
     fn collect1(
         slab: &mut SlotMap<BranchKey, BranchNode<K, H, T, N>>,
         ab_map: &mut SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>,
@@ -503,43 +1159,69 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         branch_id: BranchKey,
         loose_layer: usize,
         min_loose: &H::Vector,
-    ) {
+    ) -> (CollectReport, Option<H::Aabb>) {
+        let mut report = CollectReport::default();
         let parent = match slab.get_mut(branch_id) {
             Some(branch) => branch,
-            _ => return,
+            _ => return (report, None),
         };
         let dirty = mem::replace(&mut parent.dirty, false);
         if !dirty {
-            return;
+            return (report, None);
         }
         let parent_id = parent.parent;
         // 判断是否收缩
         if (!parent_id.is_null()) && parent.is_need_merge(adjust.0) {
             let child = parent.parent_child;
             let list = Self::merge_branch(ab_map, parent, LinkList::new());
-            slab.remove(branch_id);
-            Self::shrink(slab, ab_map, adjust.0, parent_id, child, branch_id, list);
-            return;
+            let region = slab.remove(branch_id).unwrap().aabb;
+            let (mut report, outer) =
+                Self::shrink(slab, ab_map, adjust.0, parent_id, child, branch_id, list);
+            report.branches_destroyed += 1;
+            return (report, Some(outer.unwrap_or(region)));
         }
         let (need, lists) = parent.need_split_list(adjust.1);
-        if need {
-            let aabb = parent.aabb.clone();
-            let loose = parent.loose.clone();
-            let layer = parent.layer;
-            Self::split(
-                slab,
-                ab_map,
-                adjust.1,
-                deep,
-                lists,
-                &aabb,
-                &loose,
-                layer,
-                branch_id,
-                loose_layer,
-                min_loose,
-            );
+        if !need {
+            return (report, None);
+        }
+        let aabb = parent.aabb.clone();
+        let loose = parent.loose.clone();
+        let layer = parent.layer;
+        Self::split(
+            slab,
+            ab_map,
+            adjust.1,
+            deep,
+            lists,
+            &aabb,
+            &loose,
+            layer,
+            branch_id,
+            loose_layer,
+            min_loose,
+            &mut report,
+        );
+        (report, Some(aabb))
+    }
+    // 删除分支节点及其下所有的分支节点，返回其下所有ab节点组成的列表
+    fn take_subtree(
+        slab: &mut SlotMap<BranchKey, BranchNode<K, H, T, N>>,
+        ab_map: &mut SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>,
+        branch_id: BranchKey,
+    ) -> List<K, H, T, N> {
+        let mut branch = slab.remove(branch_id).unwrap();
+        let mut list = LinkList::new();
+        list.append(&mut branch.nodes, ab_map);
+        for child in &mut branch.childs {
+            match child {
+                ChildNode::Ab(other) => list.append(other, ab_map),
+                ChildNode::Branch(id) => {
+                    let mut other = Self::take_subtree(slab, ab_map, *id);
+                    list.append(&mut other, ab_map);
+                }
+            }
         }
+        list
     }
     // 合并子空间的所有列表
     fn merge_branch(
@@ -557,7 +1239,7 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         list
     }
 
-    /// 收缩BranchNode
+    /// 收缩BranchNode，返回销毁的BranchNode数量及重新放置的ab节点数量，继续向上收缩时返回被合并的最外层的分支空间
     fn shrink(
         slab: &mut SlotMap<BranchKey, BranchNode<K, H, T, N>>,
         ab_map: &mut SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>,
@@ -566,21 +1248,27 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         parent_child: u8,
         child_id: BranchKey,
         list: List<K, H, T, N>,
-    ) {
+    ) -> (CollectReport, Option<H::Aabb>) {
         let branch = unsafe { slab.get_unchecked_mut(branch_id) };
         // 判断是否继续收缩
         if (!branch.parent.is_null()) && branch.is_need_merge_with_child(adjust, child_id, list.len()) {
             let parent_id = branch.parent;
             let child = branch.parent_child;
             let list = Self::merge_branch(ab_map, branch, list);
-            slab.remove(branch_id);
-            Self::shrink(slab, ab_map, adjust, parent_id, child, branch_id, list);
+            let region = slab.remove(branch_id).unwrap().aabb;
+            let (mut report, outer) =
+                Self::shrink(slab, ab_map, adjust, parent_id, child, branch_id, list);
+            report.branches_destroyed += 1;
+            (report, Some(outer.unwrap_or(region)))
         } else {
+            let mut report = CollectReport::default();
             for (_, node) in list.iter_mut(ab_map) {
                 node.parent = branch_id;
                 node.parent_child = parent_child;
+                report.nodes_relocated += 1;
             };
             branch.childs[parent_child as usize] = ChildNode::Ab(list);
+            (report, None)
         }
     }
     // 对列表进行分裂
@@ -597,6 +1285,7 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         parent_id: BranchKey,
         loose_layer: usize,
         min_loose: &H::Vector,
+        report: &mut CollectReport,
     ) {
         let mut branchs = [BranchKey::null(); N];
         for (i, list) in lists.into_iter().enumerate() {
@@ -613,6 +1302,7 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
                 i as u8,
             );
             let branch_id = slab.insert(branch);
+            report.branches_created += 1;
             Self::split_down(
                 slab,
                 ab_map,
@@ -622,6 +1312,7 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
                 branch_id,
                 loose_layer,
                 min_loose,
+                report,
             );
             branchs[i] = branch_id;
         }
@@ -642,6 +1333,7 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         parent_id: BranchKey,
         loose_layer: usize,
         min_loose: &H::Vector,
+        report: &mut CollectReport,
     ) {
         let parent = unsafe { slab.get_unchecked_mut(parent_id) };
         let point = H::get_max_half_loose(&parent.aabb, &parent.loose);
@@ -649,6 +1341,7 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         let mut id = drain.pop_front(ab_map);
         while !id.is_null() {
             let node = unsafe { ab_map.get_unchecked_mut(id) };
+            report.nodes_relocated += 1;
             if parent.layer >= node.layer {
                 node.parent = parent_id;
                 node.parent_child = N as u8;
@@ -686,6 +1379,7 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
                 parent_id,
                 loose_layer,
                 min_loose,
+                report,
             );
         }
     }
@@ -702,108 +1396,1682 @@ impl<K: Key, H: Helper<N>, T, const N: usize> Tree<K, H, T, N> {
         self.query1(self.root_key, branch_arg, branch_func, ab_arg, ab_func)
     }
 
-    // 查询空间内及相交的ab节点
-    fn query1<A, B>(
+    /// 查询空间内及相交的ab节点，按id的ffi值从小到大的顺序回调ab_func，结果和插入顺序无关，可用于确定性回放
+    /// + 先收集所有候选id再排序，比query多一次排序及分配，不需要确定顺序时应该使用query
+    pub fn query_sorted<A, B>(
         &self,
-        branch_id: BranchKey,
         branch_arg: &A,
         branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
         ab_arg: &mut B,
         ab_func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &T),
     ) {
-        let node = unsafe { self.slab.get_unchecked(branch_id) };
-        for (id, ab) in node.nodes.iter(&self.ab_map) {
-            ab_func(ab_arg, id, &ab.value.0, &ab.value.1);
+        fn collect<K: Key, Aabb, T>(arg: &mut Vec<(u64, K)>, id: K, _: &Aabb, _: &T) {
+            arg.push((id.data().as_ffi(), id));
         }
-        let childs = H::make_childs(&node.aabb, &node.loose);
-        for (i, ab) in childs.iter().enumerate() {
-            match node.childs[i] {
-                ChildNode::Branch(branch) => {
-                    if branch_func(branch_arg, &ab) {
-                        self.query1(branch, branch_arg, branch_func, ab_arg, ab_func);
+        let mut ids = Vec::new();
+        self.query(branch_arg, branch_func, &mut ids, collect);
+        ids.sort_unstable_by_key(|r| r.0);
+        for (_, id) in ids {
+            let node = unsafe { self.ab_map.get_unchecked(id) };
+            ab_func(ab_arg, id, &node.value.0, &node.value.1);
+        }
+    }
+
+    /// 并行查询空间内及相交的ab节点，需要开启rayon特性
+    /// + 根节点的每个子空间为一个并行任务，outer及根节点本层的ab节点为另一个任务
+    /// + 每个任务用init创建自己的累加器，由ab_func累加，最后用reduce合并所有任务的结果
+    #[cfg(feature = "rayon")]
+    pub fn par_query<A: Sync, R: Send>(
+        &self,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        init: impl Fn() -> R + Sync + Send,
+        ab_func: fn(acc: &mut R, id: K, aabb: &H::Aabb, bind: &T),
+        reduce: impl Fn(R, R) -> R + Sync + Send,
+    ) -> R
+    where
+        K: Sync,
+        T: Sync,
+        H::Aabb: Sync,
+        H::Vector: Sync,
+    {
+        use rayon::prelude::*;
+
+        let root = unsafe { self.slab.get_unchecked(self.root_key) };
+        let childs = H::make_childs(&root.aabb, &root.loose);
+        (0..N + 1)
+            .into_par_iter()
+            .map(|i| {
+                let mut acc = init();
+                if i == N {
+                    self.query_outer(&mut acc, ab_func);
+                    for (id, ab) in root.nodes.iter(&self.ab_map) {
+                        if ab.active {
+                            ab_func(&mut acc, id, &ab.value.0, &ab.value.1);
+                        }
+                    }
+                    return acc;
+                }
+                if !branch_func(branch_arg, &childs[i]) {
+                    return acc;
+                }
+                match root.childs[i] {
+                    ChildNode::Branch(branch) => {
+                        self.query1(branch, branch_arg, branch_func, &mut acc, ab_func)
+                    }
+                    ChildNode::Ab(ref list) => {
+                        for (id, ab) in list.iter(&self.ab_map) {
+                            if ab.active {
+                                ab_func(&mut acc, id, &ab.value.0, &ab.value.1);
+                            }
+                        }
+                    }
+                }
+                acc
+            })
+            .reduce(&init, &reduce)
+    }
+
+    // 查询空间内及相交的ab节点
+    fn query1<A, B>(
+        &self,
+        branch_id: BranchKey,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        ab_arg: &mut B,
+        ab_func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &T),
+    ) {
+        let node = unsafe { self.slab.get_unchecked(branch_id) };
+        for (id, ab) in node.nodes.iter(&self.ab_map) {
+            if ab.active {
+                ab_func(ab_arg, id, &ab.value.0, &ab.value.1);
+            }
+        }
+        let childs = H::make_childs(&node.aabb, &node.loose);
+        for (i, ab) in childs.iter().enumerate() {
+            match node.childs[i] {
+                ChildNode::Branch(branch) => {
+                    if branch_func(branch_arg, &ab) {
+                        self.query1(branch, branch_arg, branch_func, ab_arg, ab_func);
                     }
                 }
                 ChildNode::Ab(ref list) if !list.is_empty() => {
                     if branch_func(branch_arg, &ab) {
                         for (id, ab) in list.iter(&self.ab_map) {
+                            if ab.active {
+                                ab_func(ab_arg, id, &ab.value.0, &ab.value.1);
+                            }
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// 查询空间内及相交的ab节点，ab_func可以修改节点的绑定
+    /// + 只提供绑定的可写引用，aabb只读，修改aabb需要使用update
+    pub fn query_mut<A, B>(
+        &mut self,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        ab_arg: &mut B,
+        ab_func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &mut T),
+    ) {
+        Self::query_list_mut(&mut self.ab_map, &self.outer, ab_arg, ab_func);
+        Self::query_mut1(
+            &self.slab,
+            &mut self.ab_map,
+            self.root_key,
+            branch_arg,
+            branch_func,
+            ab_arg,
+            ab_func,
+        )
+    }
+
+    // 查询空间内及相交的ab节点，slab只读，ab_map可写
+    fn query_mut1<A, B>(
+        slab: &SlotMap<BranchKey, BranchNode<K, H, T, N>>,
+        ab_map: &mut SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>,
+        branch_id: BranchKey,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        ab_arg: &mut B,
+        ab_func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &mut T),
+    ) {
+        let node = unsafe { slab.get_unchecked(branch_id) };
+        Self::query_list_mut(ab_map, &node.nodes, ab_arg, ab_func);
+        let childs = H::make_childs(&node.aabb, &node.loose);
+        for (i, ab) in childs.iter().enumerate() {
+            match node.childs[i] {
+                ChildNode::Branch(branch) => {
+                    if branch_func(branch_arg, ab) {
+                        Self::query_mut1(
+                            slab,
+                            ab_map,
+                            branch,
+                            branch_arg,
+                            branch_func,
+                            ab_arg,
+                            ab_func,
+                        );
+                    }
+                }
+                ChildNode::Ab(ref list) if !list.is_empty() && branch_func(branch_arg, ab) => {
+                    Self::query_list_mut(ab_map, list, ab_arg, ab_func);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    // 列表中激活的ab节点，用可写的绑定回调ab_func
+    fn query_list_mut<B>(
+        ab_map: &mut SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>,
+        list: &List<K, H, T, N>,
+        ab_arg: &mut B,
+        ab_func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &mut T),
+    ) {
+        for (id, ab) in list.iter_mut(ab_map) {
+            if ab.active {
+                let (aabb, bind) = &mut ab.value;
+                ab_func(ab_arg, id, aabb, bind);
+            }
+        }
+    }
+
+    /// 查询空间内及相交的层不大于max_layer的ab节点，用于只显示大物体的LOD
+    /// + 层越小的节点尺寸越大，节点的层按添加时的aabb尺寸计算，固定在根空间的节点的层为0
+    /// + 深度大于max_layer的分支空间不再向下查询，但max_layer层分支空间本层的节点会被查询
+    pub fn query_max_layer<A, B>(
+        &self,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        max_layer: usize,
+        ab_arg: &mut B,
+        ab_func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &T),
+    ) {
+        for (id, ab) in self.outer.iter(&self.ab_map) {
+            if ab.active && ab.layer <= max_layer {
+                ab_func(ab_arg, id, &ab.value.0, &ab.value.1);
+            }
+        }
+        self.query_max_layer1(
+            self.root_key,
+            branch_arg,
+            branch_func,
+            max_layer,
+            ab_arg,
+            ab_func,
+        )
+    }
+
+    // 查询空间内及相交的层不大于max_layer的ab节点
+    fn query_max_layer1<A, B>(
+        &self,
+        branch_id: BranchKey,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        max_layer: usize,
+        ab_arg: &mut B,
+        ab_func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &T),
+    ) {
+        let node = unsafe { self.slab.get_unchecked(branch_id) };
+        for (id, ab) in node.nodes.iter(&self.ab_map) {
+            if ab.active && ab.layer <= max_layer {
+                ab_func(ab_arg, id, &ab.value.0, &ab.value.1);
+            }
+        }
+        // 子空间及子空间上的ab节点列表的层都比本空间深
+        if node.layer >= max_layer {
+            return;
+        }
+        let childs = H::make_childs(&node.aabb, &node.loose);
+        for (i, ab) in childs.iter().enumerate() {
+            match node.childs[i] {
+                ChildNode::Branch(branch) => {
+                    if branch_func(branch_arg, ab) {
+                        self.query_max_layer1(
+                            branch,
+                            branch_arg,
+                            branch_func,
+                            max_layer,
+                            ab_arg,
+                            ab_func,
+                        );
+                    }
+                }
+                ChildNode::Ab(ref list) if !list.is_empty() && branch_func(branch_arg, ab) => {
+                    for (id, ab) in list.iter(&self.ab_map) {
+                        if ab.active && ab.layer <= max_layer {
                             ab_func(ab_arg, id, &ab.value.0, &ab.value.1);
                         }
                     }
                 }
-                _ => (),
+                _ => (),
+            }
+        }
+    }
+    /// 查询空间内及相交的ab节点，通过有界通道边查询边发送结果
+    /// + 返回是否发送了全部结果，接收方被丢弃时立即停止查询并返回false
+    #[cfg(feature = "std")]
+    pub fn query_stream<A>(
+        &self,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        sender: &SyncSender<(K, H::Aabb, T)>,
+    ) -> bool
+    where
+        T: Clone + Send,
+    {
+        fn send<K, Aabb: Clone, T: Clone>(
+            sender: &mut &SyncSender<(K, Aabb, T)>,
+            id: K,
+            aabb: &Aabb,
+            bind: &T,
+        ) -> bool {
+            sender.send((id, aabb.clone(), bind.clone())).is_ok()
+        }
+        let mut sender = sender;
+        self.query_until(false, branch_arg, branch_func, &mut sender, send)
+    }
+
+    /// 查询空间内及相交的ab节点，ab_func返回true则立即停止查询，并返回该节点的id
+    /// + 先查询outer，再查询根空间，所有节点的ab_func都返回false则返回None
+    pub fn query_first<A, B>(
+        &self,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        ab_arg: &mut B,
+        ab_func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &T) -> bool,
+    ) -> Option<K> {
+        type FirstArg<'a, B, K, Aabb, T> = (
+            &'a mut B,
+            fn(arg: &mut B, id: K, aabb: &Aabb, bind: &T) -> bool,
+            Option<K>,
+        );
+        fn first<B, K: Copy, Aabb, T>(
+            arg: &mut FirstArg<B, K, Aabb, T>,
+            id: K,
+            aabb: &Aabb,
+            bind: &T,
+        ) -> bool {
+            if (arg.1)(arg.0, id, aabb, bind) {
+                arg.2 = Some(id);
+                return false;
+            }
+            true
+        }
+        let mut arg: FirstArg<B, K, H::Aabb, T> = (ab_arg, ab_func, None);
+        self.query_until(false, branch_arg, branch_func, &mut arg, first);
+        arg.2
+    }
+
+    /// 查询空间内及相交的ab节点，由ab_func的返回值决定是否接受该节点及是否继续查询，返回所有被接受的节点的id
+    /// + 先查询outer，再查询根空间
+    pub fn query_accept<A, B>(
+        &self,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        ab_arg: &mut B,
+        ab_func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &T) -> QueryAction,
+    ) -> Vec<K> {
+        type AcceptArg<'a, B, K, Aabb, T> = (
+            &'a mut B,
+            fn(arg: &mut B, id: K, aabb: &Aabb, bind: &T) -> QueryAction,
+            Vec<K>,
+        );
+        fn accept<B, K: Copy, Aabb, T>(
+            arg: &mut AcceptArg<B, K, Aabb, T>,
+            id: K,
+            aabb: &Aabb,
+            bind: &T,
+        ) -> bool {
+            match (arg.1)(arg.0, id, aabb, bind) {
+                QueryAction::Reject => true,
+                QueryAction::Accept => {
+                    arg.2.push(id);
+                    true
+                }
+                QueryAction::AcceptAndStop => {
+                    arg.2.push(id);
+                    false
+                }
+            }
+        }
+        let mut arg: AcceptArg<B, K, H::Aabb, T> = (ab_arg, ab_func, Vec::new());
+        self.query_until(false, branch_arg, branch_func, &mut arg, accept);
+        arg.2
+    }
+
+    // 查询空间内及相交的ab节点，ab_func返回false则停止查询，返回是否查询完毕
+    // inactive为true时未激活的节点也会回调
+    fn query_until<A, B>(
+        &self,
+        inactive: bool,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        ab_arg: &mut B,
+        ab_func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &T) -> bool,
+    ) -> bool {
+        for (id, ab) in self.outer.iter(&self.ab_map) {
+            if (inactive || ab.active) && !ab_func(ab_arg, id, &ab.value.0, &ab.value.1) {
+                return false;
+            }
+        }
+        self.query_until1(
+            inactive,
+            self.root_key,
+            branch_arg,
+            branch_func,
+            ab_arg,
+            ab_func,
+        )
+    }
+
+    // 查询空间内及相交的ab节点，ab_func返回false则停止查询
+    fn query_until1<A, B>(
+        &self,
+        inactive: bool,
+        branch_id: BranchKey,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        ab_arg: &mut B,
+        ab_func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &T) -> bool,
+    ) -> bool {
+        let node = unsafe { self.slab.get_unchecked(branch_id) };
+        for (id, ab) in node.nodes.iter(&self.ab_map) {
+            if (inactive || ab.active) && !ab_func(ab_arg, id, &ab.value.0, &ab.value.1) {
+                return false;
+            }
+        }
+        let childs = H::make_childs(&node.aabb, &node.loose);
+        for (i, ab) in childs.iter().enumerate() {
+            match node.childs[i] {
+                ChildNode::Branch(branch) => {
+                    if branch_func(branch_arg, ab)
+                        && !self.query_until1(
+                            inactive,
+                            branch,
+                            branch_arg,
+                            branch_func,
+                            ab_arg,
+                            ab_func,
+                        )
+                    {
+                        return false;
+                    }
+                }
+                ChildNode::Ab(ref list) if !list.is_empty() && branch_func(branch_arg, ab) => {
+                    for (id, ab) in list.iter(&self.ab_map) {
+                        if (inactive || ab.active) && !ab_func(ab_arg, id, &ab.value.0, &ab.value.1)
+                        {
+                            return false;
+                        }
+                    }
+                }
+                _ => (),
+            }
+        }
+        true
+    }
+
+    /// 查询空间内及相交的ab节点的数量，不回调每个节点
+    /// + outer上的节点需要通过branch_func的判断才会被计数，未激活的节点不计数
+    pub fn query_count<A>(
+        &self,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+    ) -> usize {
+        let mut count = 0;
+        for (_, ab) in self.outer.iter(&self.ab_map) {
+            if ab.active && branch_func(branch_arg, &ab.value.0) {
+                count += 1;
+            }
+        }
+        count + self.query_count1(self.root_key, branch_arg, branch_func)
+    }
+
+    // 计算空间内及相交的ab节点的数量
+    fn query_count1<A>(
+        &self,
+        branch_id: BranchKey,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+    ) -> usize {
+        let node = unsafe { self.slab.get_unchecked(branch_id) };
+        let mut count = Self::active_count(&self.ab_map, &node.nodes);
+        let childs = H::make_childs(&node.aabb, &node.loose);
+        for (i, ab) in childs.iter().enumerate() {
+            match node.childs[i] {
+                ChildNode::Branch(branch) => {
+                    if branch_func(branch_arg, ab) {
+                        count += self.query_count1(branch, branch_arg, branch_func);
+                    }
+                }
+                ChildNode::Ab(ref list) if !list.is_empty() && branch_func(branch_arg, ab) => {
+                    count += Self::active_count(&self.ab_map, list);
+                }
+                _ => (),
+            }
+        }
+        count
+    }
+    // 列表中激活的节点数量
+    fn active_count(
+        ab_map: &SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>,
+        list: &List<K, H, T, N>,
+    ) -> usize {
+        list.iter(ab_map).filter(|(_, ab)| ab.active).count()
+    }
+
+    /// 查询和指定范围相交的ab节点，返回按绑定导出的键排序的id，键相同的节点顺序不确定
+    pub fn query_sorted_by<O: Ord>(&self, region: &H::Aabb, key_fn: impl Fn(&T) -> O) -> Vec<K> {
+        let mut result = Vec::new();
+        self.for_each_by(&|ab| H::aabb_intersects(region, ab), &mut |id, _, bind| {
+            result.push((key_fn(bind), id))
+        });
+        result.sort_by(|a, b| a.0.cmp(&b.0));
+        result.into_iter().map(|(_, id)| id).collect()
+    }
+
+    /// 计算点到最近的ab节点表面的有符号距离，点在节点内则为负，没有节点返回None
+    /// + 点在多个重叠的节点内时，返回最小的值（即穿透最深的）
+    pub fn distance_to_nearest(&self, point: &H::Point) -> Option<H::Scalar> {
+        let zero = H::Scalar::zero();
+        let mut inside: Option<H::Scalar> = None;
+        self.for_each_by(
+            &|ab| H::aabb_point_distance_sq(ab, point) == zero,
+            &mut |_, ab, _| {
+                let d = H::aabb_point_signed_distance(ab, point);
+                match inside {
+                    Some(min) if min <= d => (),
+                    _ => inside = Some(d),
+                }
+            },
+        );
+        if inside.is_some() {
+            return inside;
+        }
+        self.k_nearest(point, 1).first().map(|(_, d)| d.sqrt())
+    }
+
+    // 查询满足pred的ab节点，并回调f，pred同时用于裁剪分支空间
+    fn for_each_by<'a, P: Fn(&H::Aabb) -> bool, F: FnMut(K, &'a H::Aabb, &'a T)>(
+        &'a self,
+        pred: &P,
+        f: &mut F,
+    ) {
+        Self::for_each_list(&self.ab_map, &self.outer, pred, f);
+        self.for_each_by1(self.root_key, pred, f);
+    }
+
+    // 查询分支空间内满足pred的ab节点
+    fn for_each_by1<'a, P: Fn(&H::Aabb) -> bool, F: FnMut(K, &'a H::Aabb, &'a T)>(
+        &'a self,
+        branch_id: BranchKey,
+        pred: &P,
+        f: &mut F,
+    ) {
+        let node = unsafe { self.slab.get_unchecked(branch_id) };
+        Self::for_each_list(&self.ab_map, &node.nodes, pred, f);
+        let childs = H::make_childs(&node.aabb, &node.loose);
+        for (i, ab) in childs.iter().enumerate() {
+            if !pred(ab) {
+                continue;
+            }
+            match node.childs[i] {
+                ChildNode::Branch(branch) => self.for_each_by1(branch, pred, f),
+                ChildNode::Ab(ref list) => Self::for_each_list(&self.ab_map, list, pred, f),
+            }
+        }
+    }
+
+    // 列表中满足pred的激活的ab节点，回调f
+    fn for_each_list<'a, P: Fn(&H::Aabb) -> bool, F: FnMut(K, &'a H::Aabb, &'a T)>(
+        ab_map: &'a SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>,
+        list: &List<K, H, T, N>,
+        pred: &P,
+        f: &mut F,
+    ) {
+        for (id, ab) in list.iter(ab_map) {
+            if ab.active && pred(&ab.value.0) {
+                f(id, &ab.value.0, &ab.value.1);
+            }
+        }
+    }
+
+    /// 查询和指定范围相交的ab节点，返回记录了范围及匹配id的结果集，之后可用QuerySet::refresh得到进入及离开的id
+    pub fn query_handle(&self, region: &H::Aabb) -> QuerySet<K, H::Aabb> {
+        let mut ids = Vec::new();
+        self.for_each_by(&|ab| H::aabb_intersects(region, ab), &mut |id, _, _| {
+            ids.push(id)
+        });
+        QuerySet {
+            region: region.clone(),
+            ids,
+        }
+    }
+
+    /// 查询和指定范围相交的ab节点，一次遍历返回节点数量及所有节点aabb的并集，没有匹配的节点时并集为None
+    pub fn query_summary(
+        &self,
+        region: &H::Aabb,
+        branch_func: fn(arg: &H::Aabb, aabb: &H::Aabb) -> bool,
+    ) -> (usize, Option<H::Aabb>) {
+        let mut count = 0;
+        let mut bounds: Option<H::Aabb> = None;
+        self.for_each_by(&|ab| branch_func(region, ab), &mut |_, ab, _| {
+            count += 1;
+            bounds = Some(match &bounds {
+                Some(b) => H::aabb_merged(b, ab),
+                None => ab.clone(),
+            });
+        });
+        (count, bounds)
+    }
+
+    /// 查询和指定范围相交的ab节点，返回每层匹配的节点数量，节点所在的层由其aabb的大小决定
+    /// + branch_func同时用于判断分支空间及ab节点是否和范围相交
+    pub fn query_layer_counts(
+        &self,
+        region: &H::Aabb,
+        branch_func: fn(arg: &H::Aabb, aabb: &H::Aabb) -> bool,
+    ) -> Vec<usize> {
+        let mut counts = Vec::new();
+        self.for_each_by(&|ab| branch_func(region, ab), &mut |id, _, _| {
+            let layer = unsafe { self.ab_map.get_unchecked(id) }.layer;
+            if counts.len() <= layer {
+                counts.resize(layer + 1, 0);
+            }
+            counts[layer] += 1;
+        });
+        counts
+    }
+
+    /// 查询和指定范围相交的ab节点，用Ritter算法计算包含所有节点aabb中心点的近似最小外接圆
+    /// + 返回圆心及半径，没有匹配的节点时返回None
+    pub fn query_bounding_circle(
+        &self,
+        region: &H::Aabb,
+        branch_func: fn(arg: &H::Aabb, aabb: &H::Aabb) -> bool,
+    ) -> Option<(H::Point, H::Scalar)> {
+        let mut abs: Vec<&H::Aabb> = Vec::new();
+        self.for_each_by(&|ab| branch_func(region, ab), &mut |_, ab, _| abs.push(ab));
+        let first = H::aabb_center(abs.first()?);
+        // 离任意点最远的点y，再找离y最远的点z，以yz为直径得到初始圆
+        let farthest = |p: &H::Point| {
+            let mut r = (0, H::Scalar::zero());
+            for (i, ab) in abs.iter().enumerate() {
+                let d = H::aabb_center_distance_sq(ab, p);
+                if d > r.1 {
+                    r = (i, d);
+                }
+            }
+            r
+        };
+        let y = H::aabb_center(abs[farthest(&first).0]);
+        let (z, d) = farthest(&y);
+        let two = H::Scalar::one() + H::Scalar::one();
+        let mut center = H::point_lerp(&y, &H::aabb_center(abs[z]), two.recip());
+        let mut radius = d.sqrt() / two;
+        // 逐个扩大圆以包含圆外的点
+        for ab in abs.iter() {
+            let p = H::aabb_center(ab);
+            let d = H::point_distance_sq(&center, &p).sqrt();
+            if d > radius {
+                let r = (radius + d) / two;
+                center = H::point_lerp(&center, &p, (r - radius) / d);
+                radius = r;
+            }
+        }
+        Some((center, radius))
+    }
+
+    /// 查询空间内的ab节点，在位集中设置id的索引对应的位，需要开启fixedbitset特性
+    /// + 要求id可以用在数组索引上，索引超出位集长度时位集会自动扩大
+    #[cfg(feature = "fixedbitset")]
+    pub fn query_bitset(
+        &self,
+        region: &H::Aabb,
+        branch_func: fn(arg: &H::Aabb, aabb: &H::Aabb) -> bool,
+        out: &mut FixedBitSet,
+    ) {
+        self.for_each_by(&|ab| branch_func(region, ab), &mut |id, _, _| {
+            let index = id.index();
+            if index >= out.len() {
+                out.grow(index + 1);
+            }
+            out.insert(index);
+        });
+    }
+
+    /// 查询空间内的ab节点，将id写入调用方提供的缓冲区，不分配内存，可用于热路径
+    /// + branch_func同时用于判断分支空间及ab节点的aabb
+    /// + 返回写入的数量，及是否因为缓冲区已满而截断了结果，写满后遇到下一个节点即停止查询
+    pub fn query_into(
+        &self,
+        region: &H::Aabb,
+        branch_func: fn(arg: &H::Aabb, aabb: &H::Aabb) -> bool,
+        out: &mut [K],
+    ) -> (usize, bool) {
+        type IntoArg<'a, K, Aabb> = (
+            &'a Aabb,
+            fn(arg: &Aabb, aabb: &Aabb) -> bool,
+            &'a mut [K],
+            usize,
+        );
+        fn fill<K: Copy, Aabb, T>(arg: &mut IntoArg<K, Aabb>, id: K, aabb: &Aabb, _: &T) -> bool {
+            if !(arg.1)(arg.0, aabb) {
+                return true;
+            }
+            if arg.3 == arg.2.len() {
+                return false;
+            }
+            arg.2[arg.3] = id;
+            arg.3 += 1;
+            true
+        }
+        let mut arg: IntoArg<K, H::Aabb> = (region, branch_func, out, 0);
+        let complete = self.query_until(false, region, branch_func, &mut arg, fill);
+        (arg.3, !complete)
+    }
+
+    /// 查询和指定范围相交的ab节点，返回借用了aabb及绑定的查询结果
+    pub fn query_collect(&self, region: &H::Aabb) -> QueryResult<'_, K, H::Aabb, T> {
+        let mut result = QueryResult::default();
+        self.for_each_by(
+            &|ab| H::aabb_intersects(region, ab),
+            &mut |id, aabb, bind| result.items.push((id, aabb, bind)),
+        );
+        result
+    }
+
+    /// 查询和指定范围相交的ab节点，按绑定计算的分组键对id分组，可用于按绑定合批
+    #[cfg(feature = "std")]
+    pub fn query_grouped_by<G: Eq + Hash>(
+        &self,
+        region: &H::Aabb,
+        group_fn: impl Fn(&T) -> G,
+    ) -> HashMap<G, Vec<K>> {
+        let mut groups: HashMap<G, Vec<K>> = HashMap::new();
+        self.for_each_by(&|ab| H::aabb_intersects(region, ab), &mut |id, _, bind| {
+            groups.entry(group_fn(bind)).or_default().push(id)
+        });
+        groups
+    }
+
+    /// 查询和指定范围相交的ab节点，并用可写的绑定回调f
+    pub fn for_each_in_mut(&mut self, region: &H::Aabb, mut f: impl FnMut(K, &H::Aabb, &mut T)) {
+        Self::for_each_list_mut(&mut self.ab_map, &self.outer, region, &mut f);
+        Self::for_each_in_mut1(&self.slab, &mut self.ab_map, self.root_key, region, &mut f);
+    }
+
+    // 查询分支空间内和指定范围相交的ab节点，slab只读，ab_map可写
+    fn for_each_in_mut1<F: FnMut(K, &H::Aabb, &mut T)>(
+        slab: &SlotMap<BranchKey, BranchNode<K, H, T, N>>,
+        ab_map: &mut SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>,
+        branch_id: BranchKey,
+        region: &H::Aabb,
+        f: &mut F,
+    ) {
+        let node = unsafe { slab.get_unchecked(branch_id) };
+        Self::for_each_list_mut(ab_map, &node.nodes, region, f);
+        let childs = H::make_childs(&node.aabb, &node.loose);
+        for (i, ab) in childs.iter().enumerate() {
+            if !H::aabb_intersects(region, ab) {
+                continue;
+            }
+            match node.childs[i] {
+                ChildNode::Branch(branch) => {
+                    Self::for_each_in_mut1(slab, ab_map, branch, region, f)
+                }
+                ChildNode::Ab(ref list) => Self::for_each_list_mut(ab_map, list, region, f),
+            }
+        }
+    }
+
+    // 列表中和指定范围相交的ab节点，用可写的绑定回调f
+    fn for_each_list_mut<F: FnMut(K, &H::Aabb, &mut T)>(
+        ab_map: &mut SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>,
+        list: &List<K, H, T, N>,
+        region: &H::Aabb,
+        f: &mut F,
+    ) {
+        for (id, ab) in list.iter_mut(ab_map) {
+            if !ab.active {
+                continue;
+            }
+            let (aabb, bind) = &mut ab.value;
+            if H::aabb_intersects(region, aabb) {
+                f(id, aabb, bind);
+            }
+        }
+    }
+
+    /// 查询空间外的ab节点
+    pub fn query_outer<B>(
+        &self,
+        arg: &mut B,
+        func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &T),
+    ) {
+        for (id, ab) in self.outer.iter(&self.ab_map) {
+            if ab.active {
+                func(arg, id, &ab.value.0, &ab.value.1);
+            }
+        }
+    }
+
+    /// 查询离指定点最远的ab节点，返回其id及点到其aabb的最近距离的平方
+    /// + 按分支空间到点的最远距离（上界）优先遍历，上界不超过当前最远值的分支空间会被剪枝
+    pub fn query_farthest(&self, point: &H::Point) -> Option<(K, H::Scalar)> {
+        let mut result: Option<(K, H::Scalar)> = None;
+        Self::farthest_list(&self.ab_map, &self.outer, point, &mut result);
+        let mut heap = BinaryHeap::new();
+        let root = unsafe { self.slab.get_unchecked(self.root_key) };
+        heap.push(Priority(
+            H::aabb_point_max_distance_sq(&root.aabb, point),
+            self.root_key,
+        ));
+        while let Some(Priority(d, branch_id)) = heap.pop() {
+            if let Some((_, max)) = result {
+                if d <= max {
+                    break;
+                }
+            }
+            let node = unsafe { self.slab.get_unchecked(branch_id) };
+            Self::farthest_list(&self.ab_map, &node.nodes, point, &mut result);
+            let childs = H::make_childs(&node.aabb, &node.loose);
+            for (i, ab) in childs.iter().enumerate() {
+                match node.childs[i] {
+                    ChildNode::Branch(branch) => {
+                        heap.push(Priority(H::aabb_point_max_distance_sq(ab, point), branch));
+                    }
+                    ChildNode::Ab(ref list) if !list.is_empty() => {
+                        let d = H::aabb_point_max_distance_sq(ab, point);
+                        match result {
+                            Some((_, max)) if d <= max => (),
+                            _ => Self::farthest_list(&self.ab_map, list, point, &mut result),
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        result
+    }
+    // 在列表中查找离点最远的ab节点
+    fn farthest_list(
+        ab_map: &SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>,
+        list: &List<K, H, T, N>,
+        point: &H::Point,
+        result: &mut Option<(K, H::Scalar)>,
+    ) {
+        for (id, ab) in list.iter(ab_map) {
+            if !ab.active {
+                continue;
+            }
+            let d = H::aabb_point_distance_sq(&ab.value.0, point);
+            match result {
+                Some((_, max)) if d <= *max => (),
+                _ => *result = Some((id, d)),
+            }
+        }
+    }
+
+    /// 查询离指定点最近的k个ab节点，返回按距离从近到远排序的id及点到其aabb的最近距离的平方
+    /// + 按分支空间到点的最近距离优先遍历，最近距离超过当前第k近的分支空间会被剪枝
+    pub fn k_nearest(&self, point: &H::Point, k: usize) -> Vec<(K, H::Scalar)> {
+        self.k_nearest_by(point, k, H::aabb_point_distance_sq)
+    }
+
+    /// 查询中心离指定点最近的2个不同的ab节点，按距离从近到远返回，节点不足2个返回None
+    pub fn two_nearest(&self, point: &H::Point) -> Option<(K, K)> {
+        match self.k_nearest_by(point, 2, H::aabb_center_distance_sq)[..] {
+            [(a, _), (b, _)] => Some((a, b)),
+            _ => None,
+        }
+    }
+
+    // 按指定的距离函数查询离点最近的k个ab节点，要求节点的距离不小于点到其所在分支空间的最近距离
+    fn k_nearest_by(
+        &self,
+        point: &H::Point,
+        k: usize,
+        dist: fn(&H::Aabb, &H::Point) -> H::Scalar,
+    ) -> Vec<(K, H::Scalar)> {
+        if k == 0 {
+            return Vec::new();
+        }
+        let mut result = BinaryHeap::with_capacity(k + 1);
+        Self::nearest_list(&self.ab_map, &self.outer, point, k, dist, &mut result);
+        let mut heap = BinaryHeap::new();
+        let root = unsafe { self.slab.get_unchecked(self.root_key) };
+        heap.push(Reverse(Priority(
+            H::aabb_point_distance_sq(&root.aabb, point),
+            self.root_key,
+        )));
+        while let Some(Reverse(Priority(d, branch_id))) = heap.pop() {
+            if Self::nearest_prune(&result, k, d) {
+                break;
+            }
+            let node = unsafe { self.slab.get_unchecked(branch_id) };
+            Self::nearest_list(&self.ab_map, &node.nodes, point, k, dist, &mut result);
+            let childs = H::make_childs(&node.aabb, &node.loose);
+            for (i, ab) in childs.iter().enumerate() {
+                match node.childs[i] {
+                    ChildNode::Branch(branch) => {
+                        heap.push(Reverse(Priority(
+                            H::aabb_point_distance_sq(ab, point),
+                            branch,
+                        )));
+                    }
+                    ChildNode::Ab(ref list) if !list.is_empty() => {
+                        let d = H::aabb_point_distance_sq(ab, point);
+                        if !Self::nearest_prune(&result, k, d) {
+                            Self::nearest_list(&self.ab_map, list, point, k, dist, &mut result);
+                        }
+                    }
+                    _ => (),
+                }
+            }
+        }
+        result
+            .into_sorted_vec()
+            .into_iter()
+            .map(|Priority(d, id)| (id, d))
+            .collect()
+    }
+    // 判断距离是否超过当前第k近的距离
+    fn nearest_prune(result: &BinaryHeap<Priority<H::Scalar, K>>, k: usize, d: H::Scalar) -> bool {
+        match result.peek() {
+            Some(max) if result.len() >= k => d > max.0,
+            _ => false,
+        }
+    }
+    // 将列表中离点更近的ab节点放入结果堆，堆中最多保留k个
+    fn nearest_list(
+        ab_map: &SecondaryMap<K, Node<K, AbNode<H::Aabb, T>>>,
+        list: &List<K, H, T, N>,
+        point: &H::Point,
+        k: usize,
+        dist: fn(&H::Aabb, &H::Point) -> H::Scalar,
+        result: &mut BinaryHeap<Priority<H::Scalar, K>>,
+    ) {
+        for (id, ab) in list.iter(ab_map) {
+            if !ab.active {
+                continue;
+            }
+            let d = dist(&ab.value.0, point);
+            if result.len() < k {
+                result.push(Priority(d, id));
+            } else if d < result.peek().unwrap().0 {
+                result.pop();
+                result.push(Priority(d, id));
+            }
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ab_map.len()
+    }
+
+    /// 检查松散值的变化是否正确，用于检查自定义Helper的create_child实现，只在debug下可用
+    /// + 沿第0个子节点从根走到最大深度，松散值在loose_layer之前每层减半，之后等于min_loose
+    #[cfg(debug_assertions)]
+    pub fn validate_loose_progression(&self) -> Result<(), String> {
+        let root = unsafe { self.slab.get_unchecked(self.root_key) };
+        let mut aabb = root.aabb.clone();
+        let mut loose = root.loose.clone();
+        for layer in 0..self.deep {
+            let (child, child_loose) =
+                H::create_child(&aabb, &loose, layer, self.loose_layer, &self.min_loose, 0);
+            if layer < self.loose_layer {
+                if H::calc_layer(&loose, &child_loose) != 1 {
+                    return Err(format!("layer {}: loose is not halved", layer + 1));
+                }
+            } else if !H::smaller_than_min_loose(&child_loose, &self.min_loose)
+                || !H::smaller_than_min_loose(&self.min_loose, &child_loose)
+            {
+                return Err(format!(
+                    "layer {}: loose is not min_loose beyond loose_layer {}",
+                    layer + 1,
+                    self.loose_layer
+                ));
+            }
+            if !H::aabb_contains(&aabb, &child) {
+                return Err(format!("layer {}: child is out of parent", layer + 1));
+            }
+            aabb = child;
+            loose = child_loose;
+        }
+        Ok(())
+    }
+
+    /// 校验叉树的结构，用于测试及调试自定义的Helper，返回发现的第一个错误
+    /// + 检查每个ab节点的parent、parent_child、layer和实际所在的链表一致，链表的前后链接及长度正确，节点不重复出现
+    /// + 检查每个分支节点的parent、parent_child、layer和实际位置一致，ab_map和slab中没有游离的节点
+    pub fn validate(&self) -> Result<(), TreeError<K>> {
+        let mut visited = SecondaryMap::default();
+        let mut branches = SecondaryMap::default();
+        self.validate_branch(self.root_key, &mut visited, &mut branches)?;
+        // outer最后检查，被错误链到outer上的节点会报告为重复
+        self.validate_list(
+            &self.outer,
+            BranchKey::null(),
+            N as u8,
+            |_| true,
+            &mut visited,
+        )?;
+        if let Some((id, _)) = self
+            .ab_map
+            .iter()
+            .find(|(id, _)| !visited.contains_key(*id))
+        {
+            return Err(TreeError::UnlinkedNode { id });
+        }
+        if let Some((branch, _)) = self.slab.iter().find(|(id, _)| !branches.contains_key(*id)) {
+            return Err(TreeError::OrphanBranch { branch });
+        }
+        Ok(())
+    }
+    // 校验分支节点及其下的链表和子分支
+    fn validate_branch(
+        &self,
+        branch_id: BranchKey,
+        visited: &mut SecondaryMap<K, ()>,
+        branches: &mut SecondaryMap<BranchKey, ()>,
+    ) -> Result<(), TreeError<K>> {
+        branches.insert(branch_id, ());
+        let branch = unsafe { self.slab.get_unchecked(branch_id) };
+        let layer = branch.layer;
+        self.validate_list(&branch.nodes, branch_id, N as u8, |l| l <= layer, visited)?;
+        for (i, child) in branch.childs.iter().enumerate() {
+            match child {
+                ChildNode::Branch(child_id) => {
+                    match self.slab.get(*child_id) {
+                        Some(c)
+                            if c.parent == branch_id
+                                && c.parent_child as usize == i
+                                && c.layer == layer + 1
+                                && !branches.contains_key(*child_id) => {}
+                        _ => return Err(TreeError::WrongBranch { branch: *child_id }),
+                    }
+                    self.validate_branch(*child_id, visited, branches)?;
+                }
+                ChildNode::Ab(list) => {
+                    self.validate_list(list, branch_id, i as u8, |l| l > layer, visited)?
+                }
+            }
+        }
+        Ok(())
+    }
+    // 校验链表上的ab节点
+    fn validate_list(
+        &self,
+        list: &List<K, H, T, N>,
+        branch: BranchKey,
+        child: u8,
+        layer_ok: impl Fn(usize) -> bool,
+        visited: &mut SecondaryMap<K, ()>,
+    ) -> Result<(), TreeError<K>> {
+        let mut id = list.head();
+        let mut prev = K::null();
+        let mut count = 0;
+        while !id.is_null() {
+            let node = match self.ab_map.get(id) {
+                Some(node) => node,
+                None => return Err(TreeError::MissingNode { branch, id }),
+            };
+            if visited.insert(id, ()).is_some() {
+                return Err(TreeError::DuplicateNode { branch, id });
+            }
+            if node.prev() != prev {
+                return Err(TreeError::BrokenLink { branch, id });
+            }
+            if node.parent != branch {
+                return Err(TreeError::WrongParent {
+                    id,
+                    expected: branch,
+                    found: node.parent,
+                });
+            }
+            if node.parent_child != child {
+                return Err(TreeError::WrongParentChild {
+                    id,
+                    expected: child,
+                    found: node.parent_child,
+                });
+            }
+            if !layer_ok(node.layer) {
+                return Err(TreeError::WrongLayer {
+                    branch,
+                    id,
+                    layer: node.layer,
+                });
+            }
+            count += 1;
+            prev = id;
+            id = node.next();
+        }
+        if count != list.len() {
+            return Err(TreeError::WrongLength {
+                branch,
+                child,
+                expected: list.len(),
+                found: count,
+            });
+        }
+        if list.tail() != prev {
+            return Err(TreeError::BrokenLink { branch, id: prev });
+        }
+        Ok(())
+    }
+
+    /// 获得叉树的统计信息，从根节点深度优先遍历一次
+    pub fn stats(&self) -> TreeStats {
+        let mut stats = TreeStats {
+            branch_count: self.slab.len(),
+            outer_count: self.outer.len(),
+            ..Default::default()
+        };
+        self.stats1(self.root_key, &mut stats);
+        stats
+    }
+    // 统计分支节点及其子节点
+    fn stats1(&self, branch_id: BranchKey, stats: &mut TreeStats) {
+        fn count(stats: &mut TreeStats, layer: usize, len: usize) {
+            if len == 0 {
+                return;
+            }
+            if stats.layer_counts.len() <= layer {
+                stats.layer_counts.resize(layer + 1, 0);
+            }
+            stats.layer_counts[layer] += len;
+            stats.max_layer = stats.max_layer.max(layer);
+        }
+        let node = unsafe { self.slab.get_unchecked(branch_id) };
+        count(stats, node.layer, node.nodes.len());
+        for child in node.childs.iter() {
+            match child {
+                ChildNode::Branch(branch) => self.stats1(*branch, stats),
+                ChildNode::Ab(list) => {
+                    stats.max_list_len = stats.max_list_len.max(list.len());
+                    count(stats, node.layer + 1, list.len());
+                }
+            }
+        }
+    }
+
+    /// 迭代所有的ab节点，无论其在分支节点、子节点列表还是outer上
+    pub fn iter(&self) -> impl Iterator<Item = (K, &H::Aabb, &T)> {
+        self.ab_map
+            .iter()
+            .map(|(id, node)| (id, &node.value.0, &node.value.1))
+    }
+
+    /// 深度优先迭代所有的分支节点，返回(分支, aabb, 松散值, 层)，根节点最先返回，子节点按索引顺序
+    /// + 配合Helper的make_childs可以得到每个子空间的松散aabb，用于调试时绘制叉树的划分
+    pub fn iter_branches(&self) -> impl Iterator<Item = (BranchKey, &H::Aabb, &H::Vector, usize)> {
+        let mut stack = vec![self.root_key];
+        ::core::iter::from_fn(move || {
+            let key = stack.pop()?;
+            let node = unsafe { self.slab.get_unchecked(key) };
+            for child in node.childs.iter().rev() {
+                if let ChildNode::Branch(branch) = child {
+                    stack.push(*branch);
+                }
+            }
+            Some((key, &node.aabb, &node.loose, node.layer))
+        })
+    }
+
+    /// 迭代所有的ab节点，只能修改绑定，修改aabb请使用update
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (K, &H::Aabb, &mut T)> {
+        self.ab_map.iter_mut().map(|(id, node)| {
+            let (aabb, bind) = &mut node.value;
+            (id, &*aabb, bind)
+        })
+    }
+
+    /// 检查碰撞对，遍历所有aabb相交的ab节点对，每对只回调一次
+    /// + 利用叉树结构避免O(n²)：每对节点只在其最近公共祖先的BranchNode上检查，祖先上的节点总是作为a传入
+    /// + outer上的节点会和outer及树内的所有节点检查，未激活的节点不参与检查
+    pub fn collision_pairs<A>(&self, arg: &mut A, func: CollisionFunc<A, K, H::Aabb, T>) {
+        let root = unsafe { self.slab.get_unchecked(self.root_key) };
+        for (id, ab) in self.outer.iter(&self.ab_map) {
+            self.collision_next(id, ab, arg, func);
+            if H::aabb_intersects(&ab.value.0, &root.aabb) {
+                self.collision_branch(id, ab, self.root_key, arg, func);
+            }
+        }
+        self.collision_pairs1(self.root_key, arg, func);
+    }
+
+    /// 检查指定范围内的碰撞对，只遍历至少有一个节点和region相交的aabb相交的节点对，每对只回调一次
+    /// + 先查询和region相交的节点，再用每个节点的aabb查询和其相交的节点，开销和范围内的节点数量成正比，适合只模拟活动区域的大场景
+    /// + a总是和region相交的节点，未激活的节点不参与检查
+    pub fn collision_pairs_in<A>(
+        &self,
+        region: &H::Aabb,
+        arg: &mut A,
+        func: CollisionFunc<A, K, H::Aabb, T>,
+    ) {
+        let mut members = Vec::new();
+        self.for_each_by(&|ab| H::aabb_intersects(region, ab), &mut |id, ab, bind| {
+            members.push((id, ab, bind))
+        });
+        // 已经检查过的范围内的节点，和其相交的节点对已经回调过
+        let mut done: SecondaryMap<K, ()> = SecondaryMap::with_capacity(members.len());
+        for (id, ab, bind) in members {
+            self.for_each_by(
+                &|other| H::aabb_intersects(ab, other),
+                &mut |other_id, other, other_bind| {
+                    if other_id != id && !done.contains_key(other_id) {
+                        func(arg, id, ab, bind, other_id, other, other_bind);
+                    }
+                },
+            );
+            done.insert(id, ());
+        }
+    }
+
+    // 检查指定BranchNode为最近公共祖先的碰撞对
+    fn collision_pairs1<A>(
+        &self,
+        branch_id: BranchKey,
+        arg: &mut A,
+        func: CollisionFunc<A, K, H::Aabb, T>,
+    ) {
+        let node = unsafe { self.slab.get_unchecked(branch_id) };
+        let childs = H::make_childs(&node.aabb, &node.loose);
+        // 本层的节点和本层的节点及所有子空间检查
+        for (id, ab) in node.nodes.iter(&self.ab_map) {
+            self.collision_next(id, ab, arg, func);
+            for (i, child) in node.childs.iter().enumerate() {
+                if H::aabb_intersects(&ab.value.0, &childs[i]) {
+                    self.collision_child(id, ab, child, arg, func);
+                }
+            }
+        }
+        for i in 0..N {
+            // 子空间内部的碰撞对
+            match node.childs[i] {
+                ChildNode::Branch(branch) => self.collision_pairs1(branch, arg, func),
+                ChildNode::Ab(ref list) => {
+                    for (id, ab) in list.iter(&self.ab_map) {
+                        self.collision_next(id, ab, arg, func);
+                    }
+                }
+            }
+            // 松散区域重叠的兄弟子空间之间的碰撞对
+            for j in i + 1..N {
+                if H::aabb_intersects(&childs[i], &childs[j]) {
+                    self.collision_childs(&node.childs[i], &node.childs[j], &childs[j], arg, func);
+                }
             }
         }
     }
-    /// 查询空间外的ab节点
-    pub fn query_outer<B>(
+
+    // 子空间a的所有节点和子空间b检查
+    fn collision_childs<A>(
         &self,
-        arg: &mut B,
-        func: fn(arg: &mut B, id: K, aabb: &H::Aabb, bind: &T),
+        a: &ChildNode<K, H, T, N>,
+        b: &ChildNode<K, H, T, N>,
+        b_aabb: &H::Aabb,
+        arg: &mut A,
+        func: CollisionFunc<A, K, H::Aabb, T>,
     ) {
-        for (id, ab) in self.outer.iter(&self.ab_map) {
-            func(arg, id, &ab.value.0, &ab.value.1);
+        match a {
+            ChildNode::Branch(branch) => {
+                let node = unsafe { self.slab.get_unchecked(*branch) };
+                for (id, ab) in node.nodes.iter(&self.ab_map) {
+                    if H::aabb_intersects(&ab.value.0, b_aabb) {
+                        self.collision_child(id, ab, b, arg, func);
+                    }
+                }
+                let childs = H::make_childs(&node.aabb, &node.loose);
+                for (i, child) in node.childs.iter().enumerate() {
+                    if H::aabb_intersects(&childs[i], b_aabb) {
+                        self.collision_childs(child, b, b_aabb, arg, func);
+                    }
+                }
+            }
+            ChildNode::Ab(list) => {
+                for (id, ab) in list.iter(&self.ab_map) {
+                    if H::aabb_intersects(&ab.value.0, b_aabb) {
+                        self.collision_child(id, ab, b, arg, func);
+                    }
+                }
+            }
+        }
+    }
+
+    // 指定节点和子空间内的所有节点检查
+    fn collision_child<A>(
+        &self,
+        id: K,
+        ab: &AbNode<H::Aabb, T>,
+        child: &ChildNode<K, H, T, N>,
+        arg: &mut A,
+        func: CollisionFunc<A, K, H::Aabb, T>,
+    ) {
+        match child {
+            ChildNode::Branch(branch) => self.collision_branch(id, ab, *branch, arg, func),
+            ChildNode::Ab(list) => self.collision_list(id, ab, list, arg, func),
+        }
+    }
+
+    // 指定节点和BranchNode下的所有节点检查
+    fn collision_branch<A>(
+        &self,
+        id: K,
+        ab: &AbNode<H::Aabb, T>,
+        branch_id: BranchKey,
+        arg: &mut A,
+        func: CollisionFunc<A, K, H::Aabb, T>,
+    ) {
+        let node = unsafe { self.slab.get_unchecked(branch_id) };
+        self.collision_list(id, ab, &node.nodes, arg, func);
+        let childs = H::make_childs(&node.aabb, &node.loose);
+        for (i, child) in node.childs.iter().enumerate() {
+            if H::aabb_intersects(&ab.value.0, &childs[i]) {
+                self.collision_child(id, ab, child, arg, func);
+            }
+        }
+    }
+
+    // 指定节点和列表中的所有节点检查
+    fn collision_list<A>(
+        &self,
+        id: K,
+        ab: &AbNode<H::Aabb, T>,
+        list: &List<K, H, T, N>,
+        arg: &mut A,
+        func: CollisionFunc<A, K, H::Aabb, T>,
+    ) {
+        if !ab.active {
+            return;
+        }
+        for (other_id, other) in list.iter(&self.ab_map) {
+            if other.active && H::aabb_intersects(&ab.value.0, &other.value.0) {
+                func(
+                    arg,
+                    id,
+                    &ab.value.0,
+                    &ab.value.1,
+                    other_id,
+                    &other.value.0,
+                    &other.value.1,
+                );
+            }
+        }
+    }
+
+    // 指定节点和同列表中排在其后的节点检查
+    fn collision_next<A>(
+        &self,
+        id: K,
+        ab: &AbNode<H::Aabb, T>,
+        arg: &mut A,
+        func: CollisionFunc<A, K, H::Aabb, T>,
+    ) {
+        if !ab.active {
+            return;
+        }
+        let mut next = unsafe { self.ab_map.get_unchecked(id) }.next();
+        while !next.is_null() {
+            let other = unsafe { self.ab_map.get_unchecked(next) };
+            if other.active && H::aabb_intersects(&ab.value.0, &other.value.0) {
+                func(
+                    arg,
+                    id,
+                    &ab.value.0,
+                    &ab.value.1,
+                    next,
+                    &other.value.0,
+                    &other.value.1,
+                );
+            }
+            next = other.next();
         }
     }
+}
 
+/// 查询结果，借用叉树中的aabb及绑定
+pub struct QueryResult<'a, K, Aabb, T> {
+    pub items: Vec<(K, &'a Aabb, &'a T)>,
+}
+impl<'a, K, Aabb, T> Default for QueryResult<'a, K, Aabb, T> {
+    fn default() -> Self {
+        QueryResult { items: Vec::new() }
+    }
+}
+impl<'a, K: Copy, Aabb, T> QueryResult<'a, K, Aabb, T> {
+    /// 获得所有的id
+    pub fn ids(&self) -> Vec<K> {
+        self.items.iter().map(|r| r.0).collect()
+    }
     pub fn len(&self) -> usize {
-        self.ab_map.len()
+        self.items.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.items.is_empty()
+    }
+    pub fn iter(&self) -> impl Iterator<Item = &(K, &'a Aabb, &'a T)> {
+        self.items.iter()
+    }
+    /// 按点到aabb的距离从近到远排序，距离函数一般为Helper的aabb_point_distance_sq
+    pub fn sort_by_distance<P, S: PartialOrd>(&mut self, point: &P, distance: fn(&Aabb, &P) -> S) {
+        self.items.sort_by(|a, b| {
+            distance(a.1, point)
+                .partial_cmp(&distance(b.1, point))
+                .unwrap_or(Ordering::Equal)
+        });
+    }
+}
+
+///
+/// 叉树的构建器，用具名的方法设置参数，避免new的位置参数互相写反
+///
+/// + 根空间必须设置，否则build返回None
+/// + 最大松散值默认为根空间的尺寸，最小松散值默认和最大松散值相同
+/// + 收缩及分裂的阈值、深度限制默认为0，和new一样表示使用默认值；自动整理的阈值默认为1024
+///
+pub struct TreeBuilder<H: Helper<N>, const N: usize> {
+    root: Option<H::Aabb>,
+    max_loose: Option<H::Vector>,
+    min_loose: Option<H::Vector>,
+    adjust: (usize, usize),
+    deep: usize,
+    auto_collect: usize,
+}
+impl<H: Helper<N>, const N: usize> Default for TreeBuilder<H, N> {
+    fn default() -> Self {
+        TreeBuilder {
+            root: None,
+            max_loose: None,
+            min_loose: None,
+            adjust: (0, 0),
+            deep: 0,
+            auto_collect: AUTO_COLLECT,
+        }
+    }
+}
+impl<H: Helper<N>, const N: usize> TreeBuilder<H, N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// 设置根空间
+    pub fn root(mut self, root: H::Aabb) -> Self {
+        self.root = Some(root);
+        self
+    }
+    /// 设置最大松散值，第一层的松散大小
+    pub fn max_loose(mut self, max_loose: H::Vector) -> Self {
+        self.max_loose = Some(max_loose);
+        self
+    }
+    /// 设置最小松散值
+    pub fn min_loose(mut self, min_loose: H::Vector) -> Self {
+        self.min_loose = Some(min_loose);
+        self
+    }
+    /// 设置节点收缩和分化的阈值，小于min收缩，大于max分化
+    pub fn adjust(mut self, adjust_min: usize, adjust_max: usize) -> Self {
+        self.adjust = (adjust_min, adjust_max);
+        self
+    }
+    /// 设置深度限制
+    pub fn deep(mut self, deep: usize) -> Self {
+        self.deep = deep;
+        self
+    }
+    /// 设置自动整理的阈值
+    pub fn auto_collect(mut self, auto_collect: usize) -> Self {
+        self.auto_collect = auto_collect;
+        self
+    }
+    /// 构建叉树，没有设置根空间时返回None
+    pub fn build<K: Key, T>(self) -> Option<Tree<K, H, T, N>> {
+        let root = self.root?;
+        let max_loose = self.max_loose.unwrap_or_else(|| H::aabb_extents(&root));
+        let min_loose = self.min_loose.unwrap_or_else(|| max_loose.clone());
+        let mut tree = Tree::new(
+            root,
+            max_loose,
+            min_loose,
+            self.adjust.0,
+            self.adjust.1,
+            self.deep,
+        );
+        tree.auto_collect = self.auto_collect;
+        Some(tree)
+    }
+}
+
+// TreeGroup::query的ab节点回调，带有叉树的索引
+type GroupAbFunc<K, Aabb, T, B> = fn(&mut B, usize, K, &Aabb, &T);
+
+///
+/// 叉树组，按碰撞类别等分开的多棵叉树，可以统一查询
+///
+/// + 查询结果带有来源叉树的索引
+///
+pub struct TreeGroup<K: Key, H: Helper<N>, T, const N: usize> {
+    pub trees: Vec<Tree<K, H, T, N>>,
+}
+impl<K: Key, H: Helper<N>, T, const N: usize> Default for TreeGroup<K, H, T, N> {
+    fn default() -> Self {
+        TreeGroup { trees: Vec::new() }
+    }
+}
+impl<K: Key, H: Helper<N>, T, const N: usize> TreeGroup<K, H, T, N> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    /// 添加一棵叉树，返回其索引
+    pub fn push(&mut self, tree: Tree<K, H, T, N>) -> usize {
+        self.trees.push(tree);
+        self.trees.len() - 1
+    }
+    /// 获得指定索引的叉树
+    pub fn get(&self, index: usize) -> Option<&Tree<K, H, T, N>> {
+        self.trees.get(index)
+    }
+    /// 获得指定索引的可写叉树
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Tree<K, H, T, N>> {
+        self.trees.get_mut(index)
+    }
+    pub fn len(&self) -> usize {
+        self.trees.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.trees.is_empty()
     }
+    /// 依次查询每棵叉树，同Tree::query，ab节点连同叉树索引回调ab_func，由ab_func判断ab节点是否满足
+    pub fn query<A, B>(
+        &self,
+        branch_arg: &A,
+        branch_func: fn(arg: &A, aabb: &H::Aabb) -> bool,
+        ab_arg: &mut B,
+        ab_func: fn(arg: &mut B, index: usize, id: K, aabb: &H::Aabb, bind: &T),
+    ) {
+        for (index, tree) in self.trees.iter().enumerate() {
+            let mut arg = (index, &mut *ab_arg, ab_func);
+            tree.query(branch_arg, branch_func, &mut arg, Self::tagged);
+        }
+    }
+
+    // 将叉树索引传给ab_func
+    fn tagged<B>(
+        arg: &mut (usize, &mut B, GroupAbFunc<K, H::Aabb, T, B>),
+        id: K,
+        aabb: &H::Aabb,
+        bind: &T,
+    ) {
+        (arg.2)(arg.1, arg.0, id, aabb, bind)
+    }
+    /// 查询所有叉树中和指定范围相交的ab节点，返回(叉树索引, id)，按叉树索引排列
+    pub fn query_ids(&self, region: &H::Aabb) -> Vec<(usize, K)> {
+        let mut result = Vec::new();
+        for (index, tree) in self.trees.iter().enumerate() {
+            tree.for_each_by(&|ab| H::aabb_intersects(region, ab), &mut |id, _, _| {
+                result.push((index, id))
+            });
+        }
+        result
+    }
+}
+
+/// 记录了查询范围及匹配id的结果集，用于持续的范围效果，可以对当前的叉树重新查询，得到进入及离开的id
+#[derive(Debug, Clone)]
+pub struct QuerySet<K, Aabb> {
+    region: Aabb,
+    ids: Vec<K>,
+}
+impl<K: Key, Aabb: Clone> QuerySet<K, Aabb> {
+    /// 获得查询范围
+    pub fn region(&self) -> &Aabb {
+        &self.region
+    }
+    /// 获得最近一次查询匹配的id
+    pub fn ids(&self) -> &[K] {
+        &self.ids
+    }
+    /// 对当前的叉树重新查询，返回(进入范围的id, 离开范围的id)，结果集更新为本次查询的结果
+    /// + 被移除或未激活的节点算作离开
+    pub fn refresh<H: Helper<N, Aabb = Aabb>, T, const N: usize>(
+        &mut self,
+        tree: &Tree<K, H, T, N>,
+    ) -> (Vec<K>, Vec<K>) {
+        let mut ids = Vec::new();
+        let region = &self.region;
+        tree.for_each_by(&|ab| H::aabb_intersects(region, ab), &mut |id, _, _| {
+            ids.push(id)
+        });
+        let mut old = SecondaryMap::default();
+        for id in &self.ids {
+            old.insert(*id, ());
+        }
+        let mut current = SecondaryMap::default();
+        for id in &ids {
+            current.insert(*id, ());
+        }
+        let entered = ids
+            .iter()
+            .filter(|id| !old.contains_key(**id))
+            .copied()
+            .collect();
+        let left = self
+            .ids
+            .iter()
+            .filter(|id| !current.contains_key(**id))
+            .copied()
+            .collect();
+        self.ids = ids;
+        (entered, left)
+    }
+}
+
+/// 叉树的统计信息，用于调整收缩分裂阈值及深度
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TreeStats {
+    pub branch_count: usize,      // 分支节点的数量
+    pub max_layer: usize,         // 有ab节点的最深的层
+    pub outer_count: usize,       // outer上的ab节点数量
+    pub max_list_len: usize,      // ChildNode的Ab(List)的最大长度
+    pub layer_counts: Vec<usize>, // 每层的ab节点数量，BranchNode的nodes在本层，Ab(List)在下一层
+}
+
+/// 整理的结果报告，全0表示结构没有改变
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CollectReport {
+    pub branches_created: usize,   // 分裂创建的BranchNode数量
+    pub branches_destroyed: usize, // 收缩销毁的BranchNode数量
+    pub nodes_relocated: usize,    // ab节点被重新放置的次数，多次分裂时同一节点可能被计数多次
+}
+
+impl AddAssign for CollectReport {
+    fn add_assign(&mut self, other: Self) {
+        self.branches_created += other.branches_created;
+        self.branches_destroyed += other.branches_destroyed;
+        self.nodes_relocated += other.nodes_relocated;
+    }
+}
 
-    // 检查碰撞对，不会检查outer的aabb。一般arg包含1个hashset，用(big, little)做键，判断是否已经计算过。
-    // pub fn collision<A>(
-    //     &self,
-    //     id: K,
-    //     _limit_layer: usize,
-    //     arg: &mut A,
-    //     func: fn(
-    //         arg: &mut A,
-    //         a_id: usize,
-    //         a_aabb: &H::AABB,
-    //         a_bind: &T,
-    //         b_id: usize,
-    //         b_aabb: &H::AABB,
-    //         b_bind: &T,
-    //     ) -> bool,
-    // ) {
-    //     let a = match self.ab_map.get(id) {
-    //         Some(ab) => ab,
-    //         _ => return,
-    //     };
-    //     // 先判断root.nodes是否有节点，如果有则遍历root的nodes
-    //     let node = unsafe { self.branch_slab.get_unchecked(1) };
-    //     collision_list(
-    //         &self.ab_map,
-    //         id,
-    //         &a.aabb,
-    //         &a.value.1,
-    //         arg,
-    //         func,
-    //         node.nodes.head,
-    //     );
-    //     // 和同列表节点碰撞
-    //     collision_list(&self.ab_map, id, &a.aabb, &a.value.1, arg, func, a.next);
-    //     let mut prev = a.prev;
-    //     while prev > 0 {
-    //         let b = unsafe { self.ab_map.get_unchecked(prev) };
-    //         func(arg, id, &a.aabb, &a.value.1, prev, &b.aabb, &b.value.1);
-    //         prev = b.prev;
-    //     }
-    //     // 需要计算是否在重叠区，如果在，则需要上溯检查重叠的兄弟节点。不在，其实也需要上溯检查父的匹配节点，但可以提前计算ab节点的最小层
-    //     //}
-    // }
+/// 叉树结构校验的错误，标识出问题的分支节点及ab节点
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TreeError<K> {
+    /// 链表中的id在ab_map中不存在
+    MissingNode { branch: BranchKey, id: K },
+    /// ab节点出现在多个链表中，或链表成环
+    DuplicateNode { branch: BranchKey, id: K },
+    /// ab节点的prev和链表中的前一个节点不一致，或链表尾不是最后一个节点
+    BrokenLink { branch: BranchKey, id: K },
+    /// ab节点的parent和所在的分支节点不一致，outer上的节点parent为Null
+    WrongParent {
+        id: K,
+        expected: BranchKey,
+        found: BranchKey,
+    },
+    /// ab节点的parent_child和所在的链表位置不一致，nodes上的节点为N
+    WrongParentChild { id: K, expected: u8, found: u8 },
+    /// ab节点的层和所在的链表不匹配，nodes上的节点不大于分支的层，子节点链表上的节点大于分支的层
+    WrongLayer {
+        branch: BranchKey,
+        id: K,
+        layer: usize,
+    },
+    /// 链表记录的长度和实际节点数量不一致
+    WrongLength {
+        branch: BranchKey,
+        child: u8,
+        expected: usize,
+        found: usize,
+    },
+    /// ab_map中的节点不在任何链表上
+    UnlinkedNode { id: K },
+    /// 子分支节点不存在，或其parent、parent_child、layer和所在位置不一致
+    WrongBranch { branch: BranchKey },
+    /// slab中的分支节点无法从根节点到达
+    OrphanBranch { branch: BranchKey },
+}
+
+/// 添加ab节点失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddError {
+    /// id已存在
+    DuplicateKey,
+    /// aabb无效，包含NaN、无穷值或min大于max
+    InvalidAabb,
+}
+
+/// ab节点在叉树中的位置
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Location {
+    /// 在outer上，和根空间相交或在其外
+    Outer { layer: usize },
+    /// 在分支节点上，parent_child为N表示在分支节点本层的nodes上，否则为所在子节点链表的索引
+    Branch {
+        branch: BranchKey,
+        parent_child: u8,
+        layer: usize,
+    },
 }
 
 //////////////////////////////////////////////////////本地/////////////////////////////////////////////////////////////////
 
 #[derive(Clone)]
 pub struct BranchNode<K: Key, H: Helper<N>, T, const N: usize> {
-    aabb: H::Aabb,                      // 包围盒
-    loose: H::Vector,                   // 本层的松散值
-    layer: usize,                       // 表示第几层， 根据aabb大小，决定最低为第几层
-    parent: BranchKey,                  // 父八叉空间
-    childs: [ChildNode<K, H, T, N>; N], // 子八叉空间
-    nodes: List<K, H, T, N>,            // 匹配本层大小的ab节点列表，及节点数量
-    parent_child: u8,                   // 对应父八叉空间childs的位置
+    pub(crate) aabb: H::Aabb,                      // 包围盒
+    loose: H::Vector,                              // 本层的松散值
+    pub(crate) layer: usize,                       // 表示第几层， 根据aabb大小，决定最低为第几层
+    pub(crate) parent: BranchKey,                  // 父八叉空间
+    pub(crate) childs: [ChildNode<K, H, T, N>; N], // 子八叉空间
+    pub(crate) nodes: List<K, H, T, N>,            // 匹配本层大小的ab节点列表，及节点数量
+    pub(crate) parent_child: u8,                   // 对应父八叉空间childs的位置
     dirty: bool, // 脏标记. 添加了节点，并且某个子八叉空间(AbNode)的数量超过分裂阈值，可能分裂。删除了节点，并且自己及其下ab节点的数量小于收缩阈值，可能收缩
 }
 impl<K: Key, H: Helper<N>, T, const N: usize> BranchNode<K, H, T, N> {
@@ -892,17 +3160,21 @@ impl<K: Key, H: Helper<N>, T, const N: usize> BranchNode<K, H, T, N> {
     }
 }
 #[derive(Clone)]
-enum ChildNode<K: Key, H: Helper<N>, T, const N: usize> {
+pub(crate) enum ChildNode<K: Key, H: Helper<N>, T, const N: usize> {
     Branch(BranchKey),    // 对应的BranchNode, 及其下ab节点的数量
     Ab(List<K, H, T, N>), // ab节点列表，及节点数量
 }
 
 #[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct AbNode<Aabb, T> {
-    value: (Aabb, T),  // 包围盒
-    parent: BranchKey, // 父八叉空间
-    layer: usize,      // 表示第几层， 根据aabb大小，决定最低为第几层
-    parent_child: u8,  // 父八叉空间所在的子八叉空间， 8表示不在子八叉空间上
+    value: (Aabb, T),             // 包围盒
+    pub(crate) parent: BranchKey, // 父八叉空间
+    pub(crate) layer: usize,      // 表示第几层， 根据aabb大小，决定最低为第几层
+    pub(crate) parent_child: u8,  // 父八叉空间所在的子八叉空间， 8表示不在子八叉空间上
+    active: bool,      // 是否激活，未激活的节点不会被查询到
+    #[cfg_attr(feature = "serde", serde(default))]
+    forced_layer: Option<usize>, // 固定在根节点或指定的层上时，记录指定的层
 }
 impl<Aabb, T> AbNode<Aabb, T> {
     pub fn new(aabb: Aabb, bind: T, layer: usize, n: u8) -> Self {
@@ -911,11 +3183,33 @@ impl<Aabb, T> AbNode<Aabb, T> {
             layer: layer,
             parent: BranchKey::null(),
             parent_child: n,
+            active: true,
+            forced_layer: None,
         }
     }
 }
 
+// 优先队列的元素，按距离比较
+pub(crate) struct Priority<S, V>(pub(crate) S, pub(crate) V);
+impl<S: PartialOrd, V> PartialEq for Priority<S, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+impl<S: PartialOrd, V> Eq for Priority<S, V> {}
+impl<S: PartialOrd, V> PartialOrd for Priority<S, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl<S: PartialOrd, V> Ord for Priority<S, V> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.partial_cmp(&other.0).unwrap_or(Ordering::Equal)
+    }
+}
+
 #[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct DirtyState {
     dirty_count: usize,
     min_layer: usize,
@@ -931,6 +3225,13 @@ impl DirtyState {
     }
 }
 
+// 静态查询的范围及缓存的结果
+pub(crate) struct StaticQuery<K, Aabb> {
+    region: Aabb,
+    result: Vec<K>,
+    pub(crate) dirty: bool, // 缓存是否失效
+}
+
 #[inline]
 fn set_dirty(
     dirty: &mut bool,
@@ -962,3 +3263,196 @@ fn set_tree_dirty(dirty: &mut (Vec<Vec<BranchKey>>, DirtyState), layer: usize, r
     let vec = unsafe { dirty.0.get_unchecked_mut(layer as usize) };
     vec.push(rid);
 }
+
+// 序列化。ab节点按所在列表的顺序序列化，反序列化时根据parent和parent_child重新链接到对应列表
+#[cfg(feature = "serde")]
+mod serialize {
+    use serde::{de, ser::SerializeStruct, Deserialize, Deserializer, Serialize, Serializer};
+
+    use super::*;
+
+    #[derive(Serialize)]
+    struct TreeRef<'a, K, Aabb, Vector, T, B> {
+        slab: &'a SlotMap<BranchKey, B>,
+        ab_map: Vec<(K, &'a AbNode<Aabb, T>)>,
+        max_loose: &'a Vector,
+        min_loose: &'a Vector,
+        root_key: BranchKey,
+        dirty: &'a (Vec<Vec<BranchKey>>, DirtyState),
+        adjust: (usize, usize),
+        loose_layer: usize,
+        deep: usize,
+        auto_collect: usize,
+    }
+
+    #[derive(Deserialize)]
+    struct TreeData<K, Aabb, Vector, T, B> {
+        slab: SlotMap<BranchKey, B>,
+        ab_map: Vec<(K, AbNode<Aabb, T>)>,
+        max_loose: Vector,
+        min_loose: Vector,
+        root_key: BranchKey,
+        dirty: (Vec<Vec<BranchKey>>, DirtyState),
+        adjust: (usize, usize),
+        loose_layer: usize,
+        deep: usize,
+        auto_collect: usize,
+    }
+
+    impl<K: Key + Serialize, H: Helper<N>, T: Serialize, const N: usize> Serialize for Tree<K, H, T, N>
+    where
+        H::Aabb: Serialize,
+        H::Vector: Serialize,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut ab_map = Vec::with_capacity(self.ab_map.len());
+            ab_map.extend(self.outer.iter(&self.ab_map));
+            for (_, branch) in self.slab.iter() {
+                ab_map.extend(branch.nodes.iter(&self.ab_map));
+                for child in &branch.childs {
+                    if let ChildNode::Ab(list) = child {
+                        ab_map.extend(list.iter(&self.ab_map));
+                    }
+                }
+            }
+            TreeRef {
+                slab: &self.slab,
+                ab_map,
+                max_loose: &self.max_loose,
+                min_loose: &self.min_loose,
+                root_key: self.root_key,
+                dirty: &self.dirty,
+                adjust: self.adjust,
+                loose_layer: self.loose_layer,
+                deep: self.deep,
+                auto_collect: self.auto_collect,
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de, K: Key + Deserialize<'de>, H: Helper<N>, T: Deserialize<'de>, const N: usize>
+        Deserialize<'de> for Tree<K, H, T, N>
+    where
+        H::Aabb: Deserialize<'de>,
+        H::Vector: Deserialize<'de>,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let data = TreeData::<K, H::Aabb, H::Vector, T, BranchNode<K, H, T, N>>::deserialize(
+                deserializer,
+            )?;
+            if !data.slab.contains_key(data.root_key) {
+                return Err(de::Error::custom("invalid root_key"));
+            }
+            let mut tree = Tree {
+                slab: data.slab,
+                ab_map: SecondaryMap::default(),
+                max_loose: data.max_loose,
+                min_loose: data.min_loose,
+                root_key: data.root_key,
+                outer: LinkList::new(),
+                dirty: data.dirty,
+                adjust: data.adjust,
+                loose_layer: data.loose_layer,
+                deep: data.deep,
+                auto_collect: data.auto_collect,
+                static_queries: SlotMap::with_key(),
+                changed_regions: None,
+            };
+            for (id, ab) in data.ab_map {
+                let (parent, parent_child) = (ab.parent, ab.parent_child);
+                let list = if parent.is_null() {
+                    &mut tree.outer
+                } else {
+                    match tree.slab.get_mut(parent) {
+                        Some(branch) if parent_child as usize >= N => &mut branch.nodes,
+                        Some(branch) => match branch.childs[parent_child as usize] {
+                            ChildNode::Ab(ref mut list) => list,
+                            _ => return Err(de::Error::custom("invalid ab node parent_child")),
+                        },
+                        _ => return Err(de::Error::custom("invalid ab node parent")),
+                    }
+                };
+                if tree.ab_map.insert(id, Node::new(ab)).is_some() {
+                    return Err(de::Error::custom("duplicate ab node id"));
+                }
+                list.link_before(id, K::null(), &mut tree.ab_map);
+            }
+            Ok(tree)
+        }
+    }
+
+    impl<K: Key, H: Helper<N>, T, const N: usize> Serialize for BranchNode<K, H, T, N>
+    where
+        H::Aabb: Serialize,
+        H::Vector: Serialize,
+    {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            let mut s = serializer.serialize_struct("BranchNode", 7)?;
+            s.serialize_field("aabb", &self.aabb)?;
+            s.serialize_field("loose", &self.loose)?;
+            s.serialize_field("layer", &self.layer)?;
+            s.serialize_field("parent", &self.parent)?;
+            s.serialize_field("childs", &self.childs[..])?;
+            s.serialize_field("parent_child", &self.parent_child)?;
+            s.serialize_field("dirty", &self.dirty)?;
+            s.end()
+        }
+    }
+
+    #[derive(Deserialize)]
+    struct BranchData<Aabb, Vector, C> {
+        aabb: Aabb,
+        loose: Vector,
+        layer: usize,
+        parent: BranchKey,
+        childs: Vec<C>,
+        parent_child: u8,
+        dirty: bool,
+    }
+
+    impl<'de, K: Key, H: Helper<N>, T, const N: usize> Deserialize<'de> for BranchNode<K, H, T, N>
+    where
+        H::Aabb: Deserialize<'de>,
+        H::Vector: Deserialize<'de>,
+    {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            let data: BranchData<H::Aabb, H::Vector, ChildNode<K, H, T, N>> =
+                Deserialize::deserialize(deserializer)?;
+            let childs = data
+                .childs
+                .try_into()
+                .map_err(|v: Vec<_>| de::Error::invalid_length(v.len(), &"N childs"))?;
+            Ok(BranchNode {
+                aabb: data.aabb,
+                loose: data.loose,
+                layer: data.layer,
+                parent: data.parent,
+                childs,
+                nodes: LinkList::new(),
+                parent_child: data.parent_child,
+                dirty: data.dirty,
+            })
+        }
+    }
+
+    // 子空间只序列化分支节点的key，ab节点列表由Tree重建
+    impl<K: Key, H: Helper<N>, T, const N: usize> Serialize for ChildNode<K, H, T, N> {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            match self {
+                ChildNode::Branch(branch) => Some(*branch),
+                ChildNode::Ab(_) => None,
+            }
+            .serialize(serializer)
+        }
+    }
+
+    impl<'de, K: Key, H: Helper<N>, T, const N: usize> Deserialize<'de> for ChildNode<K, H, T, N> {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            Ok(match Option::<BranchKey>::deserialize(deserializer)? {
+                Some(branch) => ChildNode::Branch(branch),
+                None => ChildNode::Ab(LinkList::new()),
+            })
+        }
+    }
+}
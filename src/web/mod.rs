@@ -2,5 +2,6 @@
 
 extern crate wasm_bindgen;
 
+pub mod oct_tree;
 pub mod quad_tree;
 pub mod tilemap;
\ No newline at end of file
@@ -0,0 +1,192 @@
+use crate::oct_helper::{
+    ab_query_func, contains_point, intersects, point_ab_query_func, AbQueryArgs,
+    OctTree as OctTreeInner, PointQueryArgs,
+};
+use nalgebra::Point3;
+use parry3d::bounding_volume::Aabb as AABB;
+use pi_slotmap::{DefaultKey, Key, KeyData, SlotMap};
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// wasm的八叉树，和web的QuadTree一样，节点的payload为f64
+/// + 和需求中的i32 payload不同：JS的数字就是f64，用add添加时id可以直接作为payload，add_with_payload可以指定应用方的payload
+/// + 同QuadTree，额外提供了点查询query_point
+#[wasm_bindgen]
+pub struct OctTree(OctTreeInner<DefaultKey, f64>, SlotMap<DefaultKey, ()>);
+
+#[wasm_bindgen]
+impl OctTree {
+    pub fn default() -> Self {
+        let max = nalgebra::Vector3::new(100f32, 100f32, 100f32);
+        let min = max / 100f32;
+
+        Self(
+            OctTreeInner::new(
+                AABB::new(
+                    Point3::new(-1024f32, -1024f32, -1024f32),
+                    Point3::new(3072f32, 3072f32, 3072f32),
+                ),
+                max,
+                min,
+                0,
+                0,
+                0,
+            ),
+            SlotMap::new(),
+        )
+    }
+
+    /*
+     * min_x & min_y & min_z: 场景最小边界
+     * max_x & max_y & max_z: 场景最大边界
+     * min_loose_x & min_loose_y & min_loose_z: 场景物体最小尺寸
+     * max_loose_x & max_loose_y & max_loose_z: 场景物体最大尺寸
+     */
+    pub fn new(
+        min_x: f64,
+        min_y: f64,
+        min_z: f64,
+        max_x: f64,
+        max_y: f64,
+        max_z: f64,
+        min_loose_x: f64,
+        min_loose_y: f64,
+        min_loose_z: f64,
+        max_loose_x: f64,
+        max_loose_y: f64,
+        max_loose_z: f64,
+    ) -> Self {
+        let max =
+            nalgebra::Vector3::new(max_loose_x as f32, max_loose_y as f32, max_loose_z as f32);
+        let min =
+            nalgebra::Vector3::new(min_loose_x as f32, min_loose_y as f32, min_loose_z as f32);
+
+        Self(
+            OctTreeInner::new(
+                AABB::new(
+                    Point3::new(min_x as f32, min_y as f32, min_z as f32),
+                    Point3::new(max_x as f32, max_y as f32, max_z as f32),
+                ),
+                max,
+                min,
+                0,
+                0,
+                0,
+            ),
+            SlotMap::new(),
+        )
+    }
+
+    /// 添加aabb，返回id，查询时返回的payload就是该id
+    pub fn add(
+        &mut self,
+        min_x: f64,
+        min_y: f64,
+        min_z: f64,
+        max_x: f64,
+        max_y: f64,
+        max_z: f64,
+    ) -> f64 {
+        let min = Point3::new(min_x as f32, min_y as f32, min_z as f32);
+        let max = Point3::new(max_x as f32, max_y as f32, max_z as f32);
+        let id = self.1.insert(());
+        let res = id.data().as_ffi() as f64;
+        self.0.add(id, AABB::new(min, max), res);
+        res
+    }
+
+    /// 添加aabb及应用方的payload，返回id，查询时返回payload而不是id
+    pub fn add_with_payload(
+        &mut self,
+        min_x: f64,
+        min_y: f64,
+        min_z: f64,
+        max_x: f64,
+        max_y: f64,
+        max_z: f64,
+        payload: f64,
+    ) -> f64 {
+        let min = Point3::new(min_x as f32, min_y as f32, min_z as f32);
+        let max = Point3::new(max_x as f32, max_y as f32, max_z as f32);
+        let id = self.1.insert(());
+        let res = id.data().as_ffi() as f64;
+        self.0.add(id, AABB::new(min, max), payload);
+        res
+    }
+
+    pub fn remove(&mut self, id: f64) {
+        self.0
+            .remove(DefaultKey::from(KeyData::from_ffi(id as u64)));
+    }
+
+    pub fn update(
+        &mut self,
+        id: f64,
+        min_x: f64,
+        min_y: f64,
+        min_z: f64,
+        max_x: f64,
+        max_y: f64,
+        max_z: f64,
+    ) {
+        let min = Point3::new(min_x as f32, min_y as f32, min_z as f32);
+        let max = Point3::new(max_x as f32, max_y as f32, max_z as f32);
+        self.0.update(
+            DefaultKey::from(KeyData::from_ffi(id as u64)),
+            AABB::new(min, max),
+        );
+    }
+
+    /// 查询和指定范围相交的节点，返回节点的payload
+    pub fn query(
+        &self,
+        min_x: f64,
+        min_y: f64,
+        min_z: f64,
+        max_x: f64,
+        max_y: f64,
+        max_z: f64,
+    ) -> Vec<f64> {
+        let min = Point3::new(min_x as f32, min_y as f32, min_z as f32);
+        let max = Point3::new(max_x as f32, max_y as f32, max_z as f32);
+        let ab = AABB::new(min, max);
+        let mut args = AbQueryArgs::new(ab);
+        self.0
+            .query(&AABB::new(min, max), intersects, &mut args, ab_query_func);
+
+        args.result.into_iter().map(|(_, bind)| bind).collect()
+    }
+
+    /// 查询和指定范围相交的节点，将节点的payload写入result，返回结果数量
+    pub fn query_max(
+        &self,
+        min_x: f64,
+        min_y: f64,
+        min_z: f64,
+        max_x: f64,
+        max_y: f64,
+        max_z: f64,
+        result: &mut [f64],
+        max_len: u32,
+    ) -> f64 {
+        let min = Point3::new(min_x as f32, min_y as f32, min_z as f32);
+        let max = Point3::new(max_x as f32, max_y as f32, max_z as f32);
+        let ab = AABB::new(min, max);
+        let mut args = AbQueryArgs::with_max_results(ab, max_len as usize);
+        self.0
+            .query(&AABB::new(min, max), intersects, &mut args, ab_query_func);
+
+        for i in 0..args.result.len() {
+            result[i] = args.result[i].1;
+        }
+        args.result.len() as f64
+    }
+
+    /// 点查询，返回aabb包含该点的节点的payload，可用于鼠标拾取
+    pub fn query_point(&self, x: f64, y: f64, z: f64) -> Vec<f64> {
+        let point = Point3::new(x as f32, y as f32, z as f32);
+        let mut args = PointQueryArgs::new(point);
+        self.0
+            .query(&point, contains_point, &mut args, point_ab_query_func);
+        args.result.into_iter().map(|(_, bind)| bind).collect()
+    }
+}
@@ -1,4 +1,6 @@
-use crate::quad_helper::{intersects, QuadTree as QuadTreeInner};
+use crate::quad_helper::{
+    contains_point, intersects, point_ab_query_func, PointQueryArgs, QuadTree as QuadTreeInner,
+};
 use nalgebra::Point2;
 use parry2d::bounding_volume::Aabb as AABB;
 use pi_slotmap::{DefaultKey, Key, KeyData, SlotMap};
@@ -22,17 +24,18 @@ impl AbQueryArgs {
 
 /// ab节点的查询函数, 这里只是一个简单范本，使用了quad节点的查询函数intersects
 /// 应用方为了功能和性能，应该实现自己需要的ab节点的查询函数， 比如点查询， 球查询-包含或相交， 视锥体查询...
-pub fn ab_query_func(arg: &mut AbQueryArgs, id: DefaultKey, aabb: &AABB, bind: &i32) {
+/// + 结果为节点绑定的payload，用add添加的节点payload就是id
+pub fn ab_query_func(arg: &mut AbQueryArgs, _id: DefaultKey, aabb: &AABB, bind: &f64) {
     // println!("ab_query_func: id: {}, bind:{:?}, arg: {:?}", id, bind, arg.result);
     if intersects(&arg.aabb, aabb) {
-        if arg.result.len() <= arg.len {
-            arg.result.push(id.data().as_ffi() as f64);
+        if arg.result.len() < arg.len {
+            arg.result.push(*bind);
         }
     }
 }
 
 #[wasm_bindgen]
-pub struct QuadTree(QuadTreeInner<DefaultKey, i32>, SlotMap<DefaultKey, ()>);
+pub struct QuadTree(QuadTreeInner<DefaultKey, f64>, SlotMap<DefaultKey, ()>);
 
 #[wasm_bindgen]
 impl QuadTree {
@@ -91,12 +94,30 @@ impl QuadTree {
         )
     }
 
+    /// 添加aabb，返回id，查询时返回的payload就是该id
     pub fn add(&mut self, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> f64 {
         let min = Point2::new(min_x as f32, min_y as f32);
         let max = Point2::new(max_x as f32, max_y as f32);
         let id = self.1.insert(());
         let res = id.data().as_ffi() as f64;
-        self.0.add(id, AABB::new(min, max), 1);
+        self.0.add(id, AABB::new(min, max), res);
+        res
+    }
+
+    /// 添加aabb及应用方的payload，返回id，查询时返回payload而不是id
+    pub fn add_with_payload(
+        &mut self,
+        min_x: f64,
+        min_y: f64,
+        max_x: f64,
+        max_y: f64,
+        payload: f64,
+    ) -> f64 {
+        let min = Point2::new(min_x as f32, min_y as f32);
+        let max = Point2::new(max_x as f32, max_y as f32);
+        let id = self.1.insert(());
+        let res = id.data().as_ffi() as f64;
+        self.0.add(id, AABB::new(min, max), payload);
         res
     }
 
@@ -114,6 +135,7 @@ impl QuadTree {
         );
     }
 
+    /// 查询和指定范围相交的节点，返回节点的payload
     pub fn query(&self, min_x: f64, min_y: f64, max_x: f64, max_y: f64) -> Vec<f64> {
         let min = Point2::new(min_x as f32, min_y as f32);
         let max = Point2::new(max_x as f32, max_y as f32);
@@ -125,6 +147,7 @@ impl QuadTree {
         args.result
     }
 
+    /// 查询和指定范围相交的节点，将节点的payload写入result，返回结果数量
     pub fn query_max(
         &self,
         min_x: f64,
@@ -146,6 +169,27 @@ impl QuadTree {
         }
         args.result.len() as f64
     }
+
+    /// 点查询，返回aabb包含该点的节点的payload，可用于鼠标点选
+    pub fn query_point(&self, x: f64, y: f64) -> Vec<f64> {
+        let point = Point2::new(x as f32, y as f32);
+        let mut args = PointQueryArgs::new(point);
+        self.0
+            .query(&point, contains_point, &mut args, point_ab_query_func);
+        args.result.into_iter().map(|(_, bind)| bind).collect()
+    }
+
+    /// 点查询，返回aabb包含该点的节点中id最小的节点的payload，没有则返回NaN
+    pub fn query_first_point(&self, x: f64, y: f64) -> f64 {
+        let point = Point2::new(x as f32, y as f32);
+        let mut args = PointQueryArgs::new(point);
+        self.0
+            .query(&point, contains_point, &mut args, point_ab_query_func);
+        match args.result.into_iter().min_by_key(|(id, _)| *id) {
+            Some((_, bind)) => bind,
+            _ => f64::NAN,
+        }
+    }
 }
 
 // #[test]
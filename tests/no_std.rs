@@ -0,0 +1,39 @@
+//! 关闭std特性时，在no_std的crate中构建八叉树并查询，验证本crate的叉树及查询只依赖core及alloc
+#![cfg(not(feature = "std"))]
+#![no_std]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use nalgebra::{Point3, Vector3};
+use parry3d::bounding_volume::Aabb;
+use pi_spatial::oct_helper::{ab_query_func, intersects, AbQueryArgs, OctTree};
+
+#[test]
+fn oct_tree_query() {
+    let mut tree: OctTree<usize, usize> = OctTree::new(
+        Aabb::new(
+            Point3::new(-1024f32, -1024f32, -1024f32),
+            Point3::new(1024f32, 1024f32, 1024f32),
+        ),
+        Vector3::new(100f32, 100f32, 100f32),
+        Vector3::new(1f32, 1f32, 1f32),
+        0,
+        0,
+        0,
+    );
+    for i in 0..100 {
+        let p = Point3::new(i as f32 * 10.0, 0.0, 0.0);
+        tree.add(i, Aabb::new(p, p + Vector3::new(5f32, 5f32, 5f32)), i);
+    }
+    tree.collect();
+    let region = Aabb::new(
+        Point3::new(96f32, -1f32, -1f32),
+        Point3::new(205f32, 1f32, 1f32),
+    );
+    let mut args: AbQueryArgs<usize, usize> = AbQueryArgs::new(region.clone());
+    tree.query(&region, intersects, &mut args, ab_query_func);
+    let mut ids: Vec<usize> = args.result.iter().map(|r| r.0).collect();
+    ids.sort();
+    assert_eq!(ids, (10..21).collect::<Vec<usize>>());
+}